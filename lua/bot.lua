@@ -5,10 +5,12 @@ bot.reaction_hooks = {}
 
 include("./lib/async.lua")
 include("./lib/hooks.lua")
-json = include("./lib/json.lua")
-Lru = include("./lib/lru.lua")
+json = require("lib.json")
+Lru = require("lib.lru")
+owned = require("lib.owned")
 include("./lib/pagination.lua")
-RingBuffer = include("./lib/ring_buffer.lua")
+RingBuffer = require("lib.ring_buffer")
+include("./lib/scripts.lua")
 include("./lib/string.lua")
 include("./lib/table.lua")
 include("./lib/tags.lua")
@@ -298,7 +300,7 @@ function bot.has_role_or_higher(role, user_role, only_higher)
     return user_role_idx > role_idx
 end
 
-local function exec_command(msg, cmd, args)
+local function exec_command(msg, cmd, args, locale)
     local has_subcommands = #cmd.sub_commands > 0
 
     if not cmd then
@@ -307,7 +309,7 @@ local function exec_command(msg, cmd, args)
 
     if cmd.role then
         if not bot.has_role_or_higher(cmd.role, msg.author.role) then
-            return msg:reply("permission denied: this command requires the role of  " .. cmd.role .. " or higher."):await()
+            return msg:reply(bot.t("commands.permission_denied", {role = cmd.role}, locale)):await()
         end
     end
 
@@ -319,7 +321,7 @@ local function exec_command(msg, cmd, args)
             local sub_cmd = cmd._sub_commands[cmd_name]
 
             if sub_cmd then
-                return exec_command(msg, sub_cmd, args)
+                return exec_command(msg, sub_cmd, args, locale)
             end
         end
 
@@ -335,24 +337,57 @@ local function exec_command(msg, cmd, args)
     local succ, res, extra_args = bot.parse_args(cmd, args)
 
     if not succ then
-        return msg:reply("argument error: " .. res .. '\nUse "' .. get_abs_cmd(cmd) .. ' --help" for more info.'):await()
+        return msg:reply(bot.t("commands.argument_error", {error = res, command = get_abs_cmd(cmd)}, locale)):await()
     end
 
     return cmd.callback({
         msg = msg,
         args = res,
-        extra_args = extra_args
+        extra_args = extra_args,
+        locale = locale,
+        -- Look up a translation in the locale of the command
+        t = function(key, args)
+            return bot.t(key, args, locale)
+        end,
     })
 end
 
-function bot.on_command(msg, args, edited, spammy_commands)
+local function exec_script(msg, script, args, locale)
+    local succ, res = pcall(scripts.exec_script, msg, script, args)
+
+    if not succ then
+        return msg:reply(bot.t("scripts.error", {error = msg.channel:escape_text(tostring(res))}, locale)):await()
+    end
+
+    if res and res ~= "" then
+        local reply = scripts.reply_text(msg.channel, res, function(key, args)
+            return bot.t(key, args, locale)
+        end)
+
+        return msg:reply(reply):await()
+    end
+end
+
+function bot.on_command(msg, args, edited, spammy_commands, locale)
     local cmd_name = args[1]
     local args = {table.unpack(args, 2, #args)}
 
     local cmd = bot.cmds[cmd_name] or bot.aliases[cmd_name]
+    local script
 
+    -- Commands take precedence over user scripts with the same name
     if not cmd then
-        return
+        local server = msg.channel.server
+
+        if not server or not cmd_name or not scripts.is_valid_name(cmd_name) then
+            return
+        end
+
+        script = scripts.find_script(server, cmd_name):await()
+
+        if not script then
+            return
+        end
     end
 
     local count = 0
@@ -371,13 +406,36 @@ function bot.on_command(msg, args, edited, spammy_commands)
         end
     end
 
-    if cmd.spammy and not spammy_commands then
-        local reply = msg:reply("Commands that can lead to spam has been disabled in this channel or server."):await()
+    if cmd and cmd.spammy and not spammy_commands then
+        local reply = msg:reply(bot.t("commands.spammy_disabled", nil, locale)):await()
         bot.add_command_history(msg, reply, count)
         return
     end
 
-    local reply = exec_command(msg, cmd, args)
+    local server = msg.channel.server
+
+    log.debug(script and "executing script" or "executing command", {
+        command = cmd_name,
+        server = server and server.id,
+        channel = msg.channel.id,
+        user = msg.author.id,
+    })
+
+    local start = os.clock()
+    local succ, reply
+
+    if script then
+        succ, reply = pcall(exec_script, msg, script, args, locale)
+        bot.record_command("script", os.clock() - start, succ)
+    else
+        succ, reply = pcall(exec_command, msg, cmd, args, locale)
+        bot.record_command(cmd.cmd, os.clock() - start, succ)
+    end
+
+    if not succ then
+        error(reply, 0)
+    end
+
     bot.add_command_history(msg, reply, count)
 end
 
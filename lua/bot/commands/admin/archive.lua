@@ -0,0 +1,51 @@
+bot.add_command("archive", {
+    description = "Export or import the bot database",
+    sub_commands = {
+        bot.sub_command("export", {
+            -- The whole database is only exported by the `kaito export` CLI, not into a channel
+            description = "Export the current server as a JSON archive",
+            callback = function(ctx)
+                local data = bot.export_db(ctx.msg.channel.server):await()
+
+                return ctx.msg:reply("", {
+                    attachments = {
+                        { filename = "kaito-archive.json", data = data }
+                    }
+                }):await()
+            end,
+        }),
+        bot.sub_command("import", {
+            args = {
+                {
+                    key = "server",
+                    long = "server",
+                    description = "import a single server archive into the current server"
+                }
+            },
+            description = "Import a JSON archive attached to the message",
+            callback = function(ctx)
+                local attachment = ctx.msg.attachments[1]
+
+                if not attachment then
+                    return ctx.msg:reply("error: attach an archive to the message"):await()
+                end
+
+                local res = http.fetch(attachment.url, {}):await()
+
+                if not res.ok then
+                    return ctx.msg:reply("error: unable to download the archive (" .. res.status .. ")"):await()
+                end
+
+                local server = ctx.args.server and ctx.msg.channel.server or nil
+                local summary = bot.import_db(res.body, server):await()
+
+                return ctx.msg:reply(
+                    "imported " .. summary.users .. " users, " .. summary.servers .. " servers, " ..
+                    summary.restrictions .. " restrictions, " .. summary.settings .. " settings and " ..
+                    summary.tags .. " tags (" .. summary.skipped_tags .. " tags already existed)"
+                ):await()
+            end,
+        })
+    },
+    role = "root",
+})
@@ -0,0 +1,54 @@
+local function set_module_enabled(ctx, enabled)
+    -- The lua module handles the commands, so it could not be enabled again from chat
+    if ctx.args.module == "lua" and not enabled then
+        return ctx.msg:reply("error: the lua module cannot be disabled from a command"):await()
+    end
+
+    local succ, err = pcall(bot.set_module_enabled, ctx.args.module, enabled)
+
+    if not succ then
+        return ctx.msg:reply("error: " .. tostring(err)):await()
+    end
+
+    return ctx.msg:reply((enabled and "Enabled" or "Disabled") .. " the " .. ctx.args.module .. " module for every server"):await()
+end
+
+local module_arg = {
+    key = "module",
+    name = "MODULE",
+    description = "Module to update",
+    required = true,
+}
+
+bot.add_command("modules", {
+    description = "List or globally enable and disable modules, use the enable setting to do it per server or channel",
+    sub_commands = {
+        bot.sub_command("list", {
+            description = "List the modules and if they are enabled globally",
+            callback = function(ctx)
+                local out = "Modules:\n"
+
+                for _, module in ipairs(bot.list_modules()) do
+                    out = out .. "   " .. bot.icode_block(ctx.msg.channel, module.id) .. " " .. (module.enabled and "enabled" or "disabled") .. "\n"
+                end
+
+                return ctx.msg:reply(out):await()
+            end,
+        }),
+        bot.sub_command("enable", {
+            args = { module_arg },
+            description = "Enable a module for every server",
+            callback = function(ctx)
+                return set_module_enabled(ctx, true)
+            end,
+        }),
+        bot.sub_command("disable", {
+            args = { module_arg },
+            description = "Disable a module for every server",
+            callback = function(ctx)
+                return set_module_enabled(ctx, false)
+            end,
+        }),
+    },
+    role = "root",
+})
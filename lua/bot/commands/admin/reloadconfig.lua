@@ -0,0 +1,15 @@
+bot.add_command("reloadconfig", {
+    description = "Reload the config file",
+    callback = function(ctx)
+        local report = bot.reload_config():await()
+
+        local out = "applied: " .. (#report.applied > 0 and table.concat(report.applied, ", ") or "nothing")
+
+        if #report.restart_required > 0 then
+            out = out .. "\nrestart required for: " .. table.concat(report.restart_required, ", ")
+        end
+
+        return ctx.msg:reply(out):await()
+    end,
+    role = "root",
+})
@@ -1,5 +1,37 @@
+-- Pick the scope from the --server, --channel and --user flags, exactly one has to be used
+local function setting_scope(args)
+    local scopes = {}
+
+    for _, scope in ipairs({"server", "channel", "user"}) do
+        if args[scope] then
+            table.insert(scopes, scope)
+        end
+    end
+
+    if #scopes ~= 1 then
+        return nil, "exactly one of --server, --channel or --user has to be used"
+    end
+
+    return scopes[1]
+end
+
+-- Everyone can change their own settings, the server and channel need admin
+local function check_scope_role(msg, scope)
+    if scope ~= "user" and not bot.has_role_or_higher("admin", msg.author.role) then
+        return "permission denied: only admins can change settings for a " .. scope
+    end
+end
+
+local function scope_name(scope)
+    if scope == "user" then
+        return "yourself"
+    end
+
+    return "the current " .. scope
+end
+
 bot.add_command("settings", {
-    description = "Update module settings for the channel or server",
+    description = "Update module settings for the channel, server or yourself",
     sub_commands = {
         bot.sub_command("list", {
             args = {
@@ -21,14 +53,27 @@ bot.add_command("settings", {
                 local out = "Module settings:\n"
 
                 local min_len = 0
+                local min_type_len = 0
                 for _, v in ipairs(module_settings) do
                     min_len = math.max(min_len, #v.name)
+                    min_type_len = math.max(min_type_len, #v.type)
                 end
         
                 local pad = min_len + 3
+                local type_pad = min_type_len + 3
                 for _, v in ipairs(module_settings) do
+                    local help = v.help
+
+                    if v.allowed then
+                        help = help .. " (" .. v.allowed .. ")"
+                    end
+
+                    if v.user_override then
+                        help = help .. " [user]"
+                    end
+
                     out =
-                        out .. "   " .. bot.icode_block(ctx.msg.channel, v.name .. string.rep(" ", pad - #v.name) .. v.help) .. "\n"
+                        out .. "   " .. bot.icode_block(ctx.msg.channel, v.name .. string.rep(" ", pad - #v.name) .. v.type .. string.rep(" ", type_pad - #v.type) .. help) .. "\n"
                 end
 
                 ctx.msg:reply(out)
@@ -63,21 +108,28 @@ bot.add_command("settings", {
                     key = "channel",
                     long = "channel",
                     description = "apply the setting for the current channel"
+                },
+                {
+                    key = "user",
+                    long = "user",
+                    description = "apply the setting only for yourself"
                 }
             },
             description = "Update a module setting",
             callback = function(ctx)
-                if not (ctx.args.server or ctx.args.channel) then
-                    return ctx.msg:reply("argument error: --channel or --server has to be used"):await()
-                end
+                local scope, err = setting_scope(ctx.args)
 
-                if ctx.args.server and ctx.args.channel then
-                    return ctx.msg:reply("argument error: only one of --channel or --server can be used"):await()
+                if err then
+                    return ctx.msg:reply("argument error: " .. err):await()
                 end
 
-                local server = ctx.args.server ~= nil
+                local denied = check_scope_role(ctx.msg, scope)
 
-                local err, fut = bot.set_setting(ctx.msg, server, ctx.args.module, ctx.args.setting, ctx.args.value)
+                if denied then
+                    return ctx.msg:reply(denied):await()
+                end
+
+                local err, fut = bot.set_setting(ctx.msg, scope, ctx.args.module, ctx.args.setting, ctx.args.value)
 
                 if err then
                     return ctx.msg:reply("argument error: " .. err):await()
@@ -85,9 +137,118 @@ bot.add_command("settings", {
 
                 fut:await()
 
-                return ctx.msg:reply("Successfully updated \"" .. ctx.args.module .. "/" .. ctx.args.setting .. "\" for the current " .. (server and "server" or "channel")):await()
+                return ctx.msg:reply("Successfully updated \"" .. ctx.args.module .. "/" .. ctx.args.setting .. "\" for " .. scope_name(scope)):await()
+            end,
+        }),
+        bot.sub_command("get", {
+            args = {
+                {
+                    key = "module",
+                    name = "MODULE",
+                    description = "Module for the settings",
+                    required = true,
+                },
+                {
+                    key = "setting",
+                    name = "SETTING",
+                    description = "Setting to show",
+                    required = true,
+                },
+            },
+            description = "Show the value of a setting in the current channel and where it is set",
+            callback = function(ctx)
+                local err, fut = bot.get_setting(ctx.msg, ctx.args.module, ctx.args.setting)
+
+                if err then
+                    return ctx.msg:reply("argument error: " .. err):await()
+                end
+
+                local setting = fut:await()
+
+                return ctx.msg:reply(
+                    bot.icode_block(ctx.msg.channel, ctx.args.module .. "/" .. setting.name .. " = " .. setting.value)
+                        .. " (from the " .. setting.source .. ")"
+                ):await()
+            end,
+        }),
+        bot.sub_command("unset", {
+            args = {
+                {
+                    key = "module",
+                    name = "MODULE",
+                    description = "Module for the settings",
+                    required = true,
+                },
+                {
+                    key = "setting",
+                    name = "SETTING",
+                    description = "Setting to reset",
+                    required = true,
+                },
+                {
+                    key = "server",
+                    long = "server",
+                    description = "remove the override for the current server"
+                },
+                {
+                    key = "channel",
+                    long = "channel",
+                    description = "remove the override for the current channel"
+                },
+                {
+                    key = "user",
+                    long = "user",
+                    description = "remove your own override"
+                }
+            },
+            description = "Remove a setting override",
+            callback = function(ctx)
+                local scope, err = setting_scope(ctx.args)
+
+                if err then
+                    return ctx.msg:reply("argument error: " .. err):await()
+                end
+
+                local denied = check_scope_role(ctx.msg, scope)
+
+                if denied then
+                    return ctx.msg:reply(denied):await()
+                end
+
+                local err, fut = bot.unset_setting(ctx.msg, scope, ctx.args.module, ctx.args.setting)
+
+                if err then
+                    return ctx.msg:reply("argument error: " .. err):await()
+                end
+
+                local name = "\"" .. ctx.args.module .. "/" .. ctx.args.setting .. "\""
+
+                if fut:await() then
+                    return ctx.msg:reply("Removed the override of " .. name .. " for " .. scope_name(scope)):await()
+                else
+                    return ctx.msg:reply(name .. " is not set for " .. scope_name(scope)):await()
+                end
+            end,
+        }),
+        bot.sub_command("overrides", {
+            description = "List the settings overridden in the current server and its channels",
+            role = "admin",
+            callback = function(ctx)
+                local overrides = bot.list_setting_overrides(ctx.msg):await()
+
+                if #overrides == 0 then
+                    return ctx.msg:reply("No settings are overridden in this server"):await()
+                end
+
+                local out = "Setting overrides:\n"
+
+                for _, v in ipairs(overrides) do
+                    local scope = v.channel and ("channel " .. v.channel) or "server"
+                    out = out .. "   " .. bot.icode_block(ctx.msg.channel, v.key .. " = " .. v.value) .. " (" .. scope .. ")\n"
+                end
+
+                return ctx.msg:reply(out):await()
             end,
         })
     },
-    role = "admin",
 })
@@ -0,0 +1,127 @@
+local function source_from_args(ctx)
+    local source = scripts.source_from_args(ctx)
+
+    if #source > scripts.MAX_SOURCE_LIMIT then
+        return nil, ctx.t("scripts.source_too_long", {max = scripts.MAX_SOURCE_LIMIT})
+    end
+
+    if source:match("^%s*$") then
+        return nil, ctx.t("scripts.source_empty")
+    end
+
+    return source
+end
+
+local SOURCE_ARG = {
+    key = "source",
+    name = "SOURCE",
+    description = "Lua source",
+}
+
+local SCRIPT = {
+    arg = "script",
+    noun = "script",
+    locale = "scripts",
+    find = scripts.find_script,
+    list = scripts.list_scripts,
+    content = {
+        arg = SOURCE_ARG,
+        from_args = source_from_args,
+    },
+}
+
+bot.add_command("script", {
+    description = "Run a script, scripts can also be run as commands",
+    args = {
+        {
+            key = "script",
+            name = "NAME",
+            description = "Script name",
+            required = true,
+        }
+    },
+    callback = function(ctx)
+        local script = scripts.find_script(ctx.msg.channel.server, ctx.args.script):await()
+
+        if not script then
+            return ctx.msg:reply(ctx.t("scripts.unknown")):await()
+        end
+
+        local succ, res = pcall(scripts.exec_script, ctx.msg, script, ctx.extra_args)
+
+        if not succ then
+            return ctx.msg:reply(ctx.t("scripts.error", {error = ctx.msg.channel:escape_text(tostring(res))})):await()
+        end
+
+        if res and res ~= "" then
+            return ctx.msg:reply(scripts.reply_text(ctx.msg.channel, res, ctx.t)):await()
+        end
+    end,
+    sub_commands = {
+        bot.sub_command("create", {
+            args = {
+                {
+                    key = "script",
+                    name = "NAME",
+                    description = "Script name",
+                    required = true,
+                },
+                SOURCE_ARG,
+            },
+            description = "Create a new script",
+            callback = function(ctx)
+                if not scripts.is_valid_name(ctx.args.script) then
+                    return ctx.msg:reply(ctx.t("scripts.invalid_name")):await()
+                end
+
+                if #ctx.args.script > scripts.MAX_NAME_LIMIT then
+                    return ctx.msg:reply(ctx.t("scripts.name_too_long", {max = scripts.MAX_NAME_LIMIT})):await()
+                end
+
+                local source, err = source_from_args(ctx)
+
+                if not source then
+                    return ctx.msg:reply(err):await()
+                end
+
+                if scripts.count_user_scripts(ctx.msg.author, ctx.msg.channel.server):await() >= scripts.MAX_USER_SCRIPTS then
+                    return ctx.msg:reply(ctx.t("scripts.limit_reached", {max = scripts.MAX_USER_SCRIPTS})):await()
+                end
+
+                local error = scripts.create_script(ctx.msg.author, ctx.msg.channel.server, ctx.args.script, source):await()
+
+                if error then
+                    return ctx.msg:reply("error: " .. ctx.msg.channel:escape_text(error)):await()
+                else
+                    return ctx.msg:reply(ctx.t("scripts.created", {name = ctx.msg.channel:escape_text(ctx.args.script)})):await()
+                end
+            end,
+        }),
+        owned.edit_command(SCRIPT),
+        owned.delete_command(SCRIPT),
+        owned.list_command(SCRIPT),
+        bot.sub_command("raw", {
+            args = {
+                {
+                    key = "script",
+                    name = "NAME",
+                    description = "Script name",
+                    required = true,
+                },
+            },
+            description = "View the source of a script",
+            callback = function(ctx)
+                local script = scripts.find_script(ctx.msg.channel.server, ctx.args.script):await()
+
+                if not script then
+                    return ctx.msg:reply(ctx.t("scripts.unknown")):await()
+                end
+
+                return ctx.msg:reply(bot.code_block(ctx.msg.channel, script.source)):await()
+            end,
+        }),
+        owned.owner_command(SCRIPT),
+        owned.gift_command(SCRIPT),
+        owned.accept_command(SCRIPT),
+    }
+})
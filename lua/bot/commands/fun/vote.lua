@@ -10,7 +10,7 @@ bot.add_command("vote", {
     callback = function(ctx)
         local vote = bot.votes.get_vote_for_channel(ctx.msg.channel)
         if not vote then
-            return ctx.msg:reply("error: no active vote was found for the current channel"):await()
+            return ctx.msg:reply(ctx.t("vote.no_active_vote")):await()
         end
 
         local choice = ctx.args.choice and tonumber(ctx.args.choice)
@@ -44,7 +44,7 @@ bot.add_command("vote", {
                 local options = ctx.extra_args
                 table.insert(options, 1, ctx.args.first_option)
 
-                return bot.votes.create(ctx.msg.author, ctx.msg.channel, ctx.args.title, time.parse_duration(ctx.args.time), options).msg
+                return bot.votes.create(ctx.msg.author, ctx.msg.channel, ctx.args.title, time.parse_duration(ctx.args.time), options, ctx.locale).msg
             end,
             role = "trusted"
         }),
@@ -54,11 +54,11 @@ bot.add_command("vote", {
             callback = function(ctx)
                 local vote = bot.votes.get_vote_for_channel(ctx.msg.channel)
                 if not vote then
-                    return ctx.msg:reply("error: no active vote was found for the current channel"):await()
+                    return ctx.msg:reply(ctx.t("vote.no_active_vote")):await()
                 end
         
                 if not vote.author == ctx.msg.author.id and not bot.has_role_or_higher("admin", ctx.msg.author.role) then
-                    return ctx.msg:reply("error: only the creator of the vote or an admin can end the vote"):await()
+                    return ctx.msg:reply(ctx.t("vote.not_creator")):await()
                 end
 
                 vote:end_vote()
@@ -77,12 +77,12 @@ bot.add_command("vote", {
             callback = function(ctx)
                 local vote = bot.votes.get_vote_for_channel(ctx.msg.channel)
                 if not vote then
-                    return ctx.msg:reply("error: no active vote was found for the current channel"):await()
+                    return ctx.msg:reply(ctx.t("vote.no_active_vote")):await()
                 end
 
                 local time = time.parse_duration(ctx.args.time)
                 if time == 0 then
-                    return ctx.msg:reply("error: invalid duration \"" .. ctx.args.time .. "\""):await()
+                    return ctx.msg:reply(ctx.t("vote.invalid_duration", {duration = ctx.args.time})):await()
                 end
 
                 vote:set_time(time)
@@ -95,7 +95,7 @@ bot.add_command("vote", {
                 local vote = bot.votes.last_vote[ctx.msg.channel.id]
 
                 if not vote then
-                    return ctx.msg:reply("error: no previous vote was found for the current channel"):await()
+                    return ctx.msg:reply(ctx.t("vote.no_previous_vote")):await()
                 end
 
                 return ctx.msg:reply(vote:msg_text(true)):await()
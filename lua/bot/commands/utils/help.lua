@@ -23,7 +23,7 @@ bot.add_command("help", {
         table.sort(cmds, function(a, b) return a.cmd < b.cmd end)
 
         return pagination.create(ctx.msg.channel, {
-            title = "Kaito - Help",
+            title = ctx.t("help.title"),
             data = cmds,
             render_data = function(ctx, cmds)
                 local content = ""
@@ -39,15 +39,9 @@ bot.add_command("help", {
                 }
             end,
             pages = {
-                function(ctx)
+                function()
                     return {
-                        content = string.trim_lines([[
-                            (ﾉ> ◇ <)ﾉ♪♪♪
-                            
-                            In short Kaito uses shell style command parsing where spaces seperates the arguments in a command.
-                            
-                            The help command can be navigated by using the page number as the first argument passed to the command, or by using reactions if the service supports it.
-                            Additionally, the --help flag may be used on any command to see its subcommands, options and arguments.]])
+                        content = ctx.t("help.intro")
                     }
                 end
             },
@@ -4,9 +4,13 @@ bot.votes = bot.votes or {active_votes = {}, last_vote = {}}
 
 local Vote  = {}
 
+function Vote:t(key, args)
+    return bot.t(key, args, self.locale)
+end
+
 function Vote:time_text()
     if self.ended then
-        return "The vote has ended"
+        return self:t("vote.ended")
     else
         local time_left = self.end_time - os.time()
 
@@ -14,14 +18,14 @@ function Vote:time_text()
             return nil
         elseif time_left >= 60 * 60 then
             local hours = math.ceil(time_left / (60 * 60))
-            return "The vote is ending  in " .. hours ..  " hour" .. string.plural(hours)
+            return hours == 1 and self:t("vote.ending_hour") or self:t("vote.ending_hours", {hours = hours})
         elseif time_left >= 30 then
             local minutes = math.ceil(time_left / 60)
-            return "The vote is ending  in " .. minutes ..  " minute" .. string.plural(minutes)
+            return minutes == 1 and self:t("vote.ending_minute") or self:t("vote.ending_minutes", {minutes = minutes})
         elseif time_left <= 10 then
-            return "The vote is ending in 10 seconds"
+            return self:t("vote.ending_10_seconds")
         else
-            return "The vote is ending in 30 seconds"
+            return self:t("vote.ending_30_seconds")
         end
     end
 end
@@ -34,7 +38,7 @@ function Vote:msg_text(full)
         self.last_time_text = time_text
     end
 
-    local text = bot.bold_block(self.channel, self.ended and "Vote results:" or "Vote:") .. " " .. self.title .. "\n\n"
+    local text = bot.bold_block(self.channel, self.ended and self:t("vote.results_title") or self:t("vote.title")) .. " " .. self.title .. "\n\n"
 
     for i, option in pairs(self.options) do
         if self.ended and self.results then
@@ -65,7 +69,7 @@ function Vote:msg_text(full)
         end
 
         if highest > 0 then
-            text = text .. bot.bold_block(self.channel, "Winner: ") .. table.list_words(results[highest]) .. "!\n"
+            text = text .. bot.bold_block(self.channel, self:t("vote.winner")) .. table.list_words(results[highest]) .. "!\n"
         end
     end
 
@@ -147,14 +151,14 @@ end
 function Vote:vote(user, choice)
     if choice then
         if choice < 1 or choice > #self.options then
-            return "choice out of range"
+            return self:t("vote.choice_out_of_range")
         end
     
         self.votes[user.id] = choice
-        return "your choice has been set to " .. choice
+        return self:t("vote.choice_set", {choice = choice})
     else
         self.votes[user.id] = nil
-        return "your choice has been removed"
+        return self:t("vote.choice_removed")
     end
 end
 
@@ -170,7 +174,8 @@ function Vote:serialize()
         interactive = self.interactive,
         votes = self.votes,
         ended = self.ended,
-        results = self.results
+        results = self.results,
+        locale = self.locale
     }
 end
 
@@ -187,22 +192,26 @@ function bot.votes.get_vote_for_channel(channel)
     end
 end
 
-function bot.votes.create(author, channel, title, time, options)
-    if time == 0 then return channel:send("error: invalid time spesified") end
+function bot.votes.create(author, channel, title, time, options, locale)
+    local function t(key, args)
+        return bot.t(key, args, locale)
+    end
+
+    if time == 0 then return channel:send(t("vote.invalid_time")) end
     if not channel:supports_feature(bot.FEATURES.Edit) then
-        return channel:send("error: channel does not support message editing which is required for votes")
+        return channel:send(t("vote.no_edit_support"))
     end
 
     if #options == 0 then
-        return channel:send("at least one option is required")
+        return channel:send(t("vote.no_options"))
     end
 
     if #options > #VOTE_EMOJIS then
-        return channel:send("error: maximum amount of vote options is " .. #VOTE_EMOJIS)
+        return channel:send(t("vote.too_many_options", {max = #VOTE_EMOJIS}))
     end
 
     if bot.votes.get_vote_for_channel(channel) then
-        return channel:send("error: there is already an active vote for the channel")
+        return channel:send(t("vote.already_active"))
     end
 
     local vote = {}
@@ -215,10 +224,11 @@ function bot.votes.create(author, channel, title, time, options)
     vote.channel = channel
     vote.interactive = channel:supports_feature(bot.FEATURES.React)
     vote.votes = {}
+    vote.locale = locale
 
     for k,v in pairs(bot.votes.active_votes) do
         if v.channel.id == channel.id then
-            return channel:send("error: there is already an active vote in the current channel")
+            return channel:send(t("vote.already_active"))
         end
     end
 
@@ -250,10 +260,10 @@ function bot.votes.create(author, channel, title, time, options)
 end
 
 hooks.add("loaded", "votes", function()
-    local data = bot.get_data("votes"):await()
+    local data = kv.get("data", "votes"):await()
     if not data then return end
 
-    for _,vote in pairs(json.decode(data)) do
+    for _,vote in pairs(data) do
         async.spawn(function()
             vote.channel = bot.channel(vote.channel_id):await()
             vote.msg = bot.message(vote.channel_id, vote.message_id):await()
@@ -281,7 +291,7 @@ hooks.add("shutdown", "votes", function()
         table.insert(data, vote:serialize())
     end
 
-    bot.set_data("votes", json.encode(data)):await()
+    kv.set("data", "votes", data):await()
 end)
 
 
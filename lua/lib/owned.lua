@@ -0,0 +1,251 @@
+-- Sub commands shared by the entries users own in a server, like tags and scripts.
+--
+-- The commands are built from a description of the entry:
+--   arg     the key of the name argument and the command name, e.g. "tag"
+--   noun    used in the help texts, e.g. "tag"
+--   locale  the section of the replies in the locale files, e.g. "tags"
+--   find    function(server, name) returning a future of the entry or nil
+--   list    function(user, server) returning a future of the names owned by the user
+--   content = { arg = <argument>, from_args = function(ctx) returning the content or nil and an error }
+local owned = {}
+
+local FORCE_ARG = {
+    key = "force",
+    long = "force",
+    description = "Force (admin)",
+}
+
+local function name_arg(kind)
+    return {
+        key = kind.arg,
+        name = "NAME",
+        description = kind.noun:gsub("^%l", string.upper) .. " name",
+        required = true,
+    }
+end
+
+local function t(ctx, kind, key, args)
+    return ctx.t(kind.locale .. "." .. key, args)
+end
+
+-- The owner can manage an entry, admins only when forcing it
+local function can_manage(ctx, entry)
+    if ctx.args.force and bot.has_role_or_higher("admin", ctx.msg.author.role) then
+        return true
+    end
+
+    return entry.uid == ctx.msg.author.uid
+end
+
+-- Find the entry named in the arguments, replying with an error if there is none
+local function find(ctx, kind)
+    local entry = kind.find(ctx.msg.channel.server, ctx.args[kind.arg]):await()
+
+    if not entry then
+        ctx.msg:reply(t(ctx, kind, "unknown")):await()
+    end
+
+    return entry
+end
+
+local function escaped_name(ctx, kind)
+    return ctx.msg.channel:escape_text(ctx.args[kind.arg])
+end
+
+function owned.edit_command(kind)
+    return bot.sub_command("edit", {
+        args = { name_arg(kind), kind.content.arg, FORCE_ARG },
+        description = "Edit a " .. kind.noun,
+        callback = function(ctx)
+            local entry = find(ctx, kind)
+
+            if not entry then return end
+
+            if not can_manage(ctx, entry) then
+                return ctx.msg:reply(t(ctx, kind, "access_denied")):await()
+            end
+
+            local content, err = kind.content.from_args(ctx)
+
+            if not content then
+                return ctx.msg:reply(err):await()
+            end
+
+            entry:edit(content):await()
+
+            return ctx.msg:reply(t(ctx, kind, "edited", {name = escaped_name(ctx, kind)})):await()
+        end,
+    })
+end
+
+function owned.delete_command(kind)
+    return bot.sub_command("delete", {
+        args = { name_arg(kind), FORCE_ARG },
+        description = "Delete a " .. kind.noun,
+        callback = function(ctx)
+            local entry = find(ctx, kind)
+
+            if not entry then return end
+
+            if not can_manage(ctx, entry) then
+                return ctx.msg:reply(t(ctx, kind, "access_denied")):await()
+            end
+
+            entry:delete():await()
+
+            return ctx.msg:reply(t(ctx, kind, "deleted", {name = escaped_name(ctx, kind)})):await()
+        end,
+    })
+end
+
+function owned.list_command(kind)
+    return bot.sub_command("list", {
+        args = {
+            {
+                key = "user",
+                name = "USER",
+                description = "User (optional)",
+            },
+            {
+                key = "page",
+                name = "PAGE",
+                description = "Page number",
+            },
+        },
+        description = "List your own or someone else's " .. kind.noun .. "s",
+        callback = function(ctx)
+            local user
+
+            if ctx.args.user then
+                user = bot.find_user(ctx.msg.channel, ctx.args.user):await()
+            else
+                user = ctx.msg.author
+            end
+
+            if not user then
+                return ctx.msg:reply(t(ctx, kind, "unknown_user", {user = ctx.msg.channel:escape_text(ctx.args.user)})):await()
+            end
+
+            local names = kind.list(user, ctx.msg.channel.server):await()
+
+            table.sort(names, function(a, b) return a < b end)
+
+            return pagination.create(ctx.msg.channel, {
+                title = t(ctx, kind, "list_title", {user = user.name}),
+                data = names,
+                render_data = function(ctx, names)
+                    local content = ""
+
+                    local i = ctx.offset
+
+                    for _,name in pairs(names) do
+                        if content ~= "" then content = content .. "\n" end
+
+                        content = content .. i .. ". \"" .. name .. "\""
+
+                        i = i + 1
+                    end
+
+                    return {
+                        content = content
+                    }
+                end,
+                page = ctx.args.page,
+                caller = ctx.msg.author
+            })
+        end,
+    })
+end
+
+function owned.owner_command(kind)
+    return bot.sub_command("owner", {
+        args = { name_arg(kind) },
+        description = "Get the owner of a " .. kind.noun,
+        callback = function(ctx)
+            local entry = find(ctx, kind)
+
+            if not entry then return end
+
+            local owner = bot.get_user(entry.uid):await()
+
+            return ctx.msg:reply(t(ctx, kind, "owner", {
+                user = ctx.msg.channel:escape_text(owner.name),
+                name = escaped_name(ctx, kind)
+            })):await()
+        end,
+    })
+end
+
+function owned.gift_command(kind)
+    return bot.sub_command("gift", {
+        args = {
+            name_arg(kind),
+            {
+                key = "user",
+                name = "USER",
+                description = "User (empty to abort transfer)",
+                required = false,
+            },
+        },
+        description = "Gift a " .. kind.noun .. " to another user",
+        callback = function(ctx)
+            local entry = find(ctx, kind)
+
+            if not entry then return end
+
+            -- Only the owner can give an entry away, forcing is done with accept
+            if entry.uid ~= ctx.msg.author.uid then
+                return ctx.msg:reply(t(ctx, kind, "access_denied")):await()
+            end
+
+            local name = escaped_name(ctx, kind)
+
+            if not ctx.args.user then
+                entry:set_transfer_user(nil):await()
+                return ctx.msg:reply(t(ctx, kind, "transfer_removed", {name = name})):await()
+            end
+
+            local user = bot.find_user(ctx.msg.channel, ctx.args.user):await()
+
+            if not user then
+                return ctx.msg:reply(t(ctx, kind, "unknown_user", {user = ctx.msg.channel:escape_text(ctx.args.user)})):await()
+            end
+
+            if user.uid == ctx.msg.author.uid then
+                return ctx.msg:reply(t(ctx, kind, "transfer_self")):await()
+            end
+
+            entry:set_transfer_user(user):await()
+
+            return ctx.msg:reply(t(ctx, kind, "transfer_started", {
+                user = ctx.msg.channel:escape_text(user.name),
+                name = name
+            })):await()
+        end,
+    })
+end
+
+function owned.accept_command(kind)
+    return bot.sub_command("accept", {
+        args = { name_arg(kind), FORCE_ARG },
+        description = "Accept a gifted " .. kind.noun,
+        callback = function(ctx)
+            local entry = find(ctx, kind)
+
+            if not entry then return end
+
+            if not ctx.args.force or not bot.has_role_or_higher("admin", ctx.msg.author.role) then
+                if entry.transfer_uid ~= ctx.msg.author.uid then
+                    return ctx.msg:reply(t(ctx, kind, "not_transferred")):await()
+                end
+            end
+
+            entry:set_transfer_user(nil):await()
+            entry:set_owner(ctx.msg.author):await()
+
+            return ctx.msg:reply(t(ctx, kind, "accepted", {name = escaped_name(ctx, kind)})):await()
+        end,
+    })
+end
+
+return owned
@@ -0,0 +1,56 @@
+scripts = scripts or {}
+
+scripts.MAX_NAME_LIMIT = 20
+scripts.MAX_SOURCE_LIMIT = 8192
+scripts.MAX_USER_SCRIPTS = 50
+-- Escaping can double the length of the output, this keeps the reply under the message limit
+scripts.MAX_OUTPUT_LIMIT = 900
+
+function scripts.is_valid_name(name)
+    return string.match(name, "[^%w_]") == nil
+end
+
+-- Run a script in the sandbox with the message of the invoker, the arguments are available as `args`
+function scripts.exec_script(msg, script, args)
+    local err, res = bot.run_sandboxed_lua(msg.author, msg, script.source, {
+        args = args,
+        script = script.name,
+        user = {
+            name = msg.author.name,
+            id = msg.author.id
+        }
+    }):await()
+
+    if err then
+        error(err, 0)
+    end
+
+    return res
+end
+
+-- The escaped reply for the output of a script, cut to the reply limit on a character boundary when
+-- it is valid utf8. `t` translates the note added to cut output
+function scripts.reply_text(channel, output, t)
+    local len = utf8.len(output)
+
+    if (len or #output) <= scripts.MAX_OUTPUT_LIMIT then
+        return channel:escape_text(output)
+    end
+
+    local cut = len and utf8.offset(output, scripts.MAX_OUTPUT_LIMIT + 1) or scripts.MAX_OUTPUT_LIMIT + 1
+
+    return channel:escape_text(output:sub(1, cut - 1)) .. "\n" .. t("scripts.output_truncated", {max = scripts.MAX_OUTPUT_LIMIT})
+end
+
+-- The source of a script from the arguments of a command, with any code block markers removed
+function scripts.source_from_args(ctx)
+    local source = ctx.args.source or ""
+
+    if #ctx.extra_args > 0 then
+        source = source .. " " .. table.concat(ctx.extra_args, " ")
+    end
+
+    source = source:gsub("^%s*```lua\n(.-)```%s*$", "%1"):gsub("^%s*```(.-)```%s*$", "%1")
+
+    return source
+end
@@ -1,64 +1,43 @@
 sandbox = sandbox or {tasks = {}}
 
 include("./lib/async.lua")
-json = include("./lib/json.lua")
-Lru = include("./lib/lru.lua")
-RingBuffer = include("./lib/ring_buffer.lua")
+json = require("lib.json")
+Lru = require("lib.lru")
+RingBuffer = require("lib.ring_buffer")
 include("./lib/string.lua")
 
 include("./sandbox/utils.lua")
 include("./sandbox/env.lua")
 
-local HOOK_EVERY_INSTRUCTION = 32
+-- Modules sandboxed code can require, by the name it uses and the module name in the lua directory
+sandbox.modules = {
+    json = "lib.json",
+    lru = "lib.lru",
+    ring_buffer = "lib.ring_buffer"
+}
 
 function sandbox.exec(state, fenv, fn)
-    local instructions_run = state:get_instructions_run()
-    local max_instructions = state:get_instruction_limit()
-
     -- Set the function env
     sandbox.utils.setfenv(fn, fenv)
 
-    -- Create the coroutine thread
+    -- Create the coroutine thread, the instruction and time limits apply while the state resumes it
     local thread = coroutine.create(fn)
-    local timeout = os.clock() + 30
-
-    debug.sethook(
-        thread,
-        coroutine.wrap(function()
-            while true do
-                instructions_run = instructions_run + HOOK_EVERY_INSTRUCTION
-                state:set_instructions_run(instructions_run)
-                if instructions_run >= max_instructions then
-                    state:terminate("exec")
-                    error("Execution quota exceeded")
-                end
-
-                if os.clock() > timeout then
-                    state:terminate("time")
-                    error("Execution time limit reached")
-                end
-                coroutine.yield()
-            end
-        end),
-        "",
-        HOOK_EVERY_INSTRUCTION
-    )
 
-    return sandbox.run_coroutine(thread)
+    return sandbox.run_coroutine(state, thread)
 end
 
-function sandbox.run_coroutine(thread)
+function sandbox.run_coroutine(state, thread)
     -- Execute the first coroutine resume
-    local ret = {pcall(coroutine.resume, thread)}
+    local ret = {state:resume(thread)}
 
     local succ, err, res
 
     -- Check if the coroutine completed, otherwise add it to the pool
     if coroutine.status(thread) == "dead" then
-        succ, err = ret[1] and ret[2], ret[1] and ret[3] or ret[2]
+        succ, err = ret[1], ret[2]
 
         if succ then
-            res = {table.unpack(ret, 3, #ret)}
+            res = {table.unpack(ret, 2, #ret)}
 
             return true, nil, res
         else
@@ -99,6 +78,29 @@ local function update_env(fenv, state)
     end
     sandbox.utils.setfenv(upd_fenv.http.fetch, fenv)
 
+    -- Persistent storage of the user running the code and of the server
+    upd_fenv.storage = {}
+    for _, scope in pairs({"user", "server"}) do
+        upd_fenv.storage[scope] = {
+            get = function(key)
+                return state:storage_get(scope, tostring(key))
+            end,
+            set = function(key, value)
+                return state:storage_set(scope, tostring(key), value)
+            end,
+            delete = function(key)
+                return state:storage_delete(scope, tostring(key))
+            end,
+            list = function(prefix)
+                return state:storage_list(scope, prefix and tostring(prefix))
+            end
+        }
+
+        for _, fn in pairs(upd_fenv.storage[scope]) do
+            sandbox.utils.setfenv(fn, fenv)
+        end
+    end
+
     upd_fenv.json = {}
     local json = json
     upd_fenv.json.decode = function(data)
@@ -113,6 +115,22 @@ local function update_env(fenv, state)
     upd_fenv.Lru = sandbox.utils.deepcopy(Lru)
     upd_fenv.RingBuffer = sandbox.utils.deepcopy(RingBuffer)
 
+    -- Each execution gets its own copy of a module so changes to it do not leak between users
+    local require = require
+    local error = error
+    local modules = sandbox.modules
+    local deepcopy = sandbox.utils.deepcopy
+    upd_fenv.require = function(name)
+        local module = modules[name]
+
+        if not module then
+            error("module '" .. tostring(name) .. "' not found", 2)
+        end
+
+        return deepcopy(require(module))
+    end
+    sandbox.utils.setfenv(upd_fenv.require, fenv)
+
     local sandbox = sandbox
     upd_fenv.print_table = function(tbl)
         state:print(sandbox.utils.table_to_string(tbl))
@@ -221,7 +239,7 @@ function sandbox.run(state, msg, source, env, main)
                 local fenv = sandbox.env.env
                 state:set_state() -- Get Rust to set the registry sandbox state variable
                 restore_env(fenv, env, msg)
-                local succ, thread, res = sandbox.run_coroutine(thread)
+                local succ, thread, res = sandbox.run_coroutine(state, thread)
 
                 if not succ then
                     sandbox.run(state, nil, function()
@@ -0,0 +1,4 @@
+-- Older channel overrides keep a NULL server until they are set again
+ALTER TABLE settings_channel ADD COLUMN server_id TEXT;
+
+CREATE INDEX settings_channel_server_id ON settings_channel (server_id);
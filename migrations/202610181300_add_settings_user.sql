@@ -0,0 +1,7 @@
+CREATE TABLE settings_user (
+    uid INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (uid, key),
+    FOREIGN KEY (uid) REFERENCES users(uid)
+);
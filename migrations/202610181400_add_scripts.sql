@@ -0,0 +1,12 @@
+CREATE TABLE scripts (
+    name TEXT NOT NULL,
+    sid INTEGER NOT NULL,
+    uid INTEGER NOT NULL,
+    transfer_uid INTEGER,
+    source TEXT NOT NULL,
+    create_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    edit_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY(uid) REFERENCES users(uid),
+    FOREIGN KEY(sid) REFERENCES servers(sid),
+    PRIMARY KEY (name, sid)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE sandbox_storage (
+    scope TEXT NOT NULL, -- "user" or "server"
+    owner INTEGER NOT NULL, -- uid or sid depending on the scope
+    key TEXT NOT NULL,
+    value TEXT NOT NULL, -- JSON
+    edit_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (scope, owner, key)
+);
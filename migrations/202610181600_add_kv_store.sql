@@ -0,0 +1,8 @@
+CREATE TABLE kv_store (
+    namespace TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL, -- JSON
+    expire_time INTEGER, -- Unix timestamp, never expires when NULL
+    edit_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (namespace, key)
+);
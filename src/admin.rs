@@ -0,0 +1,474 @@
+use anyhow::{anyhow, Result};
+use hyper::{
+    body::HttpBody,
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde_json::{json, Value};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+use thiserror::Error;
+
+use crate::{
+    bot::{db::Tag, Bot},
+    modules::lua::LuaModule,
+    services::{ChannelId, ServerId},
+    settings::{SettingContext, Settings},
+};
+
+// Shortest token the API accepts, it is the only thing guarding it
+const MIN_TOKEN_LEN: usize = 16;
+// The token of the example config, which has to be replaced
+const PLACEHOLDER_TOKEN: &str = "<random token>";
+
+// Largest request body read, every endpoint takes a small JSON object
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// The `[admin]` section of the config
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct AdminConfig {
+    pub listen: SocketAddr,
+    /// Has to be sent as `Authorization: Bearer <token>` with every request
+    pub token: String,
+}
+
+#[derive(Debug, Error)]
+enum ApiError {
+    #[error("missing or invalid token")]
+    Unauthorized,
+    #[error("not found")]
+    NotFound,
+    #[error("request body is larger than {} bytes", MAX_BODY_SIZE)]
+    PayloadTooLarge,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+fn bad_request(err: anyhow::Error) -> ApiError {
+    ApiError::BadRequest(err.to_string())
+}
+
+fn check_token(token: &str) -> Result<()> {
+    if token == PLACEHOLDER_TOKEN || token.trim().len() < MIN_TOKEN_LEN {
+        return Err(anyhow!(
+            "the admin token has to be set to a random value of at least {} characters",
+            MIN_TOKEN_LEN
+        ));
+    }
+
+    Ok(())
+}
+
+// Compare without returning early so the token cannot be guessed from response times
+fn token_matches(expected: &str, given: &str) -> bool {
+    !expected.is_empty()
+        && expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' && idx + 2 < bytes.len() {
+            if let Ok(byte) = u8::from_str_radix(&segment[idx + 1..idx + 3], 16) {
+                out.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+
+        out.push(bytes[idx]);
+        idx += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn body_str<'a>(body: &'a Value, key: &str) -> Result<&'a str, ApiError> {
+    body.get(key)
+        .and_then(|value| value.as_str())
+        .ok_or_else(|| ApiError::BadRequest(format!("missing string field \"{}\"", key)))
+}
+
+async fn setting_context(bot: &Bot, body: &Value) -> Result<SettingContext, ApiError> {
+    match (body.get("server"), body.get("channel"), body.get("user")) {
+        (Some(_), None, None) => Ok(SettingContext::Server(
+            ServerId::from_str(body_str(body, "server")?).map_err(bad_request)?,
+        )),
+        (None, Some(_), None) => Ok(SettingContext::Channel(
+            ChannelId::from_str(body_str(body, "channel")?).map_err(bad_request)?,
+        )),
+        (None, None, Some(_)) => Ok(SettingContext::User(
+            bot.db()
+                .find_user(body_str(body, "user")?)
+                .await
+                .map_err(bad_request)?
+                .uid,
+        )),
+        _ => Err(ApiError::BadRequest(
+            "exactly one of \"server\", \"channel\" or \"user\" has to be set".into(),
+        )),
+    }
+}
+
+fn module_settings(bot: &Bot, module: &str) -> Result<Arc<dyn Settings>, ApiError> {
+    bot.get_ctx()
+        .modules()
+        .get_settings(module)
+        .ok_or(ApiError::NotFound)
+}
+
+fn lua_module(bot: &Bot) -> Result<Arc<LuaModule>, ApiError> {
+    bot.get_ctx()
+        .modules()
+        .get::<LuaModule>()
+        .ok_or_else(|| ApiError::BadRequest("the lua module is not loaded".into()))
+}
+
+fn tag_json(tag: &Tag) -> Value {
+    json!({
+        "key": tag.key,
+        "uid": tag.uid,
+        "transfer_uid": tag.transfer_uid,
+        "value": tag.value,
+    })
+}
+
+async fn route(
+    bot: &Bot,
+    method: &Method,
+    segments: &[&str],
+    body: Value,
+) -> Result<Value, ApiError> {
+    let db = bot.db();
+
+    match (method, segments) {
+        // Settings
+        (&Method::GET, ["modules", module, "settings"]) => {
+            let settings = module_settings(bot, module)?;
+
+            Ok(settings
+                .enumerate()
+                .into_iter()
+                .map(|info| {
+                    json!({
+                        "name": info.name,
+                        "help": info.help,
+                        "type": info.kind.as_str(),
+                        "allowed": info.allowed,
+                        "user_override": info.user_override,
+                    })
+                })
+                .collect())
+        }
+        (&Method::PUT, ["modules", module, "settings", setting]) => {
+            let settings = module_settings(bot, module)?;
+            let ctx = setting_context(bot, &body).await?;
+            let value = body_str(&body, "value")?;
+
+            settings
+                .set_setting(ctx, setting, value)
+                .await
+                .map_err(bad_request)?;
+
+            Ok(json!({ "name": setting, "value": value }))
+        }
+        (&Method::DELETE, ["modules", module, "settings", setting]) => {
+            let settings = module_settings(bot, module)?;
+            let ctx = setting_context(bot, &body).await?;
+
+            let removed = settings
+                .unset_setting(ctx, setting)
+                .await
+                .map_err(bad_request)?;
+
+            Ok(json!({ "name": setting, "removed": removed }))
+        }
+        (&Method::GET, ["servers", server, "settings"]) => {
+            let server_id = ServerId::from_str(server).map_err(bad_request)?;
+            let overrides = db.list_setting_overrides(server_id).await?;
+
+            Ok(overrides
+                .iter()
+                .map(|setting| {
+                    json!({
+                        "key": setting.key,
+                        "value": setting.value,
+                        "channel": setting.channel_id.map(|id| id.to_str()),
+                    })
+                })
+                .collect())
+        }
+        (&Method::GET, ["servers", server, "channels", channel, "settings", module, setting])
+        | (
+            &Method::GET,
+            ["servers", server, "channels", channel, "users", _, "settings", module, setting],
+        ) => {
+            let server_id = ServerId::from_str(server).map_err(bad_request)?;
+            let channel_id = ChannelId::from_str(channel).map_err(bad_request)?;
+            let uid = match segments {
+                [.., "users", user, "settings", _, _] => {
+                    Some(db.find_user(user).await.map_err(bad_request)?.uid)
+                }
+                _ => None,
+            };
+
+            let effective = module_settings(bot, module)?
+                .get_setting(setting, server_id, channel_id, uid)
+                .await
+                .map_err(bad_request)?;
+
+            Ok(json!({
+                "name": effective.name,
+                "value": effective.value,
+                "source": effective.source.as_str(),
+            }))
+        }
+
+        (&Method::GET, ["modules"]) => Ok(bot
+            .get_ctx()
+            .modules()
+            .list()
+            .into_iter()
+            .map(|(id, enabled)| json!({ "id": id, "enabled": enabled }))
+            .collect()),
+        (&Method::PUT, ["modules", module, "enabled"]) => {
+            let enabled = body
+                .get("enabled")
+                .and_then(|value| value.as_bool())
+                .ok_or_else(|| ApiError::BadRequest("missing bool field \"enabled\"".into()))?;
+
+            bot.get_ctx()
+                .modules()
+                .set_enabled(module, enabled)
+                .map_err(|_| ApiError::NotFound)?;
+
+            Ok(json!({ "id": module, "enabled": enabled }))
+        }
+
+        // Tags
+        (&Method::GET, ["servers", server, "tags"]) => {
+            let server_id = ServerId::from_str(server).map_err(bad_request)?;
+            let sid = db.find_sid(server_id).await?.ok_or(ApiError::NotFound)?;
+            let tags = db.list_server_tags(sid).await?;
+
+            Ok(tags.iter().map(tag_json).collect())
+        }
+        (&Method::GET, ["servers", server, "tags", key]) => {
+            let server_id = ServerId::from_str(server).map_err(bad_request)?;
+
+            match db.find_tag(server_id, &key.to_lowercase()).await? {
+                Some(tag) => Ok(tag_json(&tag)),
+                None => Err(ApiError::NotFound),
+            }
+        }
+        (&Method::PUT, ["servers", server, "tags", key]) => {
+            let server_id = ServerId::from_str(server).map_err(bad_request)?;
+            let key = key.to_lowercase();
+            let value = body_str(&body, "value")?;
+
+            // Edit the tag if it exists, otherwise create it for the given user
+            match db.find_tag(server_id, &key).await? {
+                Some(tag) => db.edit_tag(tag.sid, &key, value).await?,
+                None => {
+                    let user = db
+                        .find_user(body_str(&body, "user")?)
+                        .await
+                        .map_err(bad_request)?;
+
+                    db.create_tag(user.uid, server_id, &key, value).await?;
+                }
+            }
+
+            match db.find_tag(server_id, &key).await? {
+                Some(tag) => Ok(tag_json(&tag)),
+                None => Err(ApiError::NotFound),
+            }
+        }
+        (&Method::DELETE, ["servers", server, "tags", key]) => {
+            let server_id = ServerId::from_str(server).map_err(bad_request)?;
+            let key = key.to_lowercase();
+
+            match db.find_tag(server_id, &key).await? {
+                Some(tag) => {
+                    db.delete_tag(tag.sid, &key).await?;
+                    Ok(tag_json(&tag))
+                }
+                None => Err(ApiError::NotFound),
+            }
+        }
+
+        // Users
+        (&Method::GET, ["users", user]) => {
+            let user = db.find_user(user).await.map_err(bad_request)?;
+
+            Ok(json!({
+                "uid": user.uid,
+                "role": user.role,
+                "service_id": user.service_user_id().to_str(),
+                "restricted": db.is_restricted(user.uid).await?,
+            }))
+        }
+        (&Method::PUT, ["users", user, "role"]) => {
+            let user = db.find_user(user).await.map_err(bad_request)?;
+            let role = body_str(&body, "role")?;
+
+            db.set_role_for_user(user.uid, role)
+                .await
+                .map_err(bad_request)?;
+
+            Ok(json!({ "uid": user.uid, "role": role }))
+        }
+        (&Method::PUT, ["users", user, "restriction"]) => {
+            let user = db.find_user(user).await.map_err(bad_request)?;
+            let restrictor = db
+                .find_user(body_str(&body, "by")?)
+                .await
+                .map_err(bad_request)?;
+
+            if !db.is_restricted(user.uid).await? {
+                db.restrict_user(user.uid, restrictor.uid).await?;
+            }
+
+            Ok(json!({ "uid": user.uid, "restricted": true }))
+        }
+        (&Method::DELETE, ["users", user, "restriction"]) => {
+            let user = db.find_user(user).await.map_err(bad_request)?;
+
+            db.unrestrict_user(user.uid).await?;
+
+            Ok(json!({ "uid": user.uid, "restricted": false }))
+        }
+
+        // Lua
+        (&Method::POST, ["lua", "restart-sandbox"]) => {
+            lua_module(bot)?.restart_sandbox().await?;
+
+            Ok(json!({}))
+        }
+        (&Method::POST, ["lua", "reload"]) => {
+            lua_module(bot)?.reload_bot_state().await?;
+
+            Ok(json!({}))
+        }
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+async fn handle_request(bot: &Bot, token: &str, req: Request<Body>) -> Result<Value, ApiError> {
+    let authorized = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|given| token_matches(token, given))
+        .unwrap_or(false);
+
+    if !authorized {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let method = req.method().clone();
+    let segments = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_decode)
+        .collect::<Vec<_>>();
+    let segments = segments.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+
+    let mut req_body = req.into_body();
+    let mut data = Vec::new();
+    while let Some(chunk) = req_body.data().await {
+        let chunk = chunk.map_err(|err| ApiError::BadRequest(err.to_string()))?;
+
+        if data.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(ApiError::PayloadTooLarge);
+        }
+
+        data.extend_from_slice(&chunk);
+    }
+
+    let body = if data.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&data).map_err(|err| ApiError::BadRequest(err.to_string()))?
+    };
+
+    route(bot, &method, &segments, body).await
+}
+
+/// Serve the admin API until the listener fails
+pub async fn serve(bot: Arc<Bot>, config: AdminConfig) -> Result<()> {
+    check_token(&config.token)?;
+
+    let token = Arc::new(config.token);
+
+    let make_service = make_service_fn(move |_conn| {
+        let bot = bot.clone();
+        let token = token.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let bot = bot.clone();
+                let token = token.clone();
+
+                async move {
+                    let method = req.method().clone();
+                    let path = req.uri().path().to_string();
+
+                    let (status, value) = match handle_request(&bot, &token, req).await {
+                        Ok(value) => (StatusCode::OK, value),
+                        Err(err) => {
+                            if let ApiError::Internal(err) = &err {
+                                log_error!(
+                                    "admin",
+                                    { method = method, path = path },
+                                    "Error handling admin request: {}",
+                                    err.to_string()
+                                );
+                            }
+
+                            (err.status(), json!({ "error": err.to_string() }))
+                        }
+                    };
+
+                    let mut res = Response::new(Body::from(value.to_string()));
+                    *res.status_mut() = status;
+                    res.headers_mut()
+                        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+
+                    Ok::<_, Infallible>(res)
+                }
+            }))
+        }
+    });
+
+    Server::try_bind(&config.listen)?
+        .serve(make_service)
+        .await?;
+
+    Ok(())
+}
@@ -1,31 +1,50 @@
-use anyhow::Result;
-use arc_swap::ArcSwapOption;
+use anyhow::{anyhow, Result};
+use arc_swap::{ArcSwap, ArcSwapOption};
 use std::{
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 pub mod db;
 
 use crate::{
-    config::Config,
+    config::{self, Config, ConfigReload},
+    i18n::Catalog,
+    logging,
     modules::Modules,
-    services::{ChannelId, Message, MessageId, ServerId, Service, Services, User},
+    services::{ChannelId, Message, MessageId, ServerId, Service, Services, User, UserId},
+    utils::{tasks::TaskTracker, vfs::Vfs},
 };
 use db::BotDb;
 
 pub const ROLES: &[&'static str] = &["guest", "trusted", "admin", "root"];
 pub const DEFAULT_ROLE: &'static str = ROLES[0];
 
+/// How long shutdown waits for in-flight work before unloading anyway
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct Bot {
     ctx: ArcSwapOption<BotContext>,
     db: Arc<BotDb>,
+    config: ArcSwap<Config>,
+    config_path: PathBuf,
     data_path: PathBuf,
     share_path: PathBuf,
+    catalog: ArcSwap<Catalog>,
+    tasks: Arc<TaskTracker>,
+    shutting_down: AtomicBool,
 }
 
 macro_rules! get_ctx {
     ($self:expr) => {
+        if $self.is_shutting_down() {
+            return;
+        }
+
         match &*$self.ctx.load() {
             Some(c) => c.clone(),
             None => return,
@@ -37,16 +56,110 @@ impl Bot {
     pub async fn init(
         data_path: PathBuf,
         share_path: PathBuf,
+        config_path: PathBuf,
         config: &Config,
     ) -> Result<Arc<Bot>> {
         Ok(Arc::new(Bot {
             ctx: ArcSwapOption::default(),
             db: BotDb::new(&data_path, &share_path, config).await?,
+            config: ArcSwap::from_pointee(config.clone()),
+            config_path,
             data_path,
+            catalog: ArcSwap::from_pointee(Catalog::load(&Vfs::share(&share_path, "locales"))?),
             share_path,
+            tasks: Default::default(),
+            shutting_down: AtomicBool::new(false),
         }))
     }
 
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+
+    /// Re-read the config file and apply the sections which can be changed at runtime
+    pub async fn reload_config(&self) -> Result<ConfigReload> {
+        let config = config::load_config(&self.config_path)?;
+        let old_config = self.config();
+        let mut report = ConfigReload::default();
+
+        // Check everything which can fail before applying anything, so a failed reload changes nothing.
+        // The translations are not part of the config but reloading them is expected at the same time.
+        let catalog = Catalog::load(&Vfs::share(&self.share_path, "locales"))?;
+        let ctx = self.ctx.load_full();
+
+        if let Some(ctx) = ctx.as_ref() {
+            ctx.modules().check_config(&config)?;
+        }
+
+        for (id, role) in config.user_roles.iter().flatten() {
+            UserId::from_str(id)?;
+
+            if !ROLES.contains(&role.as_str()) {
+                return Err(anyhow!(
+                    "unknown role \"{}\" for {} in user_roles",
+                    role,
+                    id
+                ));
+            }
+        }
+
+        // Pick up changes the cli made to the database while the bot was running
+        self.db.clear_cache();
+
+        if config.user_roles != old_config.user_roles {
+            let user_roles = config.user_roles.clone().unwrap_or_default();
+            let removed = old_config
+                .user_roles
+                .iter()
+                .flat_map(|old_user_roles| old_user_roles.keys())
+                .filter(|id| !user_roles.contains_key(*id))
+                .map(|id| id.as_str())
+                .collect::<Vec<_>>();
+
+            self.db.reset_user_roles(&removed).await?;
+            self.db.apply_user_roles(&user_roles).await?;
+
+            report.applied.push("user_roles");
+        }
+
+        if config.logging != old_config.logging {
+            logging::init(&config.logging.clone().unwrap_or_default());
+            report.applied.push("logging");
+        }
+
+        self.catalog.store(Arc::new(catalog));
+        report.applied.push("locales");
+
+        if let Some(ctx) = ctx {
+            ctx.modules().reload_config(&config).await?;
+        }
+
+        if config.modules != old_config.modules {
+            report.applied.push("modules");
+        }
+
+        if config.services != old_config.services {
+            report.restart_required.push("services");
+        }
+
+        let load_modules = |config: &Config| config.modules.as_ref().and_then(|m| m.load.clone());
+        if load_modules(&config) != load_modules(&old_config) {
+            report.restart_required.push("modules.load");
+        }
+
+        if config.metrics != old_config.metrics {
+            report.restart_required.push("metrics");
+        }
+
+        if config.admin != old_config.admin {
+            report.restart_required.push("admin");
+        }
+
+        self.config.store(Arc::new(config));
+
+        Ok(report)
+    }
+
     pub fn data_path(&self) -> &Path {
         &self.data_path
     }
@@ -59,6 +172,18 @@ impl Bot {
         &self.db
     }
 
+    pub fn catalog(&self) -> Arc<Catalog> {
+        self.catalog.load_full()
+    }
+
+    pub fn tasks(&self) -> &Arc<TaskTracker> {
+        &self.tasks
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Acquire)
+    }
+
     pub fn set_ctx(&self, ctx: Arc<BotContext>) {
         self.ctx.store(Some(ctx));
     }
@@ -75,6 +200,7 @@ impl Bot {
 
     pub async fn message(&self, msg: Arc<dyn Message<impl Service>>) {
         let ctx = get_ctx!(self);
+        let _task = self.tasks.track();
 
         ctx.modules().message(msg).await;
     }
@@ -85,6 +211,7 @@ impl Bot {
         old_msg: Option<Arc<dyn Message<impl Service>>>,
     ) {
         let ctx = get_ctx!(self);
+        let _task = self.tasks.track();
 
         ctx.modules().message_update(msg, old_msg).await;
     }
@@ -96,6 +223,7 @@ impl Bot {
         message_id: MessageId,
     ) {
         let ctx = get_ctx!(self);
+        let _task = self.tasks.track();
 
         ctx.modules()
             .message_delete(server_id, channel_id, message_id)
@@ -110,6 +238,7 @@ impl Bot {
         remove: bool,
     ) {
         let ctx = get_ctx!(self);
+        let _task = self.tasks.track();
 
         ctx.modules().reaction(msg, reactor, reaction, remove).await;
     }
@@ -144,8 +273,36 @@ impl BotContext {
     }
 
     pub async fn shutdown(&self) -> Result<()> {
-        self.modules().unload().await?;
+        // Stop accepting new events
+        self.bot.shutting_down.store(true, Ordering::Release);
+
+        // Let in-flight module tasks and outbound messages finish
+        self.wait_for_tasks().await;
+        let modules_res = self.modules().unload().await;
+
+        // Unloading the modules can queue up more messages, e.g. from lua shutdown hooks
+        self.wait_for_tasks().await;
+        let services_res = self.services().unload().await;
+
+        modules_res?;
+        services_res?;
 
         Ok(())
     }
+
+    async fn wait_for_tasks(&self) {
+        let tasks = self.bot.tasks();
+        log_debug!("bot", "Waiting for {} in-flight tasks", tasks.count());
+
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, tasks.wait_idle())
+            .await
+            .is_err()
+        {
+            log_warn!(
+                "bot",
+                "Timed out waiting for {} in-flight tasks, continuing shutdown",
+                tasks.count()
+            );
+        }
+    }
 }
@@ -1,22 +1,107 @@
 use anyhow::{anyhow, Result};
 use sqlx::{
-    migrate::Migrator,
+    migrate::{Migration, Migrator},
     sqlite::{Sqlite, SqliteConnectOptions, SqliteSynchronous},
     Executor, Pool,
 };
-use std::{path::Path, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+};
+
+pub mod archive;
+mod cache;
+pub mod kv;
+pub mod storage;
 
+use self::cache::DbCache;
 use super::{DEFAULT_ROLE, ROLES};
 use crate::{
     config::Config,
+    metrics::METRICS,
     services::{ChannelId, ServerId, UserId},
 };
 
 pub type Uid = i64;
 pub type Sid = i64;
 
+fn discord_id_from_bytes(data: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.clone_from_slice(&data[0..8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(feature = "embed")]
+fn embedded_migrator() -> Option<&'static Migrator> {
+    static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+    Some(&MIGRATOR)
+}
+
+#[cfg(not(feature = "embed"))]
+fn embedded_migrator() -> Option<&'static Migrator> {
+    None
+}
+
+/// The embedded migrations with the ones in `<share>/migrations` laid over them by version, so the
+/// share directory can replace single migrations or add new ones
+async fn load_migrator(share_path: &Path) -> Result<Migrator> {
+    let migrations_path = share_path.join("migrations");
+
+    let disk = if migrations_path.exists() {
+        Some(Migrator::new(migrations_path.clone()).await?)
+    } else {
+        None
+    };
+
+    let (embedded, disk) = match (embedded_migrator(), disk) {
+        (Some(embedded), Some(disk)) => (embedded, disk),
+        (None, Some(disk)) => return Ok(disk),
+        (Some(embedded), None) => {
+            return Ok(Migrator {
+                migrations: embedded.migrations.clone(),
+                ..Migrator::DEFAULT
+            })
+        }
+        (None, None) => {
+            return Err(anyhow!(
+                "no migrations found in {}",
+                migrations_path.display()
+            ))
+        }
+    };
+
+    let mut migrations: BTreeMap<i64, Migration> = embedded
+        .migrations
+        .iter()
+        .map(|migration| (migration.version, migration.clone()))
+        .collect();
+
+    for migration in disk.migrations.iter() {
+        if let Some(existing) = migrations.get(&migration.version) {
+            if existing.checksum != migration.checksum {
+                log_warn!(
+                    "db",
+                    "Migration {} in the share directory differs from the embedded one, using the share directory",
+                    migration.version
+                );
+            }
+        }
+
+        migrations.insert(migration.version, migration.clone());
+    }
+
+    Ok(Migrator {
+        migrations: Cow::Owned(migrations.into_values().collect()),
+        ..Migrator::DEFAULT
+    })
+}
+
 pub struct BotDb {
     pool: Pool<Sqlite>,
+    cache: DbCache,
 }
 
 impl BotDb {
@@ -29,23 +114,79 @@ impl BotDb {
         )
         .await?;
 
-        let m = Migrator::new(share_path.join("migrations")).await?;
-        m.run(&pool).await?;
+        load_migrator(share_path).await?.run(&pool).await?;
 
-        let db = Arc::new(BotDb { pool });
+        let db = Arc::new(BotDb {
+            pool,
+            cache: DbCache::new(),
+        });
 
         if let Some(user_roles) = config.user_roles.as_ref() {
-            for (id_str, role) in user_roles {
-                let user_id = UserId::from_str(&id_str)?;
-                let user = db.get_user_from_service_user_id(user_id).await?;
-                db.set_role_for_user(user.uid, role).await?;
-            }
+            db.apply_user_roles(user_roles).await?;
         }
 
+        let imported = db.import_data_files(data_path).await?;
+        if imported > 0 {
+            log_info!("db", "Imported {} data files into the key-value store", imported);
+        }
+
+        db.kv_purge_expired().await?;
+
         Ok(db)
     }
 
+    /// An empty in-memory database with the migrations of the repository, for the tests
+    #[cfg(test)]
+    pub async fn in_memory() -> Result<Arc<BotDb>> {
+        // Every connection opens its own in-memory database, so the pool has to keep exactly one
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with("sqlite::memory:".parse::<SqliteConnectOptions>()?)
+            .await?;
+
+        load_migrator(Path::new(env!("CARGO_MANIFEST_DIR")))
+            .await?
+            .run(&pool)
+            .await?;
+
+        Ok(Arc::new(BotDb {
+            pool,
+            cache: DbCache::new(),
+        }))
+    }
+
+    pub async fn apply_user_roles(&self, user_roles: &HashMap<String, String>) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["apply_user_roles"]);
+
+        for (id_str, role) in user_roles {
+            let user_id = UserId::from_str(&id_str)?;
+            let user = self.get_user_from_service_user_id(user_id).await?;
+            self.set_role_for_user(user.uid, role).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Put the users which were removed from the `user_roles` config back on the default role
+    pub async fn reset_user_roles(&self, ids: &[&str]) -> Result<()> {
+        for id_str in ids {
+            if let Some(uid) = self.find_uid(UserId::from_str(id_str)?).await? {
+                self.set_role_for_user(uid, DEFAULT_ROLE).await?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn get_user_from_uid(&self, uid: Uid) -> Result<User> {
+        if let Some(user) = self.cache.users.get(&uid) {
+            return Ok(user);
+        }
+
+        let _timer = METRICS.db_query_duration.start_timer(&["get_user_from_uid"]);
+
         let (role, discord_id): (Option<String>, Option<Vec<u8>>) =
             sqlx::query_as("SELECT role, discord_id FROM users WHERE uid = ?")
                 .bind(uid)
@@ -61,14 +202,28 @@ impl BotDb {
             u64::from_le_bytes(bytes)
         });
 
-        Ok(User {
+        let user = User {
             uid,
             role,
             discord_id,
-        })
+        };
+        self.cache.users.insert(uid, user.clone());
+
+        Ok(user)
     }
 
     pub async fn get_user_from_service_user_id(&self, service_user_id: UserId) -> Result<User> {
+        if let Some(user) = self
+            .cache
+            .service_users
+            .get(&service_user_id)
+            .and_then(|uid| self.cache.users.get(&uid))
+        {
+            return Ok(user);
+        }
+
+        let _timer = METRICS.db_query_duration.start_timer(&["get_user_from_service_user_id"]);
+
         let res: Result<(Uid, Option<String>, Option<Vec<u8>>), sqlx::Error> =
             match service_user_id {
                 UserId::Discord(discord_id) => {
@@ -108,14 +263,57 @@ impl BotDb {
             u64::from_le_bytes(bytes)
         });
 
-        Ok(User {
+        let user = User {
             uid,
             role,
             discord_id,
-        })
+        };
+        self.cache.service_users.insert(service_user_id, uid);
+        self.cache.users.insert(uid, user.clone());
+
+        Ok(user)
+    }
+
+    /// Find the uid of a service user without creating the user when it is not known
+    pub async fn find_uid(&self, service_user_id: UserId) -> Result<Option<Uid>> {
+        if let Some(uid) = self.cache.service_users.get(&service_user_id) {
+            return Ok(Some(uid));
+        }
+
+        let _timer = METRICS.db_query_duration.start_timer(&["find_uid"]);
+
+        let res: Option<(Uid,)> = match service_user_id {
+            UserId::Discord(discord_id) => {
+                sqlx::query_as("SELECT uid FROM users WHERE discord_id = ?")
+                    .bind(discord_id.to_le_bytes().to_vec())
+            }
+        }
+        .fetch_optional(self.pool())
+        .await?;
+
+        if let Some((uid,)) = res {
+            self.cache.service_users.insert(service_user_id, uid);
+        }
+
+        Ok(res.map(|(uid,)| uid))
+    }
+
+    /// Find a user from either a uid or a service user id like "discord:<id>", unknown users are not created
+    pub async fn find_user(&self, user: &str) -> Result<User> {
+        let uid = match user.parse::<Uid>() {
+            Ok(uid) => uid,
+            Err(_) => self
+                .find_uid(UserId::from_str(user)?)
+                .await?
+                .ok_or_else(|| anyhow!("unknown user \"{}\"", user))?,
+        };
+
+        self.get_user_from_uid(uid).await
     }
 
     pub async fn set_role_for_user(&self, user_id: Uid, role: &str) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["set_role_for_user"]);
+
         if !ROLES.contains(&role) {
             return Err(anyhow!("unknown role \"{}\"", role));
         }
@@ -127,11 +325,14 @@ impl BotDb {
                     .bind(user_id),
             )
             .await?;
+        self.cache.users.remove(&user_id);
 
         Ok(())
     }
 
     pub async fn restrict_user(&self, user_id: Uid, restrictor_user_id: Uid) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["restrict_user"]);
+
         self.pool()
             .execute(
                 sqlx::query("INSERT INTO restrictions ( uid, restrictor_user_id ) VALUES ( ?, ? )")
@@ -139,19 +340,29 @@ impl BotDb {
                     .bind(restrictor_user_id),
             )
             .await?;
+        self.cache.restrictions.insert(user_id, true);
 
         Ok(())
     }
 
     pub async fn unrestrict_user(&self, user_id: Uid) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["unrestrict_user"]);
+
         self.pool()
             .execute(sqlx::query("DELETE FROM restrictions WHERE uid = ?").bind(user_id))
             .await?;
+        self.cache.restrictions.insert(user_id, false);
 
         Ok(())
     }
 
     pub async fn is_restricted(&self, user_id: Uid) -> Result<bool> {
+        if let Some(restricted) = self.cache.restrictions.get(&user_id) {
+            return Ok(restricted);
+        }
+
+        let _timer = METRICS.db_query_duration.start_timer(&["is_restricted"]);
+
         let restricted = sqlx::query_as("SELECT uid FROM restrictions WHERE uid = ?")
             .bind(user_id)
             .fetch_one(self.pool())
@@ -161,53 +372,113 @@ impl BotDb {
                 sqlx::Error::RowNotFound => Ok(false),
                 _ => Err(err),
             })?;
+        self.cache.restrictions.insert(user_id, restricted);
 
         Ok(restricted)
     }
 
+    /// Overrides saved before channel settings were linked to a server get the known server
+    /// filled in, so they show up in the server's list of overrides
     pub async fn get_channel_setting(
         &self,
+        server_id: Option<ServerId>,
         channel_id: ChannelId,
         key: &str,
     ) -> Result<Option<String>> {
-        sqlx::query_as("SELECT value FROM settings_channel WHERE channel_id = ? AND key = ?")
+        let cache_key = (channel_id, key.to_string());
+        if let Some(value) = self.cache.channel_settings.get(&cache_key) {
+            return Ok(value);
+        }
+
+        let _timer = METRICS.db_query_duration.start_timer(&["get_channel_setting"]);
+
+        let row: Option<(String, Option<String>)> = sqlx::query_as(
+            "SELECT value, server_id FROM settings_channel WHERE channel_id = ? AND key = ?",
+        )
+        .bind(channel_id.to_short_str())
+        .bind(key)
+        .fetch_optional(self.pool())
+        .await?;
+
+        if let (Some((_, None)), Some(server_id)) = (&row, server_id) {
+            sqlx::query(
+                "UPDATE settings_channel SET server_id = ? WHERE channel_id = ? AND server_id IS NULL",
+            )
+            .bind(server_id.to_short_str())
             .bind(channel_id.to_short_str())
-            .bind(key)
-            .fetch_one(self.pool())
-            .await
-            .map(|val: (String,)| Some(val.0))
-            .or_else(|err| match err {
-                sqlx::Error::RowNotFound => Ok(None),
-                _ => Err(err.into()),
-            })
+            .execute(self.pool())
+            .await?;
+        }
+
+        let value = row.map(|(value, _)| value);
+        self.cache.channel_settings.insert(cache_key, value.clone());
+
+        Ok(value)
     }
 
+    /// The server is used to list the overrides of a server, when it's None the known server is kept
     pub async fn save_channel_setting(
         &self,
+        server_id: Option<ServerId>,
         channel_id: ChannelId,
         key: &str,
         value: &str,
     ) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["save_channel_setting"]);
+
         self.pool()
             .execute(
                 sqlx::query(
-                    "REPLACE INTO settings_channel ( channel_id, key, value ) VALUES ( ?, ?, ? )",
+                    "INSERT INTO settings_channel ( channel_id, key, value, server_id ) VALUES ( ?, ?, ?, ? )
+                    ON CONFLICT ( channel_id, key ) DO UPDATE SET value = excluded.value,
+                        server_id = COALESCE(excluded.server_id, settings_channel.server_id)",
                 )
                 .bind(channel_id.to_short_str())
                 .bind(key)
-                .bind(value),
+                .bind(value)
+                .bind(server_id.map(|server_id| server_id.to_short_str())),
             )
             .await?;
+        self.cache
+            .channel_settings
+            .insert((channel_id, key.to_string()), Some(value.to_string()));
 
         Ok(())
     }
 
+    pub async fn delete_channel_setting(&self, channel_id: ChannelId, key: &str) -> Result<bool> {
+        let _timer = METRICS
+            .db_query_duration
+            .start_timer(&["delete_channel_setting"]);
+
+        let res = self
+            .pool()
+            .execute(
+                sqlx::query("DELETE FROM settings_channel WHERE channel_id = ? AND key = ?")
+                    .bind(channel_id.to_short_str())
+                    .bind(key),
+            )
+            .await?;
+        self.cache
+            .channel_settings
+            .insert((channel_id, key.to_string()), None);
+
+        Ok(res.rows_affected() > 0)
+    }
+
     pub async fn get_server_setting(
         &self,
         server_id: ServerId,
         key: &str,
     ) -> Result<Option<String>> {
-        sqlx::query_as("SELECT value FROM settings_server WHERE server_id = ? AND key = ?")
+        let cache_key = (server_id, key.to_string());
+        if let Some(value) = self.cache.server_settings.get(&cache_key) {
+            return Ok(value);
+        }
+
+        let _timer = METRICS.db_query_duration.start_timer(&["get_server_setting"]);
+
+        let value = sqlx::query_as("SELECT value FROM settings_server WHERE server_id = ? AND key = ?")
             .bind(server_id.to_short_str())
             .bind(key)
             .fetch_one(self.pool())
@@ -215,8 +486,11 @@ impl BotDb {
             .map(|val: (String,)| Some(val.0))
             .or_else(|err| match err {
                 sqlx::Error::RowNotFound => Ok(None),
-                _ => Err(err.into()),
-            })
+                _ => Err(err),
+            })?;
+        self.cache.server_settings.insert(cache_key, value.clone());
+
+        Ok(value)
     }
 
     pub async fn save_server_setting(
@@ -225,6 +499,8 @@ impl BotDb {
         key: &str,
         value: &str,
     ) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["save_server_setting"]);
+
         self.pool()
             .execute(
                 sqlx::query(
@@ -235,11 +511,138 @@ impl BotDb {
                 .bind(value),
             )
             .await?;
+        self.cache
+            .server_settings
+            .insert((server_id, key.to_string()), Some(value.to_string()));
 
         Ok(())
     }
 
+    pub async fn delete_server_setting(&self, server_id: ServerId, key: &str) -> Result<bool> {
+        let _timer = METRICS
+            .db_query_duration
+            .start_timer(&["delete_server_setting"]);
+
+        let res = self
+            .pool()
+            .execute(
+                sqlx::query("DELETE FROM settings_server WHERE server_id = ? AND key = ?")
+                    .bind(server_id.to_short_str())
+                    .bind(key),
+            )
+            .await?;
+        self.cache
+            .server_settings
+            .insert((server_id, key.to_string()), None);
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// All server overrides and the channel overrides of channels in the server, sorted by key
+    pub async fn list_setting_overrides(&self, server_id: ServerId) -> Result<Vec<SettingOverride>> {
+        let _timer = METRICS
+            .db_query_duration
+            .start_timer(&["list_setting_overrides"]);
+
+        let server_rows: Vec<(String, String)> =
+            sqlx::query_as("SELECT key, value FROM settings_server WHERE server_id = ?")
+                .bind(server_id.to_short_str())
+                .fetch_all(self.pool())
+                .await?;
+
+        let channel_rows: Vec<(String, String, String)> = sqlx::query_as(
+            "SELECT channel_id, key, value FROM settings_channel WHERE server_id = ?",
+        )
+        .bind(server_id.to_short_str())
+        .fetch_all(self.pool())
+        .await?;
+
+        let mut overrides = server_rows
+            .into_iter()
+            .map(|(key, value)| SettingOverride {
+                channel_id: None,
+                key,
+                value,
+            })
+            .collect::<Vec<_>>();
+
+        for (channel_id, key, value) in channel_rows {
+            overrides.push(SettingOverride {
+                channel_id: Some(ChannelId::from_str(&channel_id)?),
+                key,
+                value,
+            });
+        }
+
+        overrides.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(overrides)
+    }
+
+    pub async fn get_user_setting(&self, uid: Uid, key: &str) -> Result<Option<String>> {
+        let cache_key = (uid, key.to_string());
+        if let Some(value) = self.cache.user_settings.get(&cache_key) {
+            return Ok(value);
+        }
+
+        let _timer = METRICS.db_query_duration.start_timer(&["get_user_setting"]);
+
+        let value = sqlx::query_as("SELECT value FROM settings_user WHERE uid = ? AND key = ?")
+            .bind(uid)
+            .bind(key)
+            .fetch_one(self.pool())
+            .await
+            .map(|val: (String,)| Some(val.0))
+            .or_else(|err| match err {
+                sqlx::Error::RowNotFound => Ok(None),
+                _ => Err(err),
+            })?;
+        self.cache.user_settings.insert(cache_key, value.clone());
+
+        Ok(value)
+    }
+
+    pub async fn save_user_setting(&self, uid: Uid, key: &str, value: &str) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["save_user_setting"]);
+
+        self.pool()
+            .execute(
+                sqlx::query("REPLACE INTO settings_user ( uid, key, value ) VALUES ( ?, ?, ? )")
+                    .bind(uid)
+                    .bind(key)
+                    .bind(value),
+            )
+            .await?;
+        self.cache
+            .user_settings
+            .insert((uid, key.to_string()), Some(value.to_string()));
+
+        Ok(())
+    }
+
+    pub async fn delete_user_setting(&self, uid: Uid, key: &str) -> Result<bool> {
+        let _timer = METRICS.db_query_duration.start_timer(&["delete_user_setting"]);
+
+        let res = self
+            .pool()
+            .execute(
+                sqlx::query("DELETE FROM settings_user WHERE uid = ? AND key = ?")
+                    .bind(uid)
+                    .bind(key),
+            )
+            .await?;
+        self.cache.user_settings.insert((uid, key.to_string()), None);
+
+        Ok(res.rows_affected() > 0)
+    }
+
     pub async fn get_sid(&self, server_id: ServerId) -> Result<Sid> {
+        if let Some(sid) = self.cache.sids.get(&server_id) {
+            return Ok(sid);
+        }
+
+        let _timer = METRICS.db_query_duration.start_timer(&["get_sid"]);
+
         let res: Result<(Sid,), sqlx::Error> = match server_id {
             ServerId::Discord(discord_id) => {
                 sqlx::query_as("SELECT sid FROM servers WHERE discord_id = ?")
@@ -249,7 +652,7 @@ impl BotDb {
         .fetch_one(self.pool())
         .await;
 
-        match res {
+        let sid = match res {
             Err(sqlx::Error::RowNotFound) => {
                 let res = match server_id {
                     ServerId::Discord(discord_id) => {
@@ -262,16 +665,48 @@ impl BotDb {
                     }
                 };
 
-                Ok(res.last_insert_rowid())
+                res.last_insert_rowid()
             }
             Err(err) => return Err(err.into()),
-            Ok((res,)) => Ok(res),
+            Ok((res,)) => res,
+        };
+        self.cache.sids.insert(server_id, sid);
+
+        Ok(sid)
+    }
+
+    /// Like `get_sid` but without adding the server when it is not known yet
+    pub async fn find_sid(&self, server_id: ServerId) -> Result<Option<Sid>> {
+        if let Some(sid) = self.cache.sids.get(&server_id) {
+            return Ok(Some(sid));
         }
+
+        let _timer = METRICS.db_query_duration.start_timer(&["find_sid"]);
+
+        let res: Option<(Sid,)> = match server_id {
+            ServerId::Discord(discord_id) => {
+                sqlx::query_as("SELECT sid FROM servers WHERE discord_id = ?")
+                    .bind(discord_id.to_le_bytes().to_vec())
+            }
+        }
+        .fetch_optional(self.pool())
+        .await?;
+
+        if let Some((sid,)) = res {
+            self.cache.sids.insert(server_id, sid);
+        }
+
+        Ok(res.map(|(sid,)| sid))
     }
 
     // Tags
     pub async fn find_tag(&self, server_id: ServerId, key: &str) -> Result<Option<Tag>> {
-        let sid = self.get_sid(server_id).await?;
+        let _timer = METRICS.db_query_duration.start_timer(&["find_tag"]);
+
+        let sid = match self.find_sid(server_id).await? {
+            Some(sid) => sid,
+            None => return Ok(None),
+        };
 
         sqlx::query_as("SELECT value, uid, transfer_uid FROM tags WHERE key = ? AND sid = ?")
             .bind(key)
@@ -300,6 +735,8 @@ impl BotDb {
         key: &str,
         value: &str,
     ) -> Result<bool> {
+        let _timer = METRICS.db_query_duration.start_timer(&["create_tag"]);
+
         let sid = self.get_sid(server_id).await?;
 
         match self
@@ -320,6 +757,8 @@ impl BotDb {
     }
 
     pub async fn edit_tag(&self, sid: Sid, key: &str, value: &str) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["edit_tag"]);
+
         self.pool()
             .execute(
                 sqlx::query("UPDATE tags SET value = ?, edit_time = CURRENT_TIMESTAMP WHERE key = ? AND sid = ?")
@@ -333,6 +772,8 @@ impl BotDb {
     }
 
     pub async fn set_tag_uid(&self, sid: Sid, key: &str, uid: Uid) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["set_tag_uid"]);
+
         self.pool()
             .execute(
                 sqlx::query("UPDATE tags SET uid = ? WHERE key = ? AND sid = ?")
@@ -346,6 +787,8 @@ impl BotDb {
     }
 
     pub async fn set_tag_transfer_uid(&self, sid: Sid, key: &str, uid: Option<Uid>) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["set_tag_transfer_uid"]);
+
         self.pool()
             .execute(
                 sqlx::query("UPDATE tags SET transfer_uid = ? WHERE key = ? AND sid = ?")
@@ -358,8 +801,12 @@ impl BotDb {
         Ok(())
     }
 
-    pub async fn delete_tag(&self, sid: Sid, key: &str) -> Result<()> {
-        self.pool()
+    /// Returns whether a tag was deleted
+    pub async fn delete_tag(&self, sid: Sid, key: &str) -> Result<bool> {
+        let _timer = METRICS.db_query_duration.start_timer(&["delete_tag"]);
+
+        let res = self
+            .pool()
             .execute(
                 sqlx::query("DELETE FROM tags WHERE key = ? AND sid = ?")
                     .bind(key)
@@ -367,10 +814,12 @@ impl BotDb {
             )
             .await?;
 
-        Ok(())
+        Ok(res.rows_affected() > 0)
     }
 
     pub async fn count_uid_tags(&self, uid: Uid, server_id: ServerId) -> Result<i64> {
+        let _timer = METRICS.db_query_duration.start_timer(&["count_uid_tags"]);
+
         let sid = self.get_sid(server_id).await?;
 
         let (count,) = sqlx::query_as("SELECT COUNT(*) FROM tags WHERE uid = ? AND sid = ?")
@@ -383,6 +832,8 @@ impl BotDb {
     }
 
     pub async fn list_tags(&self, uid: Uid, server_id: ServerId) -> Result<Vec<String>> {
+        let _timer = METRICS.db_query_duration.start_timer(&["list_tags"]);
+
         let sid = self.get_sid(server_id).await?;
 
         #[derive(sqlx::FromRow)]
@@ -399,9 +850,211 @@ impl BotDb {
         Ok(res.into_iter().map(|t| t.key).collect())
     }
 
+    pub async fn list_server_tags(&self, sid: Sid) -> Result<Vec<Tag>> {
+        let _timer = METRICS.db_query_duration.start_timer(&["list_server_tags"]);
+
+        let res: Vec<(String, Uid, Option<Uid>, String)> = sqlx::query_as(
+            "SELECT key, uid, transfer_uid, value FROM tags WHERE sid = ? ORDER BY key",
+        )
+        .bind(sid)
+        .fetch_all(self.pool())
+        .await?;
+
+        Ok(res
+            .into_iter()
+            .map(|(key, uid, transfer_uid, value)| Tag {
+                key,
+                uid,
+                sid,
+                transfer_uid,
+                value,
+            })
+            .collect())
+    }
+
+    pub async fn delete_uid_tags(&self, uid: Uid, sid: Sid) -> Result<u64> {
+        let _timer = METRICS.db_query_duration.start_timer(&["delete_uid_tags"]);
+
+        let res = self
+            .pool()
+            .execute(
+                sqlx::query("DELETE FROM tags WHERE uid = ? AND sid = ?")
+                    .bind(uid)
+                    .bind(sid),
+            )
+            .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    // Scripts
+    pub async fn find_script(&self, server_id: ServerId, name: &str) -> Result<Option<Script>> {
+        let _timer = METRICS.db_query_duration.start_timer(&["find_script"]);
+
+        let sid = match self.find_sid(server_id).await? {
+            Some(sid) => sid,
+            None => return Ok(None),
+        };
+
+        sqlx::query_as("SELECT source, uid, transfer_uid FROM scripts WHERE name = ? AND sid = ?")
+            .bind(name)
+            .bind(sid)
+            .fetch_one(self.pool())
+            .await
+            .map(|(source, uid, transfer_uid): (String, Uid, Option<Uid>)| {
+                Some(Script {
+                    name: name.to_string(),
+                    uid,
+                    transfer_uid,
+                    source,
+                    sid,
+                })
+            })
+            .or_else(|err| match err {
+                sqlx::Error::RowNotFound => Ok(None),
+                _ => Err(err.into()),
+            })
+    }
+
+    pub async fn create_script(
+        &self,
+        uid: Uid,
+        server_id: ServerId,
+        name: &str,
+        source: &str,
+    ) -> Result<bool> {
+        let _timer = METRICS.db_query_duration.start_timer(&["create_script"]);
+
+        let sid = self.get_sid(server_id).await?;
+
+        match self
+            .pool()
+            .execute(
+                sqlx::query("INSERT INTO scripts ( name, sid, uid, source ) VALUES ( ?, ?, ?, ? )")
+                    .bind(name)
+                    .bind(sid)
+                    .bind(uid)
+                    .bind(source),
+            )
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(sqlx::Error::Database(_)) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn edit_script(&self, sid: Sid, name: &str, source: &str) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["edit_script"]);
+
+        self.pool()
+            .execute(
+                sqlx::query("UPDATE scripts SET source = ?, edit_time = CURRENT_TIMESTAMP WHERE name = ? AND sid = ?")
+                    .bind(source)
+                    .bind(name)
+                    .bind(sid),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_script_uid(&self, sid: Sid, name: &str, uid: Uid) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["set_script_uid"]);
+
+        self.pool()
+            .execute(
+                sqlx::query("UPDATE scripts SET uid = ? WHERE name = ? AND sid = ?")
+                    .bind(uid)
+                    .bind(name)
+                    .bind(sid),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_script_transfer_uid(
+        &self,
+        sid: Sid,
+        name: &str,
+        uid: Option<Uid>,
+    ) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["set_script_transfer_uid"]);
+
+        self.pool()
+            .execute(
+                sqlx::query("UPDATE scripts SET transfer_uid = ? WHERE name = ? AND sid = ?")
+                    .bind(uid)
+                    .bind(name)
+                    .bind(sid),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_script(&self, sid: Sid, name: &str) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["delete_script"]);
+
+        self.pool()
+            .execute(
+                sqlx::query("DELETE FROM scripts WHERE name = ? AND sid = ?")
+                    .bind(name)
+                    .bind(sid),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn count_uid_scripts(&self, uid: Uid, server_id: ServerId) -> Result<i64> {
+        let _timer = METRICS.db_query_duration.start_timer(&["count_uid_scripts"]);
+
+        let sid = self.get_sid(server_id).await?;
+
+        let (count,) = sqlx::query_as("SELECT COUNT(*) FROM scripts WHERE uid = ? AND sid = ?")
+            .bind(uid)
+            .bind(sid)
+            .fetch_one(self.pool())
+            .await?;
+
+        Ok(count)
+    }
+
+    pub async fn list_scripts(&self, uid: Uid, server_id: ServerId) -> Result<Vec<String>> {
+        let _timer = METRICS.db_query_duration.start_timer(&["list_scripts"]);
+
+        let sid = self.get_sid(server_id).await?;
+
+        let res: Vec<(String,)> =
+            sqlx::query_as("SELECT name FROM scripts WHERE uid = ? AND sid = ? ORDER BY name")
+                .bind(uid)
+                .bind(sid)
+                .fetch_all(self.pool())
+                .await?;
+
+        Ok(res.into_iter().map(|(name,)| name).collect())
+    }
+
+    pub async fn applied_migrations(&self) -> Result<Vec<(i64, String)>> {
+        let _timer = METRICS.db_query_duration.start_timer(&["applied_migrations"]);
+
+        Ok(
+            sqlx::query_as("SELECT version, description FROM _sqlx_migrations ORDER BY version")
+                .fetch_all(self.pool())
+                .await?,
+        )
+    }
+
     pub fn pool(&self) -> &Pool<Sqlite> {
         &self.pool
     }
+
+    /// Forget the cached lookups so changes made by another process are read again
+    pub fn clear_cache(&self) {
+        self.cache.clear();
+    }
 }
 
 #[derive(Clone)]
@@ -421,6 +1074,13 @@ impl User {
     }
 }
 
+pub struct SettingOverride {
+    // None for a server override
+    pub channel_id: Option<ChannelId>,
+    pub key: String,
+    pub value: String,
+}
+
 pub struct Tag {
     pub key: String,
     pub uid: Uid,
@@ -428,3 +1088,11 @@ pub struct Tag {
     pub transfer_uid: Option<Uid>,
     pub value: String,
 }
+
+pub struct Script {
+    pub name: String,
+    pub uid: Uid,
+    pub sid: Sid,
+    pub transfer_uid: Option<Uid>,
+    pub source: String,
+}
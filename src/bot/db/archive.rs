@@ -0,0 +1,739 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use sqlx::{sqlite::Sqlite, Transaction};
+use std::collections::{HashMap, HashSet};
+
+use super::{discord_id_from_bytes, BotDb, Sid, Uid};
+use crate::{
+    bot::ROLES,
+    metrics::METRICS,
+    services::{ServerId, UserId},
+};
+
+/// Bumped whenever the archive layout changes in a way older importers cannot read
+pub const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct DbArchive {
+    pub version: u32,
+    pub exported_at: i64,
+    /// Set when the archive only contains the data of a single server
+    pub sid: Option<Sid>,
+    pub users: Vec<ArchiveUser>,
+    pub servers: Vec<ArchiveServer>,
+    pub restrictions: Vec<ArchiveRestriction>,
+    pub server_settings: Vec<ArchiveSetting>,
+    pub channel_settings: Vec<ArchiveSetting>,
+    /// Settings users set for themselves, missing in archives exported before they were added
+    #[serde(default)]
+    pub user_settings: Vec<ArchiveUserSetting>,
+    pub tags: Vec<ArchiveTag>,
+    /// Missing in archives exported before scripts were added
+    #[serde(default)]
+    pub scripts: Vec<ArchiveScript>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ArchiveUser {
+    pub uid: Uid,
+    pub role: Option<String>,
+    pub service_id: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ArchiveServer {
+    pub sid: Sid,
+    pub service_id: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ArchiveRestriction {
+    pub uid: Uid,
+    pub restrictor_uid: Uid,
+    pub time: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ArchiveSetting {
+    /// Short service id of the server or channel, e.g. "d:1234"
+    pub id: String,
+    /// Server of a channel setting, missing for settings saved before they were linked to one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_id: Option<String>,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ArchiveUserSetting {
+    pub uid: Uid,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ArchiveTag {
+    pub key: String,
+    pub sid: Sid,
+    pub uid: Uid,
+    pub transfer_uid: Option<Uid>,
+    pub value: String,
+    pub create_time: Option<String>,
+    pub edit_time: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ArchiveScript {
+    pub name: String,
+    pub sid: Sid,
+    pub uid: Uid,
+    pub transfer_uid: Option<Uid>,
+    pub source: String,
+    pub create_time: Option<String>,
+    pub edit_time: Option<String>,
+}
+
+#[derive(Default, Debug)]
+pub struct ImportSummary {
+    pub users: u64,
+    pub servers: u64,
+    pub restrictions: u64,
+    pub settings: u64,
+    pub tags: u64,
+    pub skipped_tags: u64,
+    pub scripts: u64,
+    pub skipped_scripts: u64,
+}
+
+impl BotDb {
+    /// Export the database, or only the data belonging to `sid`, into an archive.
+    ///
+    /// Channel settings which are not linked to a server yet and the settings users set for
+    /// themselves are only part of whole-database exports.
+    pub async fn export_archive(&self, sid: Option<Sid>) -> Result<DbArchive> {
+        let _timer = METRICS.db_query_duration.start_timer(&["export_archive"]);
+
+        let servers: Vec<(Sid, Option<Vec<u8>>)> = match sid {
+            Some(sid) => {
+                sqlx::query_as("SELECT sid, discord_id FROM servers WHERE sid = ?")
+                    .bind(sid)
+                    .fetch_all(self.pool())
+                    .await?
+            }
+            None => {
+                sqlx::query_as("SELECT sid, discord_id FROM servers")
+                    .fetch_all(self.pool())
+                    .await?
+            }
+        };
+
+        if let Some(sid) = sid {
+            if servers.is_empty() {
+                return Err(anyhow!("unknown sid {}", sid));
+            }
+        }
+
+        let servers = servers
+            .into_iter()
+            .filter_map(|(sid, discord_id)| {
+                discord_id.map(|data| ArchiveServer {
+                    sid,
+                    service_id: ServerId::Discord(discord_id_from_bytes(&data)).to_str(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Tags and scripts are stored the same way
+        type OwnedRow = (
+            String,
+            Sid,
+            Uid,
+            Option<Uid>,
+            String,
+            Option<String>,
+            Option<String>,
+        );
+
+        let tags: Vec<OwnedRow> = match sid {
+            Some(sid) => {
+                sqlx::query_as("SELECT key, sid, uid, transfer_uid, value, CAST(create_time AS TEXT), CAST(edit_time AS TEXT) FROM tags WHERE sid = ?")
+                    .bind(sid)
+                    .fetch_all(self.pool())
+                    .await?
+            }
+            None => {
+                sqlx::query_as("SELECT key, sid, uid, transfer_uid, value, CAST(create_time AS TEXT), CAST(edit_time AS TEXT) FROM tags")
+                    .fetch_all(self.pool())
+                    .await?
+            }
+        };
+
+        let tags = tags
+            .into_iter()
+            .map(
+                |(key, sid, uid, transfer_uid, value, create_time, edit_time)| ArchiveTag {
+                    key,
+                    sid,
+                    uid,
+                    transfer_uid,
+                    value,
+                    create_time,
+                    edit_time,
+                },
+            )
+            .collect::<Vec<_>>();
+
+        let scripts: Vec<OwnedRow> = match sid {
+            Some(sid) => {
+                sqlx::query_as("SELECT name, sid, uid, transfer_uid, source, CAST(create_time AS TEXT), CAST(edit_time AS TEXT) FROM scripts WHERE sid = ?")
+                    .bind(sid)
+                    .fetch_all(self.pool())
+                    .await?
+            }
+            None => {
+                sqlx::query_as("SELECT name, sid, uid, transfer_uid, source, CAST(create_time AS TEXT), CAST(edit_time AS TEXT) FROM scripts")
+                    .fetch_all(self.pool())
+                    .await?
+            }
+        };
+
+        let scripts = scripts
+            .into_iter()
+            .map(
+                |(name, sid, uid, transfer_uid, source, create_time, edit_time)| ArchiveScript {
+                    name,
+                    sid,
+                    uid,
+                    transfer_uid,
+                    source,
+                    create_time,
+                    edit_time,
+                },
+            )
+            .collect::<Vec<_>>();
+
+        let restrictions: Vec<(Uid, Uid, Option<String>)> =
+            sqlx::query_as("SELECT uid, restrictor_user_id, CAST(time AS TEXT) FROM restrictions")
+                .fetch_all(self.pool())
+                .await?;
+
+        let mut restrictions = restrictions
+            .into_iter()
+            .map(|(uid, restrictor_uid, time)| ArchiveRestriction {
+                uid,
+                restrictor_uid,
+                time,
+            })
+            .collect::<Vec<_>>();
+
+        // Only keep the users which are referenced by the exported server
+        let referenced_uids = if sid.is_some() {
+            let mut uids = HashSet::new();
+
+            for tag in &tags {
+                uids.insert(tag.uid);
+
+                if let Some(transfer_uid) = tag.transfer_uid {
+                    uids.insert(transfer_uid);
+                }
+            }
+
+            for script in &scripts {
+                uids.insert(script.uid);
+
+                if let Some(transfer_uid) = script.transfer_uid {
+                    uids.insert(transfer_uid);
+                }
+            }
+
+            restrictions.retain(|restriction| uids.contains(&restriction.uid));
+
+            for restriction in &restrictions {
+                uids.insert(restriction.restrictor_uid);
+            }
+
+            Some(uids)
+        } else {
+            None
+        };
+
+        let users: Vec<(Uid, Option<String>, Option<Vec<u8>>)> =
+            sqlx::query_as("SELECT uid, role, discord_id FROM users")
+                .fetch_all(self.pool())
+                .await?;
+
+        let users = users
+            .into_iter()
+            .filter(|(uid, _, _)| {
+                referenced_uids
+                    .as_ref()
+                    .map(|uids| uids.contains(uid))
+                    .unwrap_or(true)
+            })
+            .filter_map(|(uid, role, discord_id)| {
+                discord_id.map(|data| ArchiveUser {
+                    uid,
+                    role,
+                    service_id: UserId::Discord(discord_id_from_bytes(&data)).to_str(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Short service ids of the exported servers, only used to filter single server exports
+        let server_ids = match sid {
+            Some(_) => Some(
+                servers
+                    .iter()
+                    .map(|server| {
+                        ServerId::from_str(&server.service_id).map(|id| id.to_short_str())
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+
+        let server_settings: Vec<(String, String, String)> = match &server_ids {
+            Some(server_ids) => {
+                let mut settings = Vec::new();
+
+                for server_id in server_ids {
+                    let mut rows = sqlx::query_as(
+                        "SELECT server_id, key, value FROM settings_server WHERE server_id = ?",
+                    )
+                    .bind(server_id)
+                    .fetch_all(self.pool())
+                    .await?;
+
+                    settings.append(&mut rows);
+                }
+
+                settings
+            }
+            None => {
+                sqlx::query_as("SELECT server_id, key, value FROM settings_server")
+                    .fetch_all(self.pool())
+                    .await?
+            }
+        };
+
+        let channel_settings: Vec<(String, Option<String>, String, String)> = match &server_ids {
+            Some(server_ids) => {
+                let mut settings = Vec::new();
+
+                for server_id in server_ids {
+                    let mut rows = sqlx::query_as(
+                        "SELECT channel_id, server_id, key, value FROM settings_channel WHERE server_id = ?",
+                    )
+                    .bind(server_id)
+                    .fetch_all(self.pool())
+                    .await?;
+
+                    settings.append(&mut rows);
+                }
+
+                settings
+            }
+            None => {
+                sqlx::query_as("SELECT channel_id, server_id, key, value FROM settings_channel")
+                    .fetch_all(self.pool())
+                    .await?
+            }
+        };
+
+        let user_settings: Vec<(Uid, String, String)> = match sid {
+            Some(_) => Vec::new(),
+            None => {
+                sqlx::query_as("SELECT uid, key, value FROM settings_user")
+                    .fetch_all(self.pool())
+                    .await?
+            }
+        };
+
+        Ok(DbArchive {
+            version: ARCHIVE_VERSION,
+            exported_at: Utc::now().timestamp(),
+            sid,
+            users,
+            servers,
+            restrictions,
+            server_settings: server_settings
+                .into_iter()
+                .map(|(id, key, value)| ArchiveSetting {
+                    id,
+                    server_id: None,
+                    key,
+                    value,
+                })
+                .collect(),
+            channel_settings: channel_settings
+                .into_iter()
+                .map(|(id, server_id, key, value)| ArchiveSetting {
+                    id,
+                    server_id,
+                    key,
+                    value,
+                })
+                .collect(),
+            user_settings: user_settings
+                .into_iter()
+                .map(|(uid, key, value)| ArchiveUserSetting { uid, key, value })
+                .collect(),
+            tags,
+            scripts,
+        })
+    }
+
+    /// Import an archive, remapping the archived uids and sids to the ones used by this database.
+    ///
+    /// `target_server` imports a single server archive into another server. Existing tags and
+    /// scripts are kept and the archived ones with the same name are skipped.
+    pub async fn import_archive(
+        &self,
+        archive: &DbArchive,
+        target_server: Option<ServerId>,
+    ) -> Result<ImportSummary> {
+        let _timer = METRICS.db_query_duration.start_timer(&["import_archive"]);
+
+        if archive.version > ARCHIVE_VERSION {
+            return Err(anyhow!(
+                "archive version {} is newer than the supported version {}",
+                archive.version,
+                ARCHIVE_VERSION
+            ));
+        }
+
+        if target_server.is_some() && archive.servers.len() != 1 {
+            return Err(anyhow!(
+                "a target server can only be used with an archive containing a single server"
+            ));
+        }
+
+        let mut summary = ImportSummary::default();
+        let mut tx = self.pool().begin().await?;
+
+        let mut uid_map = HashMap::new();
+        for user in &archive.users {
+            let uid = import_uid(&mut tx, UserId::from_str(&user.service_id)?).await?;
+
+            if let Some(role) = user
+                .role
+                .as_ref()
+                .filter(|role| ROLES.contains(&role.as_str()))
+            {
+                sqlx::query("UPDATE users SET role = ? WHERE uid = ?")
+                    .bind(role)
+                    .bind(uid)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            uid_map.insert(user.uid, uid);
+            summary.users += 1;
+        }
+
+        let mut sid_map = HashMap::new();
+        let mut server_id_map = HashMap::new();
+        for server in &archive.servers {
+            let archived_server_id = ServerId::from_str(&server.service_id)?;
+            let server_id = target_server.unwrap_or(archived_server_id);
+            let sid = import_sid(&mut tx, server_id).await?;
+
+            sid_map.insert(server.sid, sid);
+            server_id_map.insert(archived_server_id.to_short_str(), server_id.to_short_str());
+            summary.servers += 1;
+        }
+
+        let map_uid = |uid: Uid| {
+            uid_map
+                .get(&uid)
+                .copied()
+                .ok_or_else(|| anyhow!("archive references unknown uid {}", uid))
+        };
+
+        for restriction in &archive.restrictions {
+            let res = sqlx::query(
+                "INSERT OR IGNORE INTO restrictions ( uid, restrictor_user_id, time ) VALUES ( ?, ?, COALESCE(?, CURRENT_TIMESTAMP) )",
+            )
+            .bind(map_uid(restriction.uid)?)
+            .bind(map_uid(restriction.restrictor_uid)?)
+            .bind(&restriction.time)
+            .execute(&mut *tx)
+            .await?;
+
+            if res.rows_affected() > 0 {
+                summary.restrictions += 1;
+            }
+        }
+
+        for setting in &archive.server_settings {
+            let server_id = server_id_map.get(&setting.id).unwrap_or(&setting.id);
+
+            sqlx::query("REPLACE INTO settings_server ( server_id, key, value ) VALUES ( ?, ?, ? )")
+                .bind(server_id)
+                .bind(&setting.key)
+                .bind(&setting.value)
+                .execute(&mut *tx)
+                .await?;
+
+            summary.settings += 1;
+        }
+
+        for setting in &archive.channel_settings {
+            let server_id = setting
+                .server_id
+                .as_ref()
+                .map(|server_id| server_id_map.get(server_id).unwrap_or(server_id));
+
+            sqlx::query(
+                "REPLACE INTO settings_channel ( channel_id, key, value, server_id ) VALUES ( ?, ?, ?, ? )",
+            )
+            .bind(&setting.id)
+            .bind(&setting.key)
+            .bind(&setting.value)
+            .bind(server_id)
+            .execute(&mut *tx)
+            .await?;
+
+            summary.settings += 1;
+        }
+
+        for setting in &archive.user_settings {
+            sqlx::query("REPLACE INTO settings_user ( uid, key, value ) VALUES ( ?, ?, ? )")
+                .bind(map_uid(setting.uid)?)
+                .bind(&setting.key)
+                .bind(&setting.value)
+                .execute(&mut *tx)
+                .await?;
+
+            summary.settings += 1;
+        }
+
+        for tag in &archive.tags {
+            let sid = sid_map
+                .get(&tag.sid)
+                .copied()
+                .ok_or_else(|| anyhow!("archive references unknown sid {}", tag.sid))?;
+            let transfer_uid = match tag.transfer_uid {
+                Some(uid) => Some(map_uid(uid)?),
+                None => None,
+            };
+
+            let res = sqlx::query(
+                "INSERT OR IGNORE INTO tags ( key, sid, uid, transfer_uid, value, create_time, edit_time ) VALUES ( ?, ?, ?, ?, ?, COALESCE(?, CURRENT_TIMESTAMP), COALESCE(?, CURRENT_TIMESTAMP) )",
+            )
+            .bind(&tag.key)
+            .bind(sid)
+            .bind(map_uid(tag.uid)?)
+            .bind(transfer_uid)
+            .bind(&tag.value)
+            .bind(&tag.create_time)
+            .bind(&tag.edit_time)
+            .execute(&mut *tx)
+            .await?;
+
+            if res.rows_affected() > 0 {
+                summary.tags += 1;
+            } else {
+                summary.skipped_tags += 1;
+            }
+        }
+
+        for script in &archive.scripts {
+            let sid = sid_map
+                .get(&script.sid)
+                .copied()
+                .ok_or_else(|| anyhow!("archive references unknown sid {}", script.sid))?;
+            let transfer_uid = match script.transfer_uid {
+                Some(uid) => Some(map_uid(uid)?),
+                None => None,
+            };
+
+            let res = sqlx::query(
+                "INSERT OR IGNORE INTO scripts ( name, sid, uid, transfer_uid, source, create_time, edit_time ) VALUES ( ?, ?, ?, ?, ?, COALESCE(?, CURRENT_TIMESTAMP), COALESCE(?, CURRENT_TIMESTAMP) )",
+            )
+            .bind(&script.name)
+            .bind(sid)
+            .bind(map_uid(script.uid)?)
+            .bind(transfer_uid)
+            .bind(&script.source)
+            .bind(&script.create_time)
+            .bind(&script.edit_time)
+            .execute(&mut *tx)
+            .await?;
+
+            if res.rows_affected() > 0 {
+                summary.scripts += 1;
+            } else {
+                summary.skipped_scripts += 1;
+            }
+        }
+
+        tx.commit().await?;
+
+        // The import wrote to the tables directly
+        self.cache.clear();
+
+        Ok(summary)
+    }
+}
+
+async fn import_uid(tx: &mut Transaction<'_, Sqlite>, user_id: UserId) -> Result<Uid> {
+    let existing: Option<(Uid,)> = match user_id {
+        UserId::Discord(discord_id) => {
+            sqlx::query_as("SELECT uid FROM users WHERE discord_id = ?")
+                .bind(discord_id.to_le_bytes().to_vec())
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+    };
+
+    if let Some((uid,)) = existing {
+        return Ok(uid);
+    }
+
+    let res = match user_id {
+        UserId::Discord(discord_id) => {
+            sqlx::query("INSERT INTO users ( discord_id ) VALUES ( ? )")
+                .bind(discord_id.to_le_bytes().to_vec())
+                .execute(&mut **tx)
+                .await?
+        }
+    };
+
+    Ok(res.last_insert_rowid())
+}
+
+async fn import_sid(tx: &mut Transaction<'_, Sqlite>, server_id: ServerId) -> Result<Sid> {
+    let existing: Option<(Sid,)> = match server_id {
+        ServerId::Discord(discord_id) => {
+            sqlx::query_as("SELECT sid FROM servers WHERE discord_id = ?")
+                .bind(discord_id.to_le_bytes().to_vec())
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+    };
+
+    if let Some((sid,)) = existing {
+        return Ok(sid);
+    }
+
+    let res = match server_id {
+        ServerId::Discord(discord_id) => {
+            sqlx::query("INSERT INTO servers ( discord_id ) VALUES ( ? )")
+                .bind(discord_id.to_le_bytes().to_vec())
+                .execute(&mut **tx)
+                .await?
+        }
+    };
+
+    Ok(res.last_insert_rowid())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bot::db::BotDb,
+        services::{ServerId, UserId},
+    };
+
+    #[tokio::test]
+    async fn import_remap_test() {
+        let source = BotDb::in_memory().await.unwrap();
+        let server_id = ServerId::from_str("discord:10").unwrap();
+        let other_server_id = ServerId::from_str("discord:20").unwrap();
+
+        let alice = source
+            .get_user_from_service_user_id(UserId::from_str("discord:1").unwrap())
+            .await
+            .unwrap()
+            .uid;
+        let bob = source
+            .get_user_from_service_user_id(UserId::from_str("discord:2").unwrap())
+            .await
+            .unwrap()
+            .uid;
+        let sid = source.get_sid(server_id).await.unwrap();
+        source
+            .create_tag(alice, server_id, "hello", "world")
+            .await
+            .unwrap();
+        source.restrict_user(bob, alice).await.unwrap();
+        source
+            .save_user_setting(alice, "lua/prefix", "!")
+            .await
+            .unwrap();
+
+        let archive = source.export_archive(None).await.unwrap();
+
+        // Take the uids and sids of the source so the import has to remap them
+        let target = BotDb::in_memory().await.unwrap();
+        target
+            .get_user_from_service_user_id(UserId::from_str("discord:3").unwrap())
+            .await
+            .unwrap();
+        target
+            .get_user_from_service_user_id(UserId::from_str("discord:2").unwrap())
+            .await
+            .unwrap();
+        target.get_sid(other_server_id).await.unwrap();
+
+        let summary = target.import_archive(&archive, None).await.unwrap();
+        assert_eq!(summary.users, 2);
+        assert_eq!(summary.servers, 1);
+        assert_eq!(summary.tags, 1);
+        assert_eq!(summary.restrictions, 1);
+
+        let target_alice = target.find_user("discord:1").await.unwrap().uid;
+        let target_bob = target.find_user("discord:2").await.unwrap().uid;
+        assert_ne!(target_alice, alice);
+        assert_ne!(target_bob, bob);
+
+        let target_sid = target.find_sid(server_id).await.unwrap();
+        assert!(target_sid.is_some());
+        assert_ne!(target_sid, Some(sid));
+
+        let tag = target.find_tag(server_id, "hello").await.unwrap().unwrap();
+        assert_eq!(tag.uid, target_alice);
+        assert_eq!(Some(tag.sid), target_sid);
+        assert_eq!(tag.value, "world");
+
+        assert!(target.is_restricted(target_bob).await.unwrap());
+        assert!(!target.is_restricted(target_alice).await.unwrap());
+        assert_eq!(
+            target
+                .get_user_setting(target_alice, "lua/prefix")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("!")
+        );
+
+        // Importing the same archive again skips what is already there
+        let summary = target.import_archive(&archive, None).await.unwrap();
+        assert_eq!(summary.restrictions, 0);
+        assert_eq!(summary.tags, 0);
+        assert_eq!(summary.skipped_tags, 1);
+
+        // A single server imported into another server keeps the existing tags
+        target
+            .create_tag(target_bob, other_server_id, "hello", "kept")
+            .await
+            .unwrap();
+
+        let archive = source.export_archive(Some(sid)).await.unwrap();
+        let summary = target
+            .import_archive(&archive, Some(other_server_id))
+            .await
+            .unwrap();
+        assert_eq!(summary.tags, 0);
+        assert_eq!(summary.skipped_tags, 1);
+
+        let tag = target
+            .find_tag(other_server_id, "hello")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tag.uid, target_bob);
+        assert_eq!(tag.value, "kept");
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{Sid, Uid, User};
+use crate::{
+    metrics::METRICS,
+    services::{ChannelId, ServerId, UserId},
+};
+
+// A map is cleared completely once it grows past this, which is simpler than tracking usage
+const MAX_ENTRIES: usize = 50_000;
+// Entries are read again after this, so changes made by another process like the cli are picked up
+const MAX_AGE: Duration = Duration::from_secs(60);
+
+pub struct CacheMap<K, V> {
+    name: &'static str,
+    map: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Hash + Eq, V: Clone> CacheMap<K, V> {
+    fn new(name: &'static str) -> CacheMap<K, V> {
+        CacheMap {
+            name,
+            map: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self
+            .map
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|(inserted, _)| inserted.elapsed() < MAX_AGE)
+            .map(|(_, value)| value.clone());
+
+        METRICS.db_cache_lookups.inc(&[
+            self.name,
+            if value.is_some() { "hit" } else { "miss" },
+        ]);
+
+        value
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let mut map = self.map.lock().unwrap();
+
+        if map.len() >= MAX_ENTRIES && !map.contains_key(&key) {
+            map.clear();
+        }
+
+        map.insert(key, (Instant::now(), value));
+    }
+
+    pub fn remove(&self, key: &K) {
+        self.map.lock().unwrap().remove(key);
+    }
+
+    fn clear(&self) {
+        self.map.lock().unwrap().clear();
+    }
+}
+
+/// Write-through cache for the lookups done for every message
+///
+/// Only writes done through the same `BotDb` update the cache, changes made by another process
+/// like the cli are seen by a running bot once the entries are older than `MAX_AGE` or the cache
+/// is cleared on a config reload.
+pub struct DbCache {
+    pub users: CacheMap<Uid, User>,
+    pub service_users: CacheMap<UserId, Uid>,
+    pub restrictions: CacheMap<Uid, bool>,
+    pub sids: CacheMap<ServerId, Sid>,
+    // Missing settings are cached as None, most lookups are for settings that were never set
+    pub server_settings: CacheMap<(ServerId, String), Option<String>>,
+    pub channel_settings: CacheMap<(ChannelId, String), Option<String>>,
+    pub user_settings: CacheMap<(Uid, String), Option<String>>,
+}
+
+impl DbCache {
+    pub fn new() -> DbCache {
+        DbCache {
+            users: CacheMap::new("users"),
+            service_users: CacheMap::new("service_users"),
+            restrictions: CacheMap::new("restrictions"),
+            sids: CacheMap::new("sids"),
+            server_settings: CacheMap::new("server_settings"),
+            channel_settings: CacheMap::new("channel_settings"),
+            user_settings: CacheMap::new("user_settings"),
+        }
+    }
+
+    /// Drop everything, used after writes that bypass the cached methods like archive imports
+    pub fn clear(&self) {
+        self.users.clear();
+        self.service_users.clear();
+        self.restrictions.clear();
+        self.sids.clear();
+        self.server_settings.clear();
+        self.channel_settings.clear();
+        self.user_settings.clear();
+    }
+}
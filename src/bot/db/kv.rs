@@ -0,0 +1,364 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use sqlx::Executor;
+use std::{path::Path, time::Duration};
+
+use super::BotDb;
+use crate::metrics::METRICS;
+
+/// Namespace of the values imported from the `<key>.txt` files once written by `bot.set_data`
+pub const LEGACY_DATA_NAMESPACE: &str = "data";
+
+fn now() -> i64 {
+    Utc::now().timestamp()
+}
+
+fn expire_time(ttl: Option<Duration>) -> Option<i64> {
+    ttl.map(|ttl| now().saturating_add(ttl.as_secs().max(1) as i64))
+}
+
+impl BotDb {
+    pub async fn kv_get(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+        let _timer = METRICS.db_query_duration.start_timer(&["kv_get"]);
+
+        let res: Option<(String,)> = sqlx::query_as(
+            "SELECT value FROM kv_store WHERE namespace = ? AND key = ? \
+             AND ( expire_time IS NULL OR expire_time > ? )",
+        )
+        .bind(namespace)
+        .bind(key)
+        .bind(now())
+        .fetch_optional(self.pool())
+        .await?;
+
+        Ok(res.map(|(value,)| value))
+    }
+
+    pub async fn kv_set(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        let _timer = METRICS.db_query_duration.start_timer(&["kv_set"]);
+
+        self.pool()
+            .execute(
+                sqlx::query(
+                    "INSERT INTO kv_store ( namespace, key, value, expire_time ) VALUES ( ?, ?, ?, ? ) \
+                     ON CONFLICT ( namespace, key ) DO UPDATE SET value = excluded.value, \
+                     expire_time = excluded.expire_time, edit_time = CURRENT_TIMESTAMP",
+                )
+                .bind(namespace)
+                .bind(key)
+                .bind(value)
+                .bind(expire_time(ttl)),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Delete a key, returns if it existed
+    pub async fn kv_delete(&self, namespace: &str, key: &str) -> Result<bool> {
+        let _timer = METRICS.db_query_duration.start_timer(&["kv_delete"]);
+
+        let res = self
+            .pool()
+            .execute(
+                sqlx::query(
+                    "DELETE FROM kv_store WHERE namespace = ? AND key = ? \
+                     AND ( expire_time IS NULL OR expire_time > ? )",
+                )
+                .bind(namespace)
+                .bind(key)
+                .bind(now()),
+            )
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Atomically add to an integer value, a missing or expired key starts from 0
+    ///
+    /// The TTL is only applied when the key is created.
+    pub async fn kv_incr(
+        &self,
+        namespace: &str,
+        key: &str,
+        by: i64,
+        ttl: Option<Duration>,
+    ) -> Result<i64> {
+        let _timer = METRICS.db_query_duration.start_timer(&["kv_incr"]);
+
+        let now = now();
+
+        // Values that are not integers become NULL and are rejected by the NOT NULL constraint
+        let res: Result<(i64,), _> = sqlx::query_as(
+            "INSERT INTO kv_store ( namespace, key, value, expire_time ) VALUES ( ?, ?, ?, ? ) \
+             ON CONFLICT ( namespace, key ) DO UPDATE SET \
+             value = CASE \
+                 WHEN expire_time IS NOT NULL AND expire_time <= ? THEN excluded.value \
+                 WHEN json_type(value) = 'integer' THEN CAST(value AS INTEGER) + ? \
+                 ELSE NULL END, \
+             expire_time = CASE \
+                 WHEN expire_time IS NOT NULL AND expire_time <= ? THEN excluded.expire_time \
+                 ELSE expire_time END, \
+             edit_time = CURRENT_TIMESTAMP \
+             RETURNING CAST(value AS INTEGER)",
+        )
+        .bind(namespace)
+        .bind(key)
+        .bind(by.to_string())
+        .bind(expire_time(ttl))
+        .bind(now)
+        .bind(by)
+        .bind(now)
+        .fetch_one(self.pool())
+        .await;
+
+        match res {
+            Ok((value,)) => Ok(value),
+            Err(sqlx::Error::Database(_)) => {
+                Err(anyhow!("the value of \"{}\" is not an integer", key))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replace a value only if it currently is `expected`, `None` meaning a missing or expired key
+    ///
+    /// Setting `None` deletes the key. Returns if the value was replaced.
+    pub async fn kv_compare_and_set(
+        &self,
+        namespace: &str,
+        key: &str,
+        expected: Option<&str>,
+        value: Option<&str>,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        let _timer = METRICS
+            .db_query_duration
+            .start_timer(&["kv_compare_and_set"]);
+
+        let now = now();
+
+        let query = match (expected, value) {
+            (None, None) => return Ok(self.kv_get(namespace, key).await?.is_none()),
+            (None, Some(value)) => sqlx::query(
+                "INSERT INTO kv_store ( namespace, key, value, expire_time ) VALUES ( ?, ?, ?, ? ) \
+                 ON CONFLICT ( namespace, key ) DO UPDATE SET value = excluded.value, \
+                 expire_time = excluded.expire_time, edit_time = CURRENT_TIMESTAMP \
+                 WHERE expire_time IS NOT NULL AND expire_time <= ?",
+            )
+            .bind(namespace)
+            .bind(key)
+            .bind(value)
+            .bind(expire_time(ttl))
+            .bind(now),
+            (Some(expected), Some(value)) => sqlx::query(
+                "UPDATE kv_store SET value = ?, expire_time = ?, edit_time = CURRENT_TIMESTAMP \
+                 WHERE namespace = ? AND key = ? AND value = ? \
+                 AND ( expire_time IS NULL OR expire_time > ? )",
+            )
+            .bind(value)
+            .bind(expire_time(ttl))
+            .bind(namespace)
+            .bind(key)
+            .bind(expected)
+            .bind(now),
+            (Some(expected), None) => sqlx::query(
+                "DELETE FROM kv_store WHERE namespace = ? AND key = ? AND value = ? \
+                 AND ( expire_time IS NULL OR expire_time > ? )",
+            )
+            .bind(namespace)
+            .bind(key)
+            .bind(expected)
+            .bind(now),
+        };
+
+        Ok(self.pool().execute(query).await?.rows_affected() > 0)
+    }
+
+    /// The keys of a namespace starting with `prefix`, in order
+    pub async fn kv_list(&self, namespace: &str, prefix: &str) -> Result<Vec<String>> {
+        let _timer = METRICS.db_query_duration.start_timer(&["kv_list"]);
+
+        let res: Vec<(String,)> = sqlx::query_as(
+            "SELECT key FROM kv_store WHERE namespace = ? AND SUBSTR(key, 1, ?) = ? \
+             AND ( expire_time IS NULL OR expire_time > ? ) ORDER BY key",
+        )
+        .bind(namespace)
+        .bind(prefix.chars().count() as i64)
+        .bind(prefix)
+        .bind(now())
+        .fetch_all(self.pool())
+        .await?;
+
+        Ok(res.into_iter().map(|(key,)| key).collect())
+    }
+
+    pub async fn kv_purge_expired(&self) -> Result<u64> {
+        let _timer = METRICS.db_query_duration.start_timer(&["kv_purge_expired"]);
+
+        let res = self
+            .pool()
+            .execute(
+                sqlx::query(
+                    "DELETE FROM kv_store WHERE expire_time IS NOT NULL AND expire_time <= ?",
+                )
+                .bind(now()),
+            )
+            .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Move the `<key>.txt` files written by the old `bot.set_data` into the store
+    ///
+    /// Files holding JSON are stored as is, anything else as a string. The files are renamed to
+    /// `<key>.txt.migrated` so they are only imported once.
+    pub async fn import_data_files(&self, data_path: &Path) -> Result<usize> {
+        let mut imported = 0;
+
+        let mut entries = tokio::fs::read_dir(data_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+
+            let key = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => match name.strip_suffix(".txt") {
+                    Some(key)
+                        if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric()) =>
+                    {
+                        key.to_string()
+                    }
+                    _ => continue,
+                },
+                None => continue,
+            };
+
+            let data = String::from_utf8_lossy(&tokio::fs::read(&path).await?).into_owned();
+            let value = match serde_json::from_str::<serde_json::Value>(&data) {
+                Ok(value) => value,
+                Err(_) => serde_json::Value::String(data),
+            };
+
+            // Values already in the store win over the files
+            if self.kv_get(LEGACY_DATA_NAMESPACE, &key).await?.is_none() {
+                self.kv_set(LEGACY_DATA_NAMESPACE, &key, &value.to_string(), None)
+                    .await?;
+            }
+
+            tokio::fs::rename(&path, path.with_extension("txt.migrated")).await?;
+            imported += 1;
+        }
+
+        Ok(imported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Executor;
+    use std::time::Duration;
+
+    use crate::bot::db::BotDb;
+
+    #[tokio::test]
+    async fn kv_incr_test() {
+        let db = BotDb::in_memory().await.unwrap();
+
+        assert_eq!(db.kv_incr("test", "counter", 2, None).await.unwrap(), 2);
+        assert_eq!(db.kv_incr("test", "counter", -5, None).await.unwrap(), -3);
+        assert_eq!(
+            db.kv_get("test", "counter").await.unwrap().as_deref(),
+            Some("-3")
+        );
+
+        // Expired keys start from 0 again and get the new TTL
+        db.kv_incr("test", "expiring", 10, Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        db.pool()
+            .execute("UPDATE kv_store SET expire_time = 0 WHERE key = 'expiring'")
+            .await
+            .unwrap();
+        assert_eq!(db.kv_incr("test", "expiring", 1, None).await.unwrap(), 1);
+
+        db.kv_set("test", "text", "\"a\"", None).await.unwrap();
+        assert!(db.kv_incr("test", "text", 1, None).await.is_err());
+        assert_eq!(
+            db.kv_get("test", "text").await.unwrap().as_deref(),
+            Some("\"a\"")
+        );
+
+        assert_eq!(db.kv_incr("other", "counter", 1, None).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn kv_compare_and_set_test() {
+        let db = BotDb::in_memory().await.unwrap();
+
+        assert!(db
+            .kv_compare_and_set("test", "key", None, None, None)
+            .await
+            .unwrap());
+        assert!(db
+            .kv_compare_and_set("test", "key", None, Some("1"), None)
+            .await
+            .unwrap());
+        assert!(!db
+            .kv_compare_and_set("test", "key", None, Some("2"), None)
+            .await
+            .unwrap());
+        assert!(!db
+            .kv_compare_and_set("test", "key", None, None, None)
+            .await
+            .unwrap());
+
+        assert!(!db
+            .kv_compare_and_set("test", "key", Some("2"), Some("3"), None)
+            .await
+            .unwrap());
+        assert!(db
+            .kv_compare_and_set("test", "key", Some("1"), Some("3"), None)
+            .await
+            .unwrap());
+        assert_eq!(
+            db.kv_get("test", "key").await.unwrap().as_deref(),
+            Some("3")
+        );
+
+        assert!(!db
+            .kv_compare_and_set("test", "key", Some("1"), None, None)
+            .await
+            .unwrap());
+        assert!(db
+            .kv_compare_and_set("test", "key", Some("3"), None, None)
+            .await
+            .unwrap());
+        assert_eq!(db.kv_get("test", "key").await.unwrap(), None);
+
+        // An expired value counts as missing
+        db.kv_set("test", "expired", "1", Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        db.pool()
+            .execute("UPDATE kv_store SET expire_time = 0 WHERE key = 'expired'")
+            .await
+            .unwrap();
+        assert!(!db
+            .kv_compare_and_set("test", "expired", Some("1"), Some("2"), None)
+            .await
+            .unwrap());
+        assert!(db
+            .kv_compare_and_set("test", "expired", None, Some("2"), None)
+            .await
+            .unwrap());
+        assert_eq!(
+            db.kv_get("test", "expired").await.unwrap().as_deref(),
+            Some("2")
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::Result;
+use sqlx::Executor;
+
+use super::{BotDb, Uid};
+use crate::{metrics::METRICS, services::ServerId};
+
+/// The namespace of a sandbox storage entry
+#[derive(Clone, Copy, Debug)]
+pub enum StorageScope {
+    User(Uid),
+    Server(ServerId),
+}
+
+impl BotDb {
+    async fn storage_owner(&self, scope: StorageScope) -> Result<(&'static str, i64)> {
+        Ok(match scope {
+            StorageScope::User(uid) => ("user", uid),
+            StorageScope::Server(server_id) => ("server", self.get_sid(server_id).await?),
+        })
+    }
+
+    pub async fn storage_get(&self, scope: StorageScope, key: &str) -> Result<Option<String>> {
+        let _timer = METRICS.db_query_duration.start_timer(&["storage_get"]);
+
+        let (scope, owner) = self.storage_owner(scope).await?;
+
+        let res: Option<(String,)> = sqlx::query_as(
+            "SELECT value FROM sandbox_storage WHERE scope = ? AND owner = ? AND key = ?",
+        )
+        .bind(scope)
+        .bind(owner)
+        .bind(key)
+        .fetch_optional(self.pool())
+        .await?;
+
+        Ok(res.map(|(value,)| value))
+    }
+
+    /// Set an entry, returns false without writing if the namespace would grow past `max_size` bytes
+    pub async fn storage_set(
+        &self,
+        scope: StorageScope,
+        key: &str,
+        value: &str,
+        max_size: usize,
+    ) -> Result<bool> {
+        let _timer = METRICS.db_query_duration.start_timer(&["storage_set"]);
+
+        let (scope, owner) = self.storage_owner(scope).await?;
+
+        let mut tx = self.pool().begin().await?;
+
+        let (used,): (i64,) = sqlx::query_as(
+            "SELECT COALESCE(SUM(LENGTH(CAST(key AS BLOB)) + LENGTH(CAST(value AS BLOB))), 0) \
+             FROM sandbox_storage WHERE scope = ? AND owner = ? AND key != ?",
+        )
+        .bind(scope)
+        .bind(owner)
+        .bind(key)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if used as usize + key.len() + value.len() > max_size {
+            return Ok(false);
+        }
+
+        sqlx::query(
+            "INSERT INTO sandbox_storage ( scope, owner, key, value ) VALUES ( ?, ?, ?, ? ) \
+             ON CONFLICT ( scope, owner, key ) \
+             DO UPDATE SET value = excluded.value, edit_time = CURRENT_TIMESTAMP",
+        )
+        .bind(scope)
+        .bind(owner)
+        .bind(key)
+        .bind(value)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Delete an entry, returns if it existed
+    pub async fn storage_delete(&self, scope: StorageScope, key: &str) -> Result<bool> {
+        let _timer = METRICS.db_query_duration.start_timer(&["storage_delete"]);
+
+        let (scope, owner) = self.storage_owner(scope).await?;
+
+        let res = self
+            .pool()
+            .execute(
+                sqlx::query(
+                    "DELETE FROM sandbox_storage WHERE scope = ? AND owner = ? AND key = ?",
+                )
+                .bind(scope)
+                .bind(owner)
+                .bind(key),
+            )
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// The keys of a namespace starting with `prefix`, in order
+    pub async fn storage_list(&self, scope: StorageScope, prefix: &str) -> Result<Vec<String>> {
+        let _timer = METRICS.db_query_duration.start_timer(&["storage_list"]);
+
+        let (scope, owner) = self.storage_owner(scope).await?;
+
+        let res: Vec<(String,)> = sqlx::query_as(
+            "SELECT key FROM sandbox_storage \
+             WHERE scope = ? AND owner = ? AND SUBSTR(key, 1, ?) = ? ORDER BY key",
+        )
+        .bind(scope)
+        .bind(owner)
+        .bind(prefix.chars().count() as i64)
+        .bind(prefix)
+        .fetch_all(self.pool())
+        .await?;
+
+        Ok(res.into_iter().map(|(key,)| key).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StorageScope;
+    use crate::{bot::db::BotDb, services::ServerId};
+
+    #[tokio::test]
+    async fn storage_set_size_test() {
+        let db = BotDb::in_memory().await.unwrap();
+        let user = StorageScope::User(1);
+
+        // Both keys and values count towards the size, in bytes
+        assert!(db.storage_set(user, "a", "12345", 20).await.unwrap());
+        assert!(!db
+            .storage_set(user, "b", "12345678901234", 20)
+            .await
+            .unwrap());
+        assert!(db
+            .storage_set(user, "b", "1234567890123", 20)
+            .await
+            .unwrap());
+        assert!(!db.storage_set(user, "c", "", 20).await.unwrap());
+        assert_eq!(db.storage_get(user, "c").await.unwrap(), None);
+
+        // Replacing a value only counts the new value
+        assert!(db.storage_delete(user, "b").await.unwrap());
+        assert!(db
+            .storage_set(user, "a", "1234567890123456789", 20)
+            .await
+            .unwrap());
+        assert!(!db
+            .storage_set(user, "a", "12345678901234567890", 20)
+            .await
+            .unwrap());
+        assert!(db.storage_set(user, "a", "ééééééééé", 20).await.unwrap());
+        assert!(!db.storage_set(user, "a", "éééééééééé", 20).await.unwrap());
+
+        // Every user and server has their own namespace
+        let server = StorageScope::Server(ServerId::from_str("discord:1").unwrap());
+        assert!(db
+            .storage_set(StorageScope::User(2), "a", "1234567890123456789", 20)
+            .await
+            .unwrap());
+        assert!(db
+            .storage_set(server, "a", "1234567890123456789", 20)
+            .await
+            .unwrap());
+        assert_eq!(
+            db.storage_list(user, "").await.unwrap(),
+            vec!["a".to_string()]
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use anyhow::{anyhow, Result};
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    bot::db::{archive::DbArchive, BotDb, Sid, Uid},
+    modules::ModuleRegistry,
+    services::{ChannelId, ServerId},
+    settings::SettingContext,
+};
+
+pub const USAGE: &str = "usage: kaito [COMMAND]
+
+USER is either a uid or a service id like \"discord:<id>\", SERVER is either a sid or a
+service id like \"discord:<id>\".
+
+COMMANDS:
+   run                                          Start the bot (default)
+   migrate                                      Run the database migrations and list them
+   role <USER> [ROLE]                           Show or set the role of a user
+   restrict <USER> --by <USER>                  Restrict a user
+   unrestrict <USER>                            Unrestrict a user
+   tags list <SERVER> [--user USER]             List the tags in a server
+   tags delete <SERVER> [KEY]... [--user USER]  Delete tags, or every tag owned by a user
+   setting get <MODULE/SETTING> (--server ID | --channel ID | --uid UID)
+                                                Show a setting override
+   setting set <MODULE/SETTING> <VALUE> (--server ID | --channel ID | --uid UID)
+                                                Set a setting override
+   setting unset <MODULE/SETTING> (--server ID | --channel ID | --uid UID)
+                                                Remove a setting override
+   export <FILE> [--sid SID]                    Export the database, or a single server, to a JSON archive
+   import <FILE> [--server ID]                  Import a JSON archive, optionally into another server
+   help                                         Show this message";
+
+pub enum Command {
+    Run,
+    Help,
+    Migrate,
+    Role {
+        user: String,
+        role: Option<String>,
+    },
+    Restrict {
+        user: String,
+        restrictor: String,
+    },
+    Unrestrict {
+        user: String,
+    },
+    ListTags {
+        server: String,
+        user: Option<String>,
+    },
+    DeleteTags {
+        server: String,
+        keys: Vec<String>,
+        user: Option<String>,
+    },
+    GetSetting {
+        key: String,
+        ctx: SettingContext,
+    },
+    SetSetting {
+        key: String,
+        value: String,
+        ctx: SettingContext,
+    },
+    UnsetSetting {
+        key: String,
+        ctx: SettingContext,
+    },
+    Export {
+        path: PathBuf,
+        sid: Option<Sid>,
+    },
+    Import {
+        path: PathBuf,
+        server: Option<ServerId>,
+    },
+}
+
+impl Command {
+    pub fn parse(args: &[String]) -> Result<Command> {
+        let mut args = args.to_vec();
+
+        if args.is_empty() {
+            return Ok(Command::Run);
+        }
+
+        let command = args.remove(0);
+
+        let command = match command.as_str() {
+            "run" => Command::Run,
+            "help" | "--help" | "-h" => Command::Help,
+            "migrate" => Command::Migrate,
+            "role" => Command::Role {
+                user: take_positional(&mut args, "USER")?,
+                role: take_positional(&mut args, "ROLE").ok(),
+            },
+            "restrict" => Command::Restrict {
+                restrictor: take_option(&mut args, "--by")?
+                    .ok_or_else(|| anyhow!("missing option --by\n\n{}", USAGE))?,
+                user: take_positional(&mut args, "USER")?,
+            },
+            "unrestrict" => Command::Unrestrict {
+                user: take_positional(&mut args, "USER")?,
+            },
+            "tags" => {
+                let user = take_option(&mut args, "--user")?;
+                let subcommand = take_positional(&mut args, "SUBCOMMAND")?;
+                let server = take_positional(&mut args, "SERVER")?;
+
+                match subcommand.as_str() {
+                    "list" => Command::ListTags { server, user },
+                    "delete" => {
+                        let keys = args.drain(..).collect::<Vec<_>>();
+
+                        if keys.is_empty() && user.is_none() {
+                            return Err(anyhow!("expected tag keys or --user\n\n{}", USAGE));
+                        }
+
+                        Command::DeleteTags { server, keys, user }
+                    }
+                    _ => return Err(anyhow!("unknown subcommand \"tags {}\"", subcommand)),
+                }
+            }
+            "setting" => {
+                let ctx = take_setting_context(&mut args)?;
+                let subcommand = take_positional(&mut args, "SUBCOMMAND")?;
+                let key = take_positional(&mut args, "MODULE/SETTING")?;
+
+                if !key.contains('/') {
+                    return Err(anyhow!("expected a setting key like \"lua/prefix\""));
+                }
+
+                match subcommand.as_str() {
+                    "get" => Command::GetSetting { key, ctx },
+                    "set" => Command::SetSetting {
+                        key,
+                        value: take_positional(&mut args, "VALUE")?,
+                        ctx,
+                    },
+                    "unset" => Command::UnsetSetting { key, ctx },
+                    _ => return Err(anyhow!("unknown subcommand \"setting {}\"", subcommand)),
+                }
+            }
+            "export" => {
+                let sid = take_option(&mut args, "--sid")?
+                    .map(|sid| {
+                        sid.parse::<Sid>()
+                            .map_err(|_| anyhow!("invalid sid \"{}\"", sid))
+                    })
+                    .transpose()?;
+
+                Command::Export {
+                    path: take_positional(&mut args, "FILE")?.into(),
+                    sid,
+                }
+            }
+            "import" => {
+                let server = take_option(&mut args, "--server")?
+                    .map(|server| ServerId::from_str(&server))
+                    .transpose()?;
+
+                Command::Import {
+                    path: take_positional(&mut args, "FILE")?.into(),
+                    server,
+                }
+            }
+            _ => return Err(anyhow!("unknown command \"{}\"\n\n{}", command, USAGE)),
+        };
+
+        if let Some(arg) = args.first() {
+            return Err(anyhow!("unexpected argument \"{}\"\n\n{}", arg, USAGE));
+        }
+
+        Ok(command)
+    }
+
+    /// Whether the command needs the database
+    pub fn needs_db(&self) -> bool {
+        !matches!(self, Command::Run | Command::Help)
+    }
+
+    // Whether the command changes users, restrictions or settings, which a running bot caches
+    fn changes_cached_data(&self) -> bool {
+        matches!(
+            self,
+            Command::Role { role: Some(_), .. }
+                | Command::Restrict { .. }
+                | Command::Unrestrict { .. }
+                | Command::SetSetting { .. }
+                | Command::UnsetSetting { .. }
+                | Command::Import { .. }
+        )
+    }
+}
+
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(idx) => {
+            if idx + 1 >= args.len() {
+                return Err(anyhow!("expected value for \"{}\"", name));
+            }
+
+            let value = args.remove(idx + 1);
+            args.remove(idx);
+
+            Ok(Some(value))
+        }
+        None => Ok(None),
+    }
+}
+
+fn take_positional(args: &mut Vec<String>, name: &str) -> Result<String> {
+    match args.iter().position(|arg| !arg.starts_with("--")) {
+        Some(idx) => Ok(args.remove(idx)),
+        None => Err(anyhow!("missing argument {}\n\n{}", name, USAGE)),
+    }
+}
+
+fn take_setting_context(args: &mut Vec<String>) -> Result<SettingContext> {
+    match (
+        take_option(args, "--server")?,
+        take_option(args, "--channel")?,
+        take_option(args, "--uid")?,
+    ) {
+        (Some(server), None, None) => Ok(SettingContext::Server(ServerId::from_str(&server)?)),
+        (None, Some(channel), None) => Ok(SettingContext::Channel(ChannelId::from_str(&channel)?)),
+        (None, None, Some(uid)) => Ok(SettingContext::User(
+            uid.parse::<Uid>()
+                .map_err(|_| anyhow!("invalid uid \"{}\"", uid))?,
+        )),
+        _ => Err(anyhow!(
+            "exactly one of --server, --channel or --uid has to be used"
+        )),
+    }
+}
+
+async fn find_sid(db: &BotDb, server: &str) -> Result<Sid> {
+    match server.parse::<Sid>() {
+        Ok(sid) => Ok(sid),
+        Err(_) => db
+            .find_sid(ServerId::from_str(server)?)
+            .await?
+            .ok_or_else(|| anyhow!("unknown server \"{}\"", server)),
+    }
+}
+
+pub async fn run_command(db: Arc<BotDb>, command: Command) -> Result<()> {
+    let changes_cached_data = command.changes_cached_data();
+
+    match command {
+        Command::Run | Command::Help => unreachable!("command is handled by main"),
+        Command::Migrate => {
+            for (version, description) in db.applied_migrations().await? {
+                println!("{} {}", version, description);
+            }
+
+            println!("The database is up to date");
+        }
+        Command::Role { user, role } => {
+            let user = db.find_user(&user).await?;
+
+            match role {
+                Some(role) => {
+                    db.set_role_for_user(user.uid, &role).await?;
+                    println!("Changed the role of uid {} to {}", user.uid, role);
+                }
+                None => println!("uid {} has the role {}", user.uid, user.role),
+            }
+        }
+        Command::Restrict { user, restrictor } => {
+            let user = db.find_user(&user).await?;
+            let restrictor = db.find_user(&restrictor).await?;
+
+            if db.is_restricted(user.uid).await? {
+                println!("uid {} is already restricted", user.uid);
+            } else {
+                db.restrict_user(user.uid, restrictor.uid).await?;
+                println!("Restricted uid {}", user.uid);
+            }
+        }
+        Command::Unrestrict { user } => {
+            let user = db.find_user(&user).await?;
+
+            db.unrestrict_user(user.uid).await?;
+            println!("Unrestricted uid {}", user.uid);
+        }
+        Command::ListTags { server, user } => {
+            let sid = find_sid(&db, &server).await?;
+            let uid = match user {
+                Some(user) => Some(db.find_user(&user).await?.uid),
+                None => None,
+            };
+
+            let tags = db.list_server_tags(sid).await?;
+            let tags = tags
+                .iter()
+                .filter(|tag| uid.map(|uid| tag.uid == uid).unwrap_or(true))
+                .collect::<Vec<_>>();
+
+            for tag in &tags {
+                println!("{} (uid {}): {}", tag.key, tag.uid, tag.value);
+            }
+
+            println!("{} tags", tags.len());
+        }
+        Command::DeleteTags { server, keys, user } => {
+            let sid = find_sid(&db, &server).await?;
+            let mut deleted = 0;
+
+            if let Some(user) = user {
+                let uid = db.find_user(&user).await?.uid;
+                deleted += db.delete_uid_tags(uid, sid).await?;
+            }
+
+            for key in keys {
+                if db.delete_tag(sid, &key.to_lowercase()).await? {
+                    deleted += 1;
+                }
+            }
+
+            println!("Deleted {} tags", deleted);
+        }
+        Command::GetSetting { key, ctx } => {
+            let value = match ctx {
+                SettingContext::Channel(channel_id) => {
+                    db.get_channel_setting(None, channel_id, &key).await?
+                }
+                SettingContext::Server(server_id) => db.get_server_setting(server_id, &key).await?,
+                SettingContext::User(uid) => db.get_user_setting(uid, &key).await?,
+            };
+
+            match value {
+                Some(value) => println!("{} = {}", key, value),
+                None => println!("{} is not set", key),
+            }
+        }
+        Command::SetSetting { key, value, ctx } => {
+            ModuleRegistry::with_builtin().check_setting_input(&key, &ctx, &value)?;
+
+            match ctx {
+                SettingContext::Channel(channel_id) => {
+                    db.save_channel_setting(None, channel_id, &key, &value)
+                        .await?
+                }
+                SettingContext::Server(server_id) => {
+                    db.save_server_setting(server_id, &key, &value).await?
+                }
+                SettingContext::User(uid) => db.save_user_setting(uid, &key, &value).await?,
+            }
+
+            println!("{} = {}", key, value);
+        }
+        Command::UnsetSetting { key, ctx } => {
+            let removed = match ctx {
+                SettingContext::Channel(channel_id) => {
+                    db.delete_channel_setting(channel_id, &key).await?
+                }
+                SettingContext::Server(server_id) => {
+                    db.delete_server_setting(server_id, &key).await?
+                }
+                SettingContext::User(uid) => db.delete_user_setting(uid, &key).await?,
+            };
+
+            if removed {
+                println!("Removed the override for {}", key);
+            } else {
+                println!("{} is not set", key);
+            }
+        }
+        Command::Export { path, sid } => {
+            let archive = db.export_archive(sid).await?;
+            tokio::fs::write(&path, serde_json::to_string_pretty(&archive)?).await?;
+
+            println!(
+                "Exported {} users, {} servers, {} tags, {} scripts to {}",
+                archive.users.len(),
+                archive.servers.len(),
+                archive.tags.len(),
+                archive.scripts.len(),
+                path.display()
+            );
+        }
+        Command::Import { path, server } => {
+            let archive: DbArchive = serde_json::from_slice(&tokio::fs::read(&path).await?)?;
+            let summary = db.import_archive(&archive, server).await?;
+
+            println!(
+                "Imported {} users, {} servers, {} restrictions, {} settings, {} tags ({} already existed), {} scripts ({} already existed)",
+                summary.users,
+                summary.servers,
+                summary.restrictions,
+                summary.settings,
+                summary.tags,
+                summary.skipped_tags,
+                summary.scripts,
+                summary.skipped_scripts
+            );
+        }
+    }
+
+    if changes_cached_data {
+        println!(
+            "A running bot sees the change within a minute, or right away after a config reload (SIGHUP)"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+
+    fn parse(args: &str) -> anyhow::Result<Command> {
+        Command::parse(
+            &args
+                .split_whitespace()
+                .map(|arg| arg.to_string())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn parse_command_test() {
+        assert!(matches!(parse(""), Ok(Command::Run)));
+        assert!(matches!(
+            parse("role discord:1 root"),
+            Ok(Command::Role { user, role: Some(role) }) if user == "discord:1" && role == "root"
+        ));
+        assert!(matches!(
+            parse("restrict 1101 --by 1102"),
+            Ok(Command::Restrict { user, restrictor }) if user == "1101" && restrictor == "1102"
+        ));
+        assert!(matches!(
+            parse("tags delete 1 spam --user 1103"),
+            Ok(Command::DeleteTags { keys, user: Some(_), .. }) if keys == vec!["spam".to_string()]
+        ));
+        assert!(parse("tags delete 1").is_err());
+        assert!(parse("setting get lua/prefix").is_err());
+        assert!(parse("role").is_err());
+        assert!(parse("unknown").is_err());
+    }
+}
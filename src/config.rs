@@ -1,20 +1,62 @@
 use anyhow::Result;
 use std::{collections::HashMap, fs, path::Path};
 
-use crate::services::discord::DiscordServiceConfig;
+use crate::{
+    admin::AdminConfig, logging::LogConfig, metrics::MetricsConfig,
+    services::discord::DiscordServiceConfig,
+};
 
-#[derive(Clone, Deserialize, Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
 pub struct Config {
     pub services: ConfigServices,
     pub user_roles: Option<HashMap<String, String>>,
+    pub logging: Option<LogConfig>,
+    pub metrics: Option<MetricsConfig>,
+    pub admin: Option<AdminConfig>,
+    pub modules: Option<ConfigModules>,
 }
 
-#[derive(Clone, Deserialize, Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
 pub struct ConfigServices {
     pub discord: Option<DiscordServiceConfig>,
 }
 
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Default)]
+pub struct ConfigModules {
+    /// The modules to load in order, every registered module is loaded if it is not set
+    pub load: Option<Vec<String>>,
+    /// The `[modules.<id>]` sections passed to the modules
+    #[serde(flatten)]
+    pub config: HashMap<String, toml::Value>,
+}
+
 pub fn load_config(path: &Path) -> Result<Config> {
     let contents = fs::read_to_string(path)?;
     Ok(toml::from_str(&contents)?)
 }
+
+/// Outcome of reloading the config file while the bot is running
+#[derive(Default, Debug)]
+pub struct ConfigReload {
+    pub applied: Vec<&'static str>,
+    pub restart_required: Vec<&'static str>,
+}
+
+impl ConfigReload {
+    pub fn summary(&self) -> String {
+        let mut out = if self.applied.is_empty() {
+            "no config changes were applied".to_string()
+        } else {
+            format!("applied config sections: {}", self.applied.join(", "))
+        };
+
+        if !self.restart_required.is_empty() {
+            out.push_str(&format!(
+                "; restart required for: {}",
+                self.restart_required.join(", ")
+            ));
+        }
+
+        out
+    }
+}
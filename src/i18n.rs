@@ -0,0 +1,172 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+use crate::utils::vfs::Vfs;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Translated strings loaded from `<share>/locales/<locale>.toml`
+///
+/// Nested tables are flattened into dotted keys, so `[sandbox] time_limit = "..."` is looked up as
+/// `sandbox.time_limit`. Arguments are inserted where the string contains `{name}`.
+#[derive(Default)]
+pub struct Catalog {
+    locales: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalog {
+    pub fn load(vfs: &Vfs) -> Result<Catalog> {
+        let mut locales = HashMap::new();
+
+        for path in vfs.glob("*.toml")? {
+            let locale = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(locale) => locale.to_string(),
+                None => continue,
+            };
+
+            let value: toml::Value = toml::from_str(&vfs.read_to_string(&path)?)
+                .map_err(|err| anyhow!("unable to parse {}: {}", path.display(), err))?;
+
+            let mut strings = HashMap::new();
+            flatten("", value, &mut strings);
+
+            locales.insert(locale, strings);
+        }
+
+        Ok(Catalog { locales })
+    }
+
+    pub fn has_locale(&self, locale: &str) -> bool {
+        self.locales.contains_key(locale)
+    }
+
+    pub fn locales(&self) -> Vec<&str> {
+        let mut locales = self.locales.keys().map(|l| l.as_str()).collect::<Vec<_>>();
+        locales.sort_unstable();
+        locales
+    }
+
+    /// Look up a string, falling back to the default locale and then to the key itself
+    pub fn t(&self, locale: &str, key: &str, args: &[(&str, String)]) -> String {
+        let template = self
+            .locales
+            .get(locale)
+            .and_then(|strings| strings.get(key))
+            .or_else(|| {
+                self.locales
+                    .get(DEFAULT_LOCALE)
+                    .and_then(|strings| strings.get(key))
+            });
+
+        match template {
+            Some(template) => format(template, args),
+            None => key.to_string(),
+        }
+    }
+}
+
+fn flatten(prefix: &str, value: toml::Value, out: &mut HashMap<String, String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+
+                flatten(&key, value, out);
+            }
+        }
+        toml::Value::String(s) => {
+            out.insert(prefix.to_string(), s);
+        }
+        value => {
+            out.insert(prefix.to_string(), value.to_string());
+        }
+    }
+}
+
+// Single pass so that inserted values containing braces are left alone
+fn format(template: &str, args: &[(&str, String)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            args.iter()
+                .find(|(name, _)| *name == &rest[1..end])
+                .map(|(_, value)| (value, end))
+        });
+
+        match value {
+            Some((value, end)) => {
+                out.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{flatten, format};
+    use std::collections::HashMap;
+
+    #[test]
+    fn format_test() {
+        let args = [("name", "kaito".to_string()), ("n", "3".to_string())];
+
+        assert_eq!(format("hi {name}", &args), "hi kaito");
+        assert_eq!(format("{n} of {n}", &args), "3 of 3");
+        assert_eq!(format("{unknown} {name}", &args), "{unknown} kaito");
+        assert_eq!(format("unclosed {name", &args), "unclosed {name");
+        assert_eq!(format("{}", &args), "{}");
+
+        // Inserted values are not formatted again
+        let args = [("a", "{b}".to_string()), ("b", "x".to_string())];
+        assert_eq!(format("{a} {b}", &args), "{b} x");
+    }
+
+    #[test]
+    fn flatten_test() {
+        let value: toml::Value = toml::from_str(
+            r#"
+            top = "a"
+            number = 5
+
+            [sandbox]
+            time_limit = "b"
+
+            [sandbox.nested]
+            key = "c"
+            "#,
+        )
+        .unwrap();
+
+        let mut out = HashMap::new();
+        flatten("", value, &mut out);
+
+        let mut keys = out.keys().map(|key| key.as_str()).collect::<Vec<_>>();
+        keys.sort_unstable();
+
+        assert_eq!(
+            keys,
+            vec!["number", "sandbox.nested.key", "sandbox.time_limit", "top"]
+        );
+        assert_eq!(out["top"], "a");
+        assert_eq!(out["number"], "5");
+        assert_eq!(out["sandbox.time_limit"], "b");
+        assert_eq!(out["sandbox.nested.key"], "c");
+    }
+}
@@ -0,0 +1,169 @@
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+};
+
+// Log an event with optional contextual fields, the fields are only formatted if the level is enabled for the target
+//
+// log_event!(Level::Info, "bot", "message {}", arg)
+// log_event!(Level::Info, "bot", { server = id.to_str(), user = uid }, "message {}", arg)
+macro_rules! log_event {
+    ($level:expr, $target:expr, { $($key:ident = $value:expr),* $(,)? }, $($arg:tt)+) => {
+        if $crate::logging::enabled($level, $target) {
+            $crate::logging::log(
+                $level,
+                $target,
+                &[$((stringify!($key), $value.to_string())),*],
+                format_args!($($arg)+),
+            );
+        }
+    };
+    ($level:expr, $target:expr, $($arg:tt)+) => {
+        log_event!($level, $target, {}, $($arg)+)
+    };
+}
+
+macro_rules! log_error {
+    ($target:expr, $($arg:tt)+) => {
+        log_event!($crate::logging::Level::Error, $target, $($arg)+)
+    };
+}
+
+macro_rules! log_warn {
+    ($target:expr, $($arg:tt)+) => {
+        log_event!($crate::logging::Level::Warn, $target, $($arg)+)
+    };
+}
+
+macro_rules! log_info {
+    ($target:expr, $($arg:tt)+) => {
+        log_event!($crate::logging::Level::Info, $target, $($arg)+)
+    };
+}
+
+macro_rules! log_debug {
+    ($target:expr, $($arg:tt)+) => {
+        log_event!($crate::logging::Level::Debug, $target, $($arg)+)
+    };
+}
+
+macro_rules! log_trace {
+    ($target:expr, $($arg:tt)+) => {
+        log_event!($crate::logging::Level::Trace, $target, $($arg)+)
+    };
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// The `[logging]` section of the config
+///
+/// Targets are matched by prefix on `/` boundaries, so a level for `modules` also applies to `modules/lua`
+/// unless there is a more specific entry.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Default)]
+pub struct LogConfig {
+    pub level: Option<Level>,
+    pub format: Option<LogFormat>,
+    #[serde(default)]
+    pub targets: HashMap<String, Level>,
+}
+
+impl LogConfig {
+    fn max_level(&self, target: &str) -> Level {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target == prefix.as_str()
+                    || (target.starts_with(prefix.as_str())
+                        && target[prefix.len()..].starts_with('/'))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or_else(|| self.level.unwrap_or(Level::Info))
+    }
+}
+
+static CONFIG: Lazy<ArcSwap<LogConfig>> = Lazy::new(|| ArcSwap::from_pointee(LogConfig::default()));
+
+/// Replace the active logging config, used both on startup and when the config is reloaded
+pub fn init(config: &LogConfig) {
+    CONFIG.store(config.clone().into());
+}
+
+pub fn enabled(level: Level, target: &str) -> bool {
+    level <= CONFIG.load().max_level(target)
+}
+
+pub fn log(level: Level, target: &str, fields: &[(&str, String)], message: fmt::Arguments) {
+    let config = CONFIG.load();
+    let time = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ");
+
+    let line = match config.format.unwrap_or(LogFormat::Text) {
+        LogFormat::Text => {
+            let mut line = format!(
+                "{} {:<5} {}: {}",
+                time,
+                level.as_str().to_uppercase(),
+                target,
+                message
+            );
+
+            for (key, value) in fields {
+                if value.is_empty() || value.contains(char::is_whitespace) {
+                    line.push_str(&format!(" {}={:?}", key, value));
+                } else {
+                    line.push_str(&format!(" {}={}", key, value));
+                }
+            }
+
+            line
+        }
+        LogFormat::Json => {
+            let mut obj = serde_json::Map::new();
+            obj.insert("time".into(), time.to_string().into());
+            obj.insert("level".into(), level.as_str().into());
+            obj.insert("target".into(), target.into());
+            obj.insert("message".into(), message.to_string().into());
+
+            for (key, value) in fields {
+                obj.insert(key.to_string(), value.clone().into());
+            }
+
+            serde_json::Value::Object(obj).to_string()
+        }
+    };
+
+    // Ignore write errors, there is nowhere left to report them
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "{}", line).ok();
+}
@@ -8,17 +8,30 @@ extern crate serde_derive;
 use anyhow::Result;
 use std::{env, path::PathBuf};
 
+#[macro_use]
+mod logging;
 #[macro_use]
 mod settings;
 
+mod admin;
 mod bot;
+mod cli;
 mod config;
+mod i18n;
 mod message;
+mod metrics;
 mod modules;
 mod services;
 mod utils;
 
 async fn run() -> Result<()> {
+    let command = cli::Command::parse(&env::args().skip(1).collect::<Vec<_>>())?;
+
+    if let cli::Command::Help = command {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
     let config_path = env::var("KAITO_CONFIG_FILE")
         .map(|p| PathBuf::from(p))
         .or_else(|_| env::current_dir().map(|p| p.join("config.toml")))?;
@@ -32,31 +45,100 @@ async fn run() -> Result<()> {
         .or_else(|_| env::current_dir())?;
 
     let config = config::load_config(&config_path)?;
+    logging::init(&config.logging.clone().unwrap_or_default());
 
     if !data_path.is_dir() {
         std::fs::create_dir_all(&data_path)?;
     }
 
-    let bot = bot::Bot::init(data_path, share_path, &config).await?;
-    let modules = modules::Modules::init(bot.clone(), &config).await?;
+    if command.needs_db() {
+        let db = bot::db::BotDb::new(&data_path, &share_path, &config).await?;
+        return cli::run_command(db, command).await;
+    }
+
+    let bot = bot::Bot::init(data_path, share_path, config_path, &config).await?;
+    let modules =
+        modules::Modules::init(bot.clone(), &config, modules::ModuleRegistry::with_builtin())
+            .await?;
     let services = services::Services::init(bot.clone(), &config.services).await?;
     let ctx = bot::BotContext::new(bot.clone(), modules, services);
     bot.set_ctx(ctx);
 
-    println!("Everything is online");
+    log_info!("bot", "Everything is online");
 
-    tokio::signal::ctrl_c().await?;
-    println!("Exit signal received, shutting down...");
+    if let Some(metrics_config) = config.metrics.clone() {
+        log_info!(
+            "metrics",
+            "Serving metrics on http://{}/metrics",
+            metrics_config.listen
+        );
+
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(metrics_config.listen).await {
+                log_error!("metrics", "Error serving metrics: {}", err.to_string());
+            }
+        });
+    }
+
+    if let Some(admin_config) = config.admin.clone() {
+        log_info!("admin", "Serving the admin API on http://{}", admin_config.listen);
+
+        let bot = bot.clone();
+        tokio::spawn(async move {
+            if let Err(err) = admin::serve(bot, admin_config).await {
+                log_error!("admin", "Error serving the admin API: {}", err.to_string());
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        let bot = bot.clone();
+
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match bot.reload_config().await {
+                    Ok(report) => log_info!("bot", "Config reloaded, {}", report.summary()),
+                    Err(err) => log_error!("bot", "Error reloading config: {}", err.to_string()),
+                }
+            }
+        });
+    }
+
+    wait_for_exit_signal().await?;
+    log_info!("bot", "Exit signal received, shutting down...");
     bot.get_ctx().shutdown().await?;
 
     Ok(())
 }
 
+async fn wait_for_exit_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res?,
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() {
     graphicsmagick::initialize();
 
     if let Err(err) = run().await {
-        println!("Error: {}", err.to_string());
+        log_error!("bot", "Error: {}", err.to_string());
     }
 }
@@ -0,0 +1,374 @@
+use anyhow::Result;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::Mutex,
+    time::Instant,
+};
+
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The `[metrics]` section of the config
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct MetricsConfig {
+    pub listen: SocketAddr,
+}
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+pub struct Metrics {
+    pub module_messages: Counter,
+    pub module_errors: Counter,
+    pub lua_commands: Counter,
+    pub lua_command_duration: Histogram,
+    pub sandbox_executions: Counter,
+    pub sandbox_recycles: Counter,
+    pub http_fetches: Counter,
+    pub image_operations: Counter,
+    pub lua_async_queue_depth: Gauge,
+    pub db_query_duration: Histogram,
+    pub db_cache_lookups: Counter,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        Metrics {
+            module_messages: Counter::new(
+                "kaito_module_messages_total",
+                "Messages handled per module",
+                &["module"],
+            ),
+            module_errors: Counter::new(
+                "kaito_module_errors_total",
+                "Errors returned by module event handlers",
+                &["module"],
+            ),
+            lua_commands: Counter::new(
+                "kaito_lua_commands_total",
+                "Lua command invocations",
+                &["command", "result"],
+            ),
+            lua_command_duration: Histogram::new(
+                "kaito_lua_command_duration_seconds",
+                "Time spent executing lua commands",
+                &["command"],
+            ),
+            sandbox_executions: Counter::new(
+                "kaito_sandbox_executions_total",
+                "Sandboxed lua executions by how they terminated",
+                &["reason"],
+            ),
+            sandbox_recycles: Counter::new(
+                "kaito_sandbox_recycles_total",
+                "Sandbox states replaced with a fresh one",
+                &["reason"],
+            ),
+            http_fetches: Counter::new(
+                "kaito_http_fetches_total",
+                "http.fetch calls from lua",
+                &["state", "result"],
+            ),
+            image_operations: Counter::new(
+                "kaito_image_operations_total",
+                "Image operations started from lua",
+                &["operation"],
+            ),
+            lua_async_queue_depth: Gauge::new(
+                "kaito_lua_async_callbacks_queued",
+                "Async callbacks waiting to be resolved by a lua state",
+                &["state"],
+            ),
+            db_query_duration: Histogram::new(
+                "kaito_db_query_duration_seconds",
+                "Latency of database queries",
+                &["query"],
+            ),
+            db_cache_lookups: Counter::new(
+                "kaito_db_cache_lookups_total",
+                "Lookups in the database cache by result",
+                &["cache", "result"],
+            ),
+        }
+    }
+
+    /// Render all metrics in the prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        self.module_messages.render(&mut out);
+        self.module_errors.render(&mut out);
+        self.lua_commands.render(&mut out);
+        self.lua_command_duration.render(&mut out);
+        self.sandbox_executions.render(&mut out);
+        self.sandbox_recycles.render(&mut out);
+        self.http_fetches.render(&mut out);
+        self.image_operations.render(&mut out);
+        self.lua_async_queue_depth.render(&mut out);
+        self.db_query_duration.render(&mut out);
+        self.db_cache_lookups.render(&mut out);
+
+        out
+    }
+}
+
+fn label_values(labels: &[&str]) -> Vec<String> {
+    labels.iter().map(|label| label.to_string()).collect()
+}
+
+fn format_labels(names: &[&str], values: &[String], extra: Option<(&str, &str)>) -> String {
+    let mut pairs = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| {
+            format!(
+                "{}=\"{}\"",
+                name,
+                value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if let Some((name, value)) = extra {
+        pairs.push(format!("{}=\"{}\"", name, value));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl Counter {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Counter {
+        Counter {
+            name,
+            help,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        *self
+            .values
+            .lock()
+            .unwrap()
+            .entry(label_values(labels))
+            .or_insert(0) += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        writeln!(out, "# HELP {} {}", self.name, self.help).ok();
+        writeln!(out, "# TYPE {} counter", self.name).ok();
+
+        for (labels, value) in self.values.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "{}{} {}",
+                self.name,
+                format_labels(self.labels, labels, None),
+                value
+            )
+            .ok();
+        }
+    }
+}
+
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, f64>>,
+}
+
+impl Gauge {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Gauge {
+        Gauge {
+            name,
+            help,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn set(&self, labels: &[&str], value: f64) {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(label_values(labels), value);
+    }
+
+    fn render(&self, out: &mut String) {
+        writeln!(out, "# HELP {} {}", self.name, self.help).ok();
+        writeln!(out, "# TYPE {} gauge", self.name).ok();
+
+        for (labels, value) in self.values.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "{}{} {}",
+                self.name,
+                format_labels(self.labels, labels, None),
+                value
+            )
+            .ok();
+        }
+    }
+}
+
+#[derive(Default)]
+struct HistogramValue {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, HistogramValue>>,
+}
+
+impl Histogram {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Histogram {
+        Histogram {
+            name,
+            help,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        let mut values = self.values.lock().unwrap();
+        let entry = values
+            .entry(label_values(labels))
+            .or_insert_with(|| HistogramValue {
+                buckets: vec![0; DURATION_BUCKETS.len()],
+                ..Default::default()
+            });
+
+        for (bucket, le) in entry.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if value <= *le {
+                *bucket += 1;
+            }
+        }
+
+        entry.sum += value;
+        entry.count += 1;
+    }
+
+    /// Observe the time until the returned timer is dropped
+    pub fn start_timer(&'static self, labels: &[&str]) -> HistogramTimer {
+        HistogramTimer {
+            histogram: self,
+            labels: label_values(labels),
+            start: Instant::now(),
+        }
+    }
+
+    fn render(&self, out: &mut String) {
+        writeln!(out, "# HELP {} {}", self.name, self.help).ok();
+        writeln!(out, "# TYPE {} histogram", self.name).ok();
+
+        for (labels, value) in self.values.lock().unwrap().iter() {
+            for (bucket, le) in value.buckets.iter().zip(DURATION_BUCKETS) {
+                writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    self.name,
+                    format_labels(self.labels, labels, Some(("le", &le.to_string()))),
+                    bucket
+                )
+                .ok();
+            }
+
+            writeln!(
+                out,
+                "{}_bucket{} {}",
+                self.name,
+                format_labels(self.labels, labels, Some(("le", "+Inf"))),
+                value.count
+            )
+            .ok();
+            writeln!(
+                out,
+                "{}_sum{} {}",
+                self.name,
+                format_labels(self.labels, labels, None),
+                value.sum
+            )
+            .ok();
+            writeln!(
+                out,
+                "{}_count{} {}",
+                self.name,
+                format_labels(self.labels, labels, None),
+                value.count
+            )
+            .ok();
+        }
+    }
+}
+
+pub struct HistogramTimer {
+    histogram: &'static Histogram,
+    labels: Vec<String>,
+    start: Instant,
+}
+
+impl Drop for HistogramTimer {
+    fn drop(&mut self) {
+        let labels = self.labels.iter().map(|l| l.as_str()).collect::<Vec<_>>();
+        self.histogram
+            .observe(&labels, self.start.elapsed().as_secs_f64());
+    }
+}
+
+async fn handle_request(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let mut res = Response::new(Body::empty());
+
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            res.headers_mut().insert(
+                CONTENT_TYPE,
+                "text/plain; version=0.0.4".parse().unwrap(),
+            );
+            *res.body_mut() = Body::from(METRICS.render());
+        }
+        _ => *res.status_mut() = StatusCode::NOT_FOUND,
+    }
+
+    Ok(res)
+}
+
+/// Serve the metrics on `/metrics` until the listener fails
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    let make_service =
+        make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+
+    Server::try_bind(&addr)?.serve(make_service).await?;
+
+    Ok(())
+}
@@ -1,130 +1,394 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    any::Any,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-mod lua;
-mod utils;
+pub mod lua;
+pub mod utils;
 
 use crate::{
-    bot::Bot,
+    bot::{db::Uid, Bot},
     config::Config,
-    services::{ChannelId, Message, MessageId, ServerId, Service, User},
-    settings::Settings,
+    logging::{self, Level},
+    metrics::METRICS,
+    services::{AnyMessage, AnyUser, ChannelId, Message, MessageId, ServerId, Service, User},
+    settings::{
+        self, EffectiveSetting, Setting, SettingBoolParameters, SettingContext, SettingFlags,
+        SettingInfo, Settings,
+    },
+    utils::tasks::TaskTracker,
 };
 
-macro_rules! modules_loader {
-    ($modules_struct:ident, $($module_ident:ident => ($module:ty, $module_config:tt)),*) => {
-        pub struct $modules_struct {
-            $(pub $module_ident: ModuleWrapper<$module>),+
-        }
+type SettingInputCheck = fn(&str, &SettingContext, &str) -> Result<()>;
 
-        impl $modules_struct {
-            #[allow(unused_variables)]
-            pub async fn init(bot: Arc<Bot>, config: &Config) -> Result<Arc<$modules_struct>> {
-                Ok(Arc::new($modules_struct {
-                    $(
-                        $module_ident: ModuleWrapper::new(modules_loader! {__init, $module, bot.clone(), config, $module_config})
-                    ),+
-                }))
-            }
+type ModuleLoader = Box<
+    dyn Fn(Arc<Bot>, Option<toml::Value>) -> BoxFuture<'static, Result<Arc<dyn DynModule>>>
+        + Send
+        + Sync,
+>;
 
-            #[allow(dead_code)]
-            pub async fn message(&self, msg: Arc<dyn Message<impl Service>>) {
-                $(
-                    if self.$module_ident.is_enabled() {
-                        let module = self.$module_ident.module().clone();
-                        let msg = msg.clone();
-                        tokio::spawn(async move {
-                            if let Err(err) = module.message(msg).await {
-                                println!("error during executing module {}: {}", module.name(), err.to_string())
-                            }
-                        });
-                    }
-                )+
-            }
+/// The modules that can be loaded, the config decides which of them are loaded and in what order
+#[derive(Default)]
+pub struct ModuleRegistry {
+    loaders: Vec<(&'static str, ModuleLoader)>,
+    setting_checks: Vec<(&'static str, SettingInputCheck)>,
+}
 
-            pub async fn message_update(&self, msg: Arc<dyn Message<impl Service>>, old_msg: Option<Arc<dyn Message<impl Service>>>) {
-                $(
-                    if self.$module_ident.is_enabled() {
-                        if let Err(err) = self.$module_ident.module().message_update(msg.clone(), old_msg.clone()).await {
-                            println!("error during executing module {}: {}", self.$module_ident.module().name(), err.to_string())
-                        };
-                    }
-                )+
-            }
+impl ModuleRegistry {
+    /// A registry with the modules that are part of the bot
+    pub fn with_builtin() -> ModuleRegistry {
+        let mut registry = ModuleRegistry::default();
 
-            pub async fn message_delete(
-                &self,
-                server_id: Option<ServerId>,
-                channel_id: ChannelId,
-                message_id: MessageId,
-            ) {
-                $(
-                    if self.$module_ident.is_enabled() {
-                        if let Err(err) = self.$module_ident.module().message_delete(server_id, channel_id, message_id).await {
-                            println!("error during executing module {}: {}", self.$module_ident.module().name(), err.to_string())
-                        };
-                    }
-                )+
+        registry
+            .register::<lua::LuaModule>()
+            .register::<utils::UtilsModule>();
+
+        registry
+    }
+
+    pub fn register<M: Module>(&mut self) -> &mut ModuleRegistry {
+        self.loaders.push((
+            M::ID,
+            Box::new(|bot, config| {
+                Box::pin(async move {
+                    let module = M::load(bot.clone(), parse_module_config::<M>(config)?).await?;
+
+                    Ok(Arc::new(ModuleWrapper::new(bot, module)?) as Arc<dyn DynModule>)
+                })
+            }),
+        ));
+        self.setting_checks
+            .push((M::ID, WrappedSettings::<M>::check_input));
+
+        self
+    }
+
+    /// Check a value for a "<module>/<setting>" key without loading the modules
+    pub fn check_setting_input(&self, key: &str, ctx: &SettingContext, input: &str) -> Result<()> {
+        let (module, setting) = key
+            .split_once('/')
+            .ok_or_else(|| anyhow!("expected a key like <module>/<setting>, got \"{}\"", key))?;
+        let (_, check) = self
+            .setting_checks
+            .iter()
+            .find(|(id, _)| *id == module)
+            .ok_or_else(|| anyhow!("unknown module \"{}\"", module))?;
+
+        check(setting, ctx, input).map_err(|err| anyhow!("{}: {}", key, err))
+    }
+}
+
+// Modules without a section in the config get the default config
+fn parse_module_config<M: Module>(config: Option<toml::Value>) -> Result<M::ModuleConfig> {
+    match config {
+        Some(config) => config
+            .try_into()
+            .map_err(|err| anyhow!("invalid config for the {} module: {}", M::ID, err)),
+        None => Ok(Default::default()),
+    }
+}
+
+pub struct Modules {
+    modules: Vec<Arc<dyn DynModule>>,
+    tasks: Arc<TaskTracker>,
+}
+
+impl Modules {
+    pub async fn init(
+        bot: Arc<Bot>,
+        config: &Config,
+        registry: ModuleRegistry,
+    ) -> Result<Arc<Modules>> {
+        let modules_config = config.modules.clone().unwrap_or_default();
+        let ids = match modules_config.load {
+            Some(load) => load,
+            None => registry
+                .loaders
+                .iter()
+                .map(|(id, _)| id.to_string())
+                .collect(),
+        };
+
+        let mut modules: Vec<Arc<dyn DynModule>> = Vec::new();
+
+        for id in ids {
+            if modules.iter().any(|module| module.id() == id) {
+                return Err(anyhow!("the {} module is loaded more than once", id));
             }
 
-            #[allow(dead_code)]
-            pub async fn reaction(&self, msg: Arc<dyn Message<impl Service>>, reactor: Arc<dyn User<impl Service>>, reaction: String, remove: bool) {
-                $(
-                    if self.$module_ident.is_enabled() {
-                        if let Err(err) = self.$module_ident.module().reaction(msg.clone(), reactor.clone(), reaction.clone(), remove).await {
-                            println!("error during executing module {}: {}", self.$module_ident.module().name(), err.to_string())
-                        };
+            let (_, loader) = registry
+                .loaders
+                .iter()
+                .find(|(registered_id, _)| *registered_id == id)
+                .ok_or_else(|| anyhow!("unknown module \"{}\"", id))?;
+
+            modules.push(loader(bot.clone(), modules_config.config.get(&id).cloned()).await?);
+            log_debug!("modules", "Loaded the {} module", id);
+        }
+
+        Ok(Arc::new(Modules {
+            modules,
+            tasks: bot.tasks().clone(),
+        }))
+    }
+
+    pub async fn message(&self, msg: Arc<dyn Message<impl Service>>) {
+        let msg = AnyMessage::new(msg);
+        let location = msg.location().await;
+
+        for module in &self.modules {
+            if module.should_handle(location).await {
+                METRICS.module_messages.inc(&[module.id()]);
+                log_trace!(
+                    "modules",
+                    { module = module.id() },
+                    "Passing a message to the module"
+                );
+
+                let module = module.clone();
+                let msg = msg.clone();
+                let task = self.tasks.track();
+                tokio::spawn(async move {
+                    let _task = task;
+
+                    if let Err(err) = module.message(msg.clone()).await {
+                        log_module_error(&*module, location, Some(&msg), &err);
                     }
-                )+
+                });
             }
+        }
+    }
+
+    pub async fn message_update(
+        &self,
+        msg: Arc<dyn Message<impl Service>>,
+        old_msg: Option<Arc<dyn Message<impl Service>>>,
+    ) {
+        let msg = AnyMessage::new(msg);
+        let old_msg = old_msg.map(AnyMessage::new);
+        let location = msg.location().await;
 
-            pub fn get_settings(&self, name: &str) -> Option<Arc<dyn Settings>> {
-                match name {
-                    $(
-                        <$module>::ID => Some(self.$module_ident.module().settings().clone() as Arc<_>),
-                    )+
-                    _ => None
+        for module in &self.modules {
+            if module.should_handle(location).await {
+                if let Err(err) = module.message_update(msg.clone(), old_msg.clone()).await {
+                    log_module_error(&**module, location, Some(&msg), &err);
                 }
             }
+        }
+    }
 
-            // Join all the unload functions of the modules and return the first error if any
-            pub async fn unload(&self) -> Result<()> {
-                let (
-                    $($module_ident,)+
-                ) = futures::join!(
-                    $(
-                        self.$module_ident.module().unload()
-                    ),+
-                );
+    pub async fn message_delete(
+        &self,
+        server_id: Option<ServerId>,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) {
+        let location = server_id.map(|server_id| (server_id, channel_id));
+
+        for module in &self.modules {
+            if module.should_handle(location).await {
+                if let Err(err) = module
+                    .message_delete(server_id, channel_id, message_id)
+                    .await
+                {
+                    log_module_error(&**module, location, None, &err);
+                }
+            }
+        }
+    }
 
+    pub async fn reaction(
+        &self,
+        msg: Arc<dyn Message<impl Service>>,
+        reactor: Arc<dyn User<impl Service>>,
+        reaction: String,
+        remove: bool,
+    ) {
+        let msg = AnyMessage::new(msg);
+        let reactor = AnyUser::new(reactor);
+        let location = msg.location().await;
 
-                match None.$(or_else(|| $module_ident.err())).+ {
-                    Some(err) => Err(err),
-                    None => Ok(())
+        for module in &self.modules {
+            if module.should_handle(location).await {
+                if let Err(err) = module
+                    .reaction(msg.clone(), reactor.clone(), reaction.clone(), remove)
+                    .await
+                {
+                    log_module_error(&**module, location, Some(&msg), &err);
                 }
             }
         }
-    };
-    (__init, $module:ty, $bot:expr, $config:expr, ()) => {
-        <$module>::load($bot, ()).await?
-    };
-    (__init, $module:ty, $bot:expr, $config:expr, $config_ident:ident) => {
-        <$module>::load($bot, $config.$config_ident).await?
-    };
+    }
+
+    /// Get a loaded module by its type
+    pub fn get<M: Module>(&self) -> Option<Arc<M>> {
+        self.modules
+            .iter()
+            .find(|module| module.id() == M::ID)?
+            .as_any()
+            .downcast::<M>()
+            .ok()
+    }
+
+    pub fn get_settings(&self, id: &str) -> Option<Arc<dyn Settings>> {
+        self.modules
+            .iter()
+            .find(|module| module.id() == id)
+            .map(|module| module.settings())
+    }
+
+    /// The ids of all loaded modules and whether they are enabled globally
+    pub fn list(&self) -> Vec<(&'static str, bool)> {
+        self.modules
+            .iter()
+            .map(|module| (module.id(), module.is_enabled()))
+            .collect()
+    }
+
+    /// Enable or disable a module for every server until the bot is restarted
+    pub fn set_enabled(&self, id: &str, enabled: bool) -> Result<()> {
+        self.modules
+            .iter()
+            .find(|module| module.id() == id)
+            .ok_or_else(|| anyhow!("unknown module \"{}\"", id))?
+            .set_enabled(enabled);
+
+        log_info!(
+            "modules",
+            "{} the {} module",
+            if enabled { "Enabled" } else { "Disabled" },
+            id
+        );
+
+        Ok(())
+    }
+
+    /// Check that the module sections of a config parse, without applying them
+    pub fn check_config(&self, config: &Config) -> Result<()> {
+        let modules_config = config.modules.clone().unwrap_or_default();
+
+        for module in &self.modules {
+            module.check_config(modules_config.config.get(module.id()).cloned())?;
+        }
+
+        Ok(())
+    }
+
+    // Pass the module sections of a reloaded config to the modules
+    pub async fn reload_config(&self, config: &Config) -> Result<()> {
+        let modules_config = config.modules.clone().unwrap_or_default();
+
+        for module in &self.modules {
+            module
+                .reload_config(modules_config.config.get(module.id()).cloned())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // Join all the unload functions of the modules and return the first error if any
+    pub async fn unload(&self) -> Result<()> {
+        futures::future::join_all(self.modules.iter().map(|module| module.unload()))
+            .await
+            .into_iter()
+            .collect()
+    }
+}
+
+/// An error of a module while handling a command, the command is added to the log fields
+#[derive(Debug)]
+pub struct CommandError {
+    pub command: String,
+    pub err: anyhow::Error,
+}
+
+impl CommandError {
+    pub fn wrap(command: impl Into<String>, err: anyhow::Error) -> anyhow::Error {
+        CommandError {
+            command: command.into(),
+            err,
+        }
+        .into()
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.err)
+    }
+}
+
+impl std::error::Error for CommandError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.err.source()
+    }
+}
+
+// Log an error returned by a module together with the server, channel, user and command of the event that caused it
+fn log_module_error(
+    module: &dyn DynModule,
+    location: Option<(ServerId, ChannelId)>,
+    msg: Option<&AnyMessage>,
+    err: &anyhow::Error,
+) {
+    METRICS.module_errors.inc(&[module.id()]);
+
+    let target = format!("modules/{}", module.id());
+
+    if !logging::enabled(Level::Error, &target) {
+        return;
+    }
+
+    let mut fields = Vec::new();
+
+    if let Some(msg) = msg {
+        fields.push(("user", msg.author_id().to_str()));
+    }
+
+    if let Some((server_id, channel_id)) = location {
+        fields.push(("channel", channel_id.to_str()));
+        fields.push(("server", server_id.to_str()));
+    }
+
+    if let Some(err) = err.downcast_ref::<CommandError>() {
+        fields.push(("command", err.command.clone()));
+    }
+
+    logging::log(
+        Level::Error,
+        &target,
+        &fields,
+        format_args!(
+            "error during executing module {}: {}",
+            module.name(),
+            err.to_string()
+        ),
+    );
 }
 
 #[async_trait]
 pub trait Module: 'static + Send + Sync + Sized {
-    const KIND: ModuleKind;
     const ID: &'static str;
     const NAME: &'static str;
 
-    type ModuleConfig: Clone + Deserialize<'static> + Serialize + std::fmt::Debug;
+    /// Parsed from the `[modules.<id>]` section of the config, the default is used if it is missing
+    type ModuleConfig: Clone + Default + Deserialize<'static> + Serialize + std::fmt::Debug + Send;
     type ModuleSettings: Settings;
 
     async fn load(bot: Arc<Bot>, config: Self::ModuleConfig) -> Result<Arc<Self>>;
     async fn unload(&self) -> Result<()>;
+    // Called with the module config when the config file has been reloaded
+    async fn reload_config(&self, _config: Self::ModuleConfig) -> Result<()> {
+        Ok(())
+    }
 
     // TODO: Move message to type alias when impl's inside type aliases becomes stable
     async fn message(&self, msg: Arc<dyn Message<impl Service>>) -> Result<()>;
@@ -148,11 +412,11 @@ pub trait Module: 'static + Send + Sync + Sized {
         remove: bool,
     ) -> Result<()>;
 
-    async fn enabled(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool>;
-
-    fn kind(&self) -> ModuleKind {
-        Self::KIND
+    // Module specific check on top of the generic enable setting managed by `Modules`
+    async fn enabled(&self, _server_id: ServerId, _channel_id: ChannelId) -> Result<bool> {
+        Ok(true)
     }
+
     fn name(&self) -> &'static str {
         Self::NAME
     }
@@ -160,32 +424,235 @@ pub trait Module: 'static + Send + Sync + Sized {
     fn settings(&self) -> &Arc<Self::ModuleSettings>;
 }
 
+/// Object safe interface for a loaded module, implemented by `ModuleWrapper` for every `Module`
+#[async_trait]
+pub trait DynModule: Send + Sync {
+    fn id(&self) -> &'static str;
+    fn name(&self) -> &'static str;
+    fn as_any(&self) -> Arc<dyn Any + Send + Sync>;
+
+    fn is_enabled(&self) -> bool;
+    fn set_enabled(&self, enabled: bool);
+    async fn should_handle(&self, location: Option<(ServerId, ChannelId)>) -> bool;
+    fn settings(&self) -> Arc<dyn Settings>;
+
+    async fn unload(&self) -> Result<()>;
+    fn check_config(&self, config: Option<toml::Value>) -> Result<()>;
+    async fn reload_config(&self, config: Option<toml::Value>) -> Result<()>;
+
+    async fn message(&self, msg: AnyMessage) -> Result<()>;
+    async fn message_update(&self, msg: AnyMessage, old_msg: Option<AnyMessage>) -> Result<()>;
+    async fn message_delete(
+        &self,
+        server_id: Option<ServerId>,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<()>;
+    async fn reaction(
+        &self,
+        msg: AnyMessage,
+        reactor: AnyUser,
+        reaction: String,
+        remove: bool,
+    ) -> Result<()>;
+}
+
 pub struct ModuleWrapper<M: Module> {
     module: Arc<M>,
+    enabled: AtomicBool,
+    enable_setting: Arc<Setting<bool, M>>,
 }
 
 impl<M: Module> ModuleWrapper<M> {
-    pub fn new(module: Arc<M>) -> ModuleWrapper<M> {
-        ModuleWrapper { module }
+    pub fn new(bot: Arc<Bot>, module: Arc<M>) -> Result<ModuleWrapper<M>> {
+        Ok(ModuleWrapper {
+            module,
+            enabled: AtomicBool::new(true),
+            enable_setting: Arc::new(Setting::create(
+                bot,
+                "enable",
+                true,
+                SettingBoolParameters::default(),
+                SettingFlags::empty(),
+                "Enable the module".into(),
+            )?),
+        })
+    }
+}
+
+#[async_trait]
+impl<M: Module> DynModule for ModuleWrapper<M> {
+    fn id(&self) -> &'static str {
+        M::ID
     }
 
-    pub fn is_enabled(&self) -> bool {
-        true
+    fn name(&self) -> &'static str {
+        M::NAME
+    }
+
+    fn as_any(&self) -> Arc<dyn Any + Send + Sync> {
+        self.module.clone()
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
     }
 
-    pub fn module(&self) -> &Arc<M> {
-        &self.module
+    /// Whether an event should be passed to the module, the per server checks are skipped for events
+    /// outside of servers
+    async fn should_handle(&self, location: Option<(ServerId, ChannelId)>) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+
+        let (server_id, channel_id) = match location {
+            Some(location) => location,
+            None => return true,
+        };
+
+        let res = match self.enable_setting.value(server_id, channel_id, None).await {
+            Ok(true) => self.module.enabled(server_id, channel_id).await,
+            res => res,
+        };
+
+        res.unwrap_or_else(|err| {
+            log_error!(
+                &format!("modules/{}", M::ID),
+                { server = server_id.to_str(), channel = channel_id.to_str() },
+                "error checking if the module is enabled: {}",
+                err.to_string()
+            );
+
+            false
+        })
+    }
+
+    /// The settings of the module including the generic settings
+    fn settings(&self) -> Arc<dyn Settings> {
+        Arc::new(WrappedSettings {
+            enable: self.enable_setting.clone(),
+            settings: self.module.settings().clone(),
+        })
+    }
+
+    async fn unload(&self) -> Result<()> {
+        self.module.unload().await
+    }
+
+    fn check_config(&self, config: Option<toml::Value>) -> Result<()> {
+        parse_module_config::<M>(config).map(|_| ())
+    }
+
+    async fn reload_config(&self, config: Option<toml::Value>) -> Result<()> {
+        self.module
+            .reload_config(parse_module_config::<M>(config)?)
+            .await
+    }
+
+    async fn message(&self, msg: AnyMessage) -> Result<()> {
+        match msg {
+            AnyMessage::Discord(msg) => self.module.message(msg).await,
+        }
+    }
+
+    async fn message_update(&self, msg: AnyMessage, old_msg: Option<AnyMessage>) -> Result<()> {
+        match msg {
+            AnyMessage::Discord(msg) => {
+                let old_msg = old_msg.map(|old_msg| match old_msg {
+                    AnyMessage::Discord(old_msg) => old_msg,
+                });
+
+                self.module.message_update(msg, old_msg).await
+            }
+        }
+    }
+
+    async fn message_delete(
+        &self,
+        server_id: Option<ServerId>,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<()> {
+        self.module
+            .message_delete(server_id, channel_id, message_id)
+            .await
+    }
+
+    async fn reaction(
+        &self,
+        msg: AnyMessage,
+        reactor: AnyUser,
+        reaction: String,
+        remove: bool,
+    ) -> Result<()> {
+        match (msg, reactor) {
+            (AnyMessage::Discord(msg), AnyUser::Discord(reactor)) => {
+                self.module.reaction(msg, reactor, reaction, remove).await
+            }
+        }
     }
 }
 
-pub enum ModuleKind {
-    Lua,
-    Utils,
+// The settings of a module together with the generic settings managed by `Modules`
+struct WrappedSettings<M: Module> {
+    enable: Arc<Setting<bool, M>>,
+    settings: Arc<M::ModuleSettings>,
 }
 
-modules_loader! {
-    Modules,
+#[async_trait]
+impl<M: Module> Settings for WrappedSettings<M> {
+    fn enumerate(&self) -> Vec<SettingInfo> {
+        let mut out = vec![self.enable.info()];
+        out.extend(self.settings.enumerate());
+        out
+    }
+
+    async fn set_setting(&self, ctx: SettingContext, setting: &str, value: &str) -> Result<()> {
+        match setting {
+            "enable" => self.enable.set_value(ctx, value).await,
+            _ => self.settings.set_setting(ctx, setting, value).await,
+        }
+    }
 
-    lua => (lua::LuaModule, ()),
-    utils => (utils::UtilsModule, ())
+    async fn get_setting(
+        &self,
+        setting: &str,
+        server_id: ServerId,
+        channel_id: ChannelId,
+        uid: Option<Uid>,
+    ) -> Result<EffectiveSetting> {
+        match setting {
+            "enable" => self.enable.effective_value(server_id, channel_id, uid).await,
+            _ => {
+                self.settings
+                    .get_setting(setting, server_id, channel_id, uid)
+                    .await
+            }
+        }
+    }
+
+    async fn unset_setting(&self, ctx: SettingContext, setting: &str) -> Result<bool> {
+        match setting {
+            "enable" => self.enable.unset_value(ctx).await,
+            _ => self.settings.unset_setting(ctx, setting).await,
+        }
+    }
+
+    fn check_input(setting: &str, ctx: &SettingContext, input: &str) -> Result<()> {
+        match setting {
+            "enable" => settings::parse_input::<bool>(
+                setting,
+                SettingFlags::empty(),
+                ctx,
+                input,
+                &SettingBoolParameters::default(),
+            )
+            .map(|_| ()),
+            _ => M::ModuleSettings::check_input(setting, ctx, input),
+        }
+    }
 }
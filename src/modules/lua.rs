@@ -1,6 +1,5 @@
 use anyhow::Result;
 use async_mutex::{Mutex, MutexGuardArc};
-use crossbeam::channel::TryRecvError;
 use lru::LruCache;
 use std::{
     sync::Arc,
@@ -10,15 +9,20 @@ use std::{
 #[macro_use]
 mod lib;
 mod http;
+mod pool;
+mod quota;
 mod state;
+mod storage;
 mod utils;
 
 use self::lib::bot::BotUser;
 
-use super::{Module, ModuleKind};
+use super::{CommandError, Module};
 use crate::{
-    bot::Bot,
+    bot::{db::User as DbUser, Bot},
+    i18n::{Catalog, DEFAULT_LOCALE},
     message::MessageSettings,
+    metrics::METRICS,
     services::{
         Channel, ChannelId, Message, MessageId, Server, ServerId, Service, ServiceFeatures,
         ServiceKind, User,
@@ -27,63 +31,99 @@ use crate::{
     utils::{escape_untrusted_text, shell_parser::parse_shell_args},
 };
 use lib::bot::BotMessage;
+use pool::SandboxPool;
+use quota::{
+    Budget, BudgetLimits, ExecutionQuotas, QuotaExhausted, SandboxQuotas, INSTRUCTION_CHUNK,
+};
 use state::{LuaState, SandboxMsg, SandboxTerminationReason};
 
+// How often a running sandbox evaluation checks if its message was deleted
+const ABORT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long the lua shutdown hooks can run before the module gives up on them
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub type LuaSandboxReplies = Mutex<LruCache<MessageId, (bool, Vec<(ChannelId, MessageId)>)>>;
 
 pub struct LuaModule {
     bot: Arc<Bot>,
     settings: Arc<LuaModuleSettings>,
     bot_state: Arc<Mutex<LuaState>>,
-    sandbox_state: Arc<Mutex<LuaState>>,
+    sandbox_pool: Arc<SandboxPool>,
+    sandbox_quotas: SandboxQuotas,
     lua_sandbox_replies: Arc<LuaSandboxReplies>,
 }
 
+/// The `[modules.lua]` section of the config
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct LuaModuleConfig {
+    /// Number of sandbox states, code from different servers can run concurrently on separate states
+    pub sandbox_pool_size: usize,
+    /// Memory a single sandbox execution can allocate in MiB
+    pub sandbox_memory_limit: usize,
+}
+
+impl LuaModuleConfig {
+    fn sandbox_memory_limit_bytes(&self) -> usize {
+        self.sandbox_memory_limit.saturating_mul(1024 * 1024)
+    }
+}
+
+impl Default for LuaModuleConfig {
+    fn default() -> LuaModuleConfig {
+        LuaModuleConfig {
+            sandbox_pool_size: 4,
+            sandbox_memory_limit: 64,
+        }
+    }
+}
+
 settings! {
     LuaModuleSettings,
     LuaModule,
     {
-        enable: bool => (true, SettingFlags::empty(), "Enable the lua module", []),
-        prefix: String => ("&".into(), SettingFlags::empty(), "Set the message prefix for lua commands", [max_len => 8]),
+        prefix: String => ("&".into(), SettingFlags::USER_OVERRIDE, "Set the message prefix for lua commands", [max_len => 8]),
         always_eval: bool => (false, SettingFlags::empty(), "Evaluate all messages in the sandbox", []),
-        lua_prefix: String => ("]".into(), SettingFlags::empty(), "Set the lua prefix for runnning lua code in the sandbox with errors", [max_len => 8]),
-        spammy_commands: bool => (true, SettingFlags::empty(), "Enable spammy commands", [])
+        lua_prefix: String => ("]".into(), SettingFlags::USER_OVERRIDE, "Set the lua prefix for runnning lua code in the sandbox with errors", [max_len => 8]),
+        spammy_commands: bool => (true, SettingFlags::empty(), "Enable spammy commands", []),
+        locale: Choice => (Choice(DEFAULT_LOCALE.into()), SettingFlags::SERVER_OVERRIDE, "Language of the messages sent by the bot", [dynamic_choices => available_locales]),
+        quota_user_executions: i64 => (20, SettingFlags::empty(), "Sandbox executions per minute for a user", [min => 1, max => 600]),
+        quota_user_instructions: i64 => (100_000_000, SettingFlags::empty(), "Sandbox instructions per minute for a user", [min => INSTRUCTION_CHUNK as i64, max => 1_000_000_000]),
+        quota_user_http_calls: i64 => (10, SettingFlags::empty(), "Sandbox http calls per minute for a user", [min => 1, max => 120]),
+        quota_user_image_operations: i64 => (40, SettingFlags::empty(), "Sandbox image operations per minute for a user", [min => 1, max => 600]),
+        quota_channel_executions: i64 => (60, SettingFlags::empty(), "Sandbox executions per minute in a channel", [min => 1, max => 1_200]),
+        quota_channel_instructions: i64 => (300_000_000, SettingFlags::empty(), "Sandbox instructions per minute in a channel", [min => INSTRUCTION_CHUNK as i64, max => 3_000_000_000]),
+        quota_channel_http_calls: i64 => (30, SettingFlags::empty(), "Sandbox http calls per minute in a channel", [min => 1, max => 240]),
+        quota_channel_image_operations: i64 => (120, SettingFlags::empty(), "Sandbox image operations per minute in a channel", [min => 1, max => 1_200]),
+        quota_trusted_multiplier: i64 => (4, SettingFlags::empty(), "Multiplier of the user sandbox quotas for trusted users and above", [min => 1, max => 10]),
+        output_attachment: bool => (true, SettingFlags::empty(), "Send sandbox output that does not fit in messages as a text file", []),
+        output_attachment_limit: i64 => (1_048_576, SettingFlags::empty(), "Maximum size in bytes of a sandbox output file", [min => 1, max => 8_388_608])
     }
 }
 
 #[async_trait]
 impl Module for LuaModule {
-    const KIND: ModuleKind = ModuleKind::Lua;
     const ID: &'static str = "lua";
     const NAME: &'static str = "Lua";
 
-    type ModuleConfig = ();
+    type ModuleConfig = LuaModuleConfig;
     type ModuleSettings = LuaModuleSettings;
 
-    async fn load(bot: Arc<Bot>, _config: ()) -> Result<Arc<LuaModule>> {
+    async fn load(bot: Arc<Bot>, config: LuaModuleConfig) -> Result<Arc<LuaModule>> {
         let lua_sandbox_replies = Arc::new(Mutex::new(LruCache::new(64)));
-        let sandbox_state = Arc::new(Mutex::new(LuaState::create_state(&bot, true, None)?));
+        let sandbox_pool = Arc::new(SandboxPool::new(
+            &bot,
+            config.sandbox_pool_size,
+            config.sandbox_memory_limit_bytes(),
+        )?);
         let bot_state = Arc::new(Mutex::new(LuaState::create_state(
             &bot,
             false,
-            Some((sandbox_state.clone(), lua_sandbox_replies.clone())),
+            Some((sandbox_pool.clone(), lua_sandbox_replies.clone())),
         )?));
 
-        let bot_state2 = bot_state.clone();
-        let sandbox_state2 = sandbox_state.clone();
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_millis(50));
-
-            loop {
-                interval.tick().await;
-                if let Err(err) = bot_state2.lock_arc().await.think() {
-                    println!("error: {}", err.to_string());
-                }
-                if let Err(err) = sandbox_state2.lock_arc().await.think() {
-                    println!("error: {}", err.to_string());
-                }
-            }
-        });
+        tokio::spawn(state::drive(Arc::downgrade(&bot_state), |_| {}));
 
         bot_state.lock_arc().await.on_loaded()?;
 
@@ -91,13 +131,23 @@ impl Module for LuaModule {
             bot: bot.clone(),
             settings: LuaModuleSettings::create(bot)?,
             bot_state,
-            sandbox_state,
+            sandbox_pool,
+            sandbox_quotas: SandboxQuotas::default(),
             lua_sandbox_replies,
         }))
     }
 
     async fn unload(&self) -> Result<()> {
-        while self.bot_state.clone().lock_arc().await.shutdown()? {}
+        self.shutdown_bot_state("the shutdown").await
+    }
+
+    async fn reload_config(&self, config: LuaModuleConfig) -> Result<()> {
+        self.sandbox_pool
+            .set_run_memory_limit(config.sandbox_memory_limit_bytes());
+
+        if config.sandbox_pool_size.max(1) != self.sandbox_pool.size() {
+            self.sandbox_pool.resize(config.sandbox_pool_size)?;
+        }
 
         Ok(())
     }
@@ -128,7 +178,7 @@ impl Module for LuaModule {
         let prefix = self
             .settings
             .prefix
-            .value(server.id(), channel.id())
+            .value(server.id(), channel.id(), Some(user.uid))
             .await?;
 
         let content = msg.content();
@@ -153,7 +203,7 @@ impl Module for LuaModule {
         let lua_prefix = self
             .settings
             .lua_prefix
-            .value(server.id(), channel.id())
+            .value(server.id(), channel.id(), Some(user.uid))
             .await?;
 
         match content.strip_prefix(&lua_prefix) {
@@ -167,7 +217,7 @@ impl Module for LuaModule {
         if self
             .settings
             .always_eval
-            .value(server.id(), channel.id())
+            .value(server.id(), channel.id(), Some(user.uid))
             .await?
         {
             let text = content.to_string();
@@ -207,7 +257,7 @@ impl Module for LuaModule {
         let prefix = self
             .settings
             .prefix
-            .value(server.id(), channel.id())
+            .value(server.id(), channel.id(), Some(user.uid))
             .await?;
 
         let content = msg.content();
@@ -253,10 +303,6 @@ impl Module for LuaModule {
         Ok(())
     }
 
-    async fn enabled(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool> {
-        self.settings.enable.value(server_id, channel_id).await
-    }
-
     fn settings(&self) -> &Arc<LuaModuleSettings> {
         &self.settings
     }
@@ -268,6 +314,23 @@ impl LuaModule {
         msg: Arc<dyn Message<impl Service>>,
         rest: String,
         edited: bool,
+    ) -> Result<()> {
+        let command = rest
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        self.run_command(msg, rest, edited)
+            .await
+            .map_err(|err| CommandError::wrap(command, err))
+    }
+
+    async fn run_command(
+        &self,
+        msg: Arc<dyn Message<impl Service>>,
+        rest: String,
+        edited: bool,
     ) -> Result<()> {
         let args = parse_shell_args(
             msg.service()
@@ -280,15 +343,26 @@ impl LuaModule {
         let sender = lua_state.async_sender();
         let bot_msg = BotMessage::from_msg(self.bot.clone(), sender, &msg).await?;
 
+        let user = self
+            .bot
+            .db()
+            .get_user_from_service_user_id(msg.author().id())
+            .await?;
         let channel = msg.channel().await?;
         let server = channel.server().await?;
         let spammy_commands = self
             .settings
             .spammy_commands
-            .value(server.id(), channel.id())
+            .value(server.id(), channel.id(), Some(user.uid))
             .await?;
+        let locale = self
+            .settings
+            .locale
+            .value(server.id(), channel.id(), Some(user.uid))
+            .await?
+            .0;
 
-        let res = lua_state.run_bot_command(bot_msg, args, edited, spammy_commands);
+        let res = lua_state.run_bot_command(bot_msg, args, edited, spammy_commands, locale);
         drop(lua_state);
 
         if let Err(err) = res {
@@ -301,8 +375,44 @@ impl LuaModule {
         Ok(())
     }
 
-    async fn restart_sandbox(&self) -> Result<()> {
-        *self.get_sandbox_state().await? = LuaState::create_state(&self.bot, true, None)?;
+    pub async fn restart_sandbox(&self) -> Result<()> {
+        self.sandbox_pool.restart().await
+    }
+
+    // Keep resuming the lua shutdown hooks until they have finished or the timeout is reached
+    async fn shutdown_bot_state(&self, action: &str) -> Result<()> {
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+
+        while self.bot_state.clone().lock_arc().await.shutdown()? {
+            if Instant::now() >= deadline {
+                log_warn!(
+                    "modules/lua",
+                    "Lua shutdown hooks did not finish within {:?}, continuing {}",
+                    SHUTDOWN_TIMEOUT,
+                    action
+                );
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        Ok(())
+    }
+
+    // Run the shutdown hooks of the bot state and replace it with a freshly loaded one
+    pub async fn reload_bot_state(&self) -> Result<()> {
+        self.shutdown_bot_state("the reload").await?;
+
+        let state = LuaState::create_state(
+            &self.bot,
+            false,
+            Some((self.sandbox_pool.clone(), self.lua_sandbox_replies.clone())),
+        )?;
+
+        let mut bot_state = self.get_bot_state().await?;
+        *bot_state = state;
+        bot_state.on_loaded()?;
 
         Ok(())
     }
@@ -343,11 +453,43 @@ impl LuaModule {
     ) -> Result<()> {
         let code = trim_codeblocks(msg.service().kind(), code);
 
-        let lua_state = self.get_sandbox_state().await?;
+        let channel = msg.channel().await?;
+        let server = channel.server().await?;
+        let locale = self
+            .settings
+            .locale
+            .value(server.id(), channel.id(), None)
+            .await?
+            .0;
+        let catalog = self.bot.catalog();
+        let t = |key: &str| catalog.t(&locale, key, &[]);
+
+        let user = self
+            .bot
+            .db()
+            .get_user_from_service_user_id(msg.author().id())
+            .await?;
+        let quotas = self
+            .execution_quotas(server.id(), channel.id(), &user)
+            .await?;
+
+        if let Err(exhausted) = quotas.take(Budget::Executions) {
+            channel
+                .send(
+                    quota_message(&catalog, &locale, &exhausted),
+                    MessageSettings::default(),
+                )
+                .await?;
+
+            return Ok(());
+        }
+
+        let lua_state = self.sandbox_pool.get(Some(server.id())).await;
 
         let sender = lua_state.async_sender();
         let bot_msg = BotMessage::from_msg(self.bot.clone(), sender, &msg).await?;
-        let (sandbox_state, recv) = match lua_state.run_sandboxed(&code, bot_msg, None) {
+        let (sandbox_state, mut recv) = match lua_state.run_sandboxed(&code, bot_msg, None, quotas)
+        {
             Ok(recv) => recv,
             Err(_err) => {
                 return Ok(());
@@ -356,40 +498,89 @@ impl LuaModule {
 
         drop(lua_state);
 
+        let attach_output = self
+            .settings
+            .output_attachment
+            .value(server.id(), channel.id(), None)
+            .await?;
+        let attachment_limit = self
+            .settings
+            .output_attachment_limit
+            .value(server.id(), channel.id(), None)
+            .await?
+            .max(1) as usize;
+
         let mut buffer: Vec<String> = Vec::new();
         let mut last_msg = Instant::now();
         let mut has_messaged = false; // only wait 100ms for the first message
         let mut aborting = None;
+        // Output past the message limits, sent as a file when the execution ends
+        let mut overflow: Option<String> = None;
+        let mut done = false;
 
-        while aborting.is_none() {
+        while aborting.is_none() && !done {
             // Check if it should abort
             if self.should_abort_sandbox(msg.id()).await {
                 return Ok(());
             }
 
-            match recv.try_recv() {
-                Ok(out) => match out {
+            let flush_wait = if has_messaged {
+                Duration::from_millis(500)
+            } else {
+                Duration::from_millis(100)
+            };
+
+            // Wake up for the next output, when the buffer should be sent or to check for an abort
+            let timeout = if buffer.is_empty() {
+                ABORT_CHECK_INTERVAL
+            } else {
+                flush_wait.saturating_sub(last_msg.elapsed())
+            };
+
+            match tokio::time::timeout(timeout, recv.recv()).await {
+                Ok(Some(out)) => match out {
                     SandboxMsg::Out(out) => {
                         if !out.is_empty() {
-                            let mut lines =
-                                out.split('\n').map(|l| l.to_string()).collect::<Vec<_>>();
+                            let spill = if overflow.is_some() {
+                                true
+                            } else if out.chars().count() > 2000 && buffer.is_empty() {
+                                if !attach_output {
+                                    buffer.extend(out.split('\n').map(|l| l.to_string()));
+                                }
 
-                            if out.chars().count() > 2000 && buffer.is_empty() {
-                                buffer.append(&mut lines);
+                                attach_output
                             } else {
                                 let lines_left = sandbox_state.limits.lines_left();
 
                                 if lines_left > 0 {
-                                    buffer.append(&mut lines);
+                                    buffer.extend(out.split('\n').map(|l| l.to_string()));
                                     sandbox_state.limits.set_lines_left(lines_left - 1);
+                                    false
+                                } else if attach_output {
+                                    true
                                 } else {
-                                    aborting =
-                                        Some("error: too many lines has been output, aborting");
+                                    aborting = Some(t("sandbox.too_many_lines"));
+                                    false
+                                }
+                            };
+
+                            if spill {
+                                // Output that has not been sent yet goes in the file first
+                                let overflow = overflow.get_or_insert_with(|| {
+                                    buffer.drain(..).collect::<Vec<_>>().join("\n")
+                                });
+
+                                if !push_output(overflow, &out, attachment_limit) {
+                                    aborting = Some(t("sandbox.output_truncated"));
                                 }
                             }
                         }
                     }
                     SandboxMsg::Error(err) => {
+                        // Errors end the execution, no termination message follows
+                        done = true;
+                        METRICS.sandbox_executions.inc(&["error"]);
+
                         if errors && !err.is_empty() {
                             let reply = msg
                                 .channel()
@@ -397,7 +588,7 @@ impl LuaModule {
                                 .send(
                                     escape_untrusted_text(
                                         msg.service().kind(),
-                                        format!("error: {}", err),
+                                        catalog.t(&locale, "sandbox.error", &[("error", err)]),
                                     ),
                                     MessageSettings::default(),
                                 )
@@ -407,55 +598,76 @@ impl LuaModule {
                                 .await?;
                         }
                     }
-                    SandboxMsg::Terminated(reason) => match reason {
-                        SandboxTerminationReason::Done => {}
-                        SandboxTerminationReason::ExecutionQuota => {
-                            let reply = msg
-                                .channel()
-                                .await?
-                                .send(
-                                    "Execution quota exceeded, terminated execution",
-                                    MessageSettings::default(),
-                                )
-                                .await?;
-                            self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
-                                .await?;
+                    SandboxMsg::Terminated(reason) => {
+                        METRICS.sandbox_executions.inc(&[reason.as_str()]);
+
+                        match reason {
+                            SandboxTerminationReason::Done => done = true,
+                            SandboxTerminationReason::ExecutionQuota => {
+                                let reply = msg
+                                    .channel()
+                                    .await?
+                                    .send(t("sandbox.execution_quota"), MessageSettings::default())
+                                    .await?;
+                                self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
+                                    .await?;
+
+                                break;
+                            }
+                            SandboxTerminationReason::TimeLimit => {
+                                let reply = msg
+                                    .channel()
+                                    .await?
+                                    .send(t("sandbox.time_limit"), MessageSettings::default())
+                                    .await?;
 
-                            break;
-                        }
-                        SandboxTerminationReason::TimeLimit => {
-                            let reply = msg
-                                .channel()
-                                .await?
-                                .send(
-                                    "Execution time limit reached, terminated execution",
-                                    MessageSettings::default(),
-                                )
-                                .await?;
+                                self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
+                                    .await?;
 
-                            self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
-                                .await?;
+                                break;
+                            }
+                            SandboxTerminationReason::MemoryLimit => {
+                                let reply = msg
+                                    .channel()
+                                    .await?
+                                    .send(t("sandbox.memory_limit"), MessageSettings::default())
+                                    .await?;
 
-                            break;
+                                self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
+                                    .await?;
+
+                                break;
+                            }
+                            SandboxTerminationReason::QuotaExhausted(exhausted) => {
+                                let reply = msg
+                                    .channel()
+                                    .await?
+                                    .send(
+                                        quota_message(&catalog, &locale, &exhausted),
+                                        MessageSettings::default(),
+                                    )
+                                    .await?;
+
+                                self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
+                                    .await?;
+
+                                break;
+                            }
                         }
-                    },
+                    }
                 },
-                Err(TryRecvError::Empty) => {
-                    tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(None) => {
+                    METRICS.sandbox_executions.inc(&["disconnected"]);
+                    done = true;
                 }
-                Err(TryRecvError::Disconnected) => break,
+                Err(_) => {}
             }
 
             // Empty the buffer
             if !buffer.is_empty() {
                 let elapsed = last_msg.elapsed();
-                let wait = if has_messaged {
-                    Duration::from_millis(500)
-                } else {
-                    Duration::from_millis(100)
-                };
 
-                if elapsed > wait || aborting.is_some() {
+                if elapsed >= flush_wait || aborting.is_some() || done {
                     let mut out = String::new();
 
                     let mut characters_left = sandbox_state.limits.characters_left();
@@ -465,7 +677,15 @@ impl LuaModule {
                     if !has_messaged {
                         let joined = lines.join("\n");
                         if joined.chars().count() > 2000 {
-                            out.push_str(&escape_untrusted_text(msg.service().kind(), joined));
+                            if attach_output {
+                                let overflow = overflow.get_or_insert_with(String::new);
+
+                                if !push_output(overflow, &joined, attachment_limit) {
+                                    aborting = Some(t("sandbox.output_truncated"));
+                                }
+                            } else {
+                                out.push_str(&escape_untrusted_text(msg.service().kind(), joined));
+                            }
                             lines.clear();
                         }
                     }
@@ -481,22 +701,34 @@ impl LuaModule {
                             if lines.peek().is_some() {
                                 out.push_str("\n");
                             }
+                        } else if attach_output {
+                            let overflow = overflow.get_or_insert_with(String::new);
+
+                            for line in std::iter::once(line).chain(lines.by_ref()) {
+                                if !push_output(overflow, &line, attachment_limit) {
+                                    aborting = Some(t("sandbox.output_truncated"));
+                                    break;
+                                }
+                            }
+                            break;
                         } else {
-                            aborting = Some("error: too many characters has been output, aborting");
+                            aborting = Some(t("sandbox.too_many_characters"));
                             break;
                         }
                     }
 
                     sandbox_state.limits.set_characters_left(characters_left);
 
-                    let reply = msg
-                        .channel()
-                        .await?
-                        .send(out, MessageSettings::default())
-                        .await?;
+                    if !out.is_empty() {
+                        let reply = msg
+                            .channel()
+                            .await?
+                            .send(out, MessageSettings::default())
+                            .await?;
 
-                    self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
-                        .await?;
+                        self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
+                            .await?;
+                    }
 
                     last_msg = Instant::now();
                     has_messaged = true;
@@ -504,6 +736,26 @@ impl LuaModule {
             }
         }
 
+        if let Some(output) = overflow {
+            let content = aborting
+                .take()
+                .unwrap_or_else(|| t("sandbox.output_attached"));
+            let reply = msg
+                .channel()
+                .await?
+                .send(
+                    content,
+                    MessageSettings {
+                        attachments: vec![("output.txt".into(), output.into_bytes())],
+                        ..Default::default()
+                    },
+                )
+                .await?;
+
+            self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
+                .await?;
+        }
+
         if let Some(aborting) = aborting {
             let reply = msg
                 .channel()
@@ -518,13 +770,128 @@ impl LuaModule {
         Ok(())
     }
 
+    /// The rolling sandbox budgets of a user running code in a channel
+    pub async fn execution_quotas(
+        &self,
+        server_id: ServerId,
+        channel_id: ChannelId,
+        user: &DbUser,
+    ) -> Result<ExecutionQuotas> {
+        let s = &self.settings;
+        let value = |setting: i64| setting.clamp(1, u32::MAX as i64) as u32;
+
+        let user_limits = BudgetLimits {
+            executions: value(
+                s.quota_user_executions
+                    .value(server_id, channel_id, None)
+                    .await?,
+            ),
+            instructions: value(
+                s.quota_user_instructions
+                    .value(server_id, channel_id, None)
+                    .await?,
+            ),
+            http_calls: value(
+                s.quota_user_http_calls
+                    .value(server_id, channel_id, None)
+                    .await?,
+            ),
+            image_operations: value(
+                s.quota_user_image_operations
+                    .value(server_id, channel_id, None)
+                    .await?,
+            ),
+        };
+        let channel_limits = BudgetLimits {
+            executions: value(
+                s.quota_channel_executions
+                    .value(server_id, channel_id, None)
+                    .await?,
+            ),
+            instructions: value(
+                s.quota_channel_instructions
+                    .value(server_id, channel_id, None)
+                    .await?,
+            ),
+            http_calls: value(
+                s.quota_channel_http_calls
+                    .value(server_id, channel_id, None)
+                    .await?,
+            ),
+            image_operations: value(
+                s.quota_channel_image_operations
+                    .value(server_id, channel_id, None)
+                    .await?,
+            ),
+        };
+        let multiplier = value(
+            s.quota_trusted_multiplier
+                .value(server_id, channel_id, None)
+                .await?,
+        );
+
+        Ok(self.sandbox_quotas.execution(
+            user.uid,
+            user_limits.for_role(&user.role, multiplier),
+            channel_id,
+            channel_limits,
+        ))
+    }
+
     pub async fn get_bot_state(&self) -> Result<MutexGuardArc<LuaState>> {
         Ok(self.bot_state.lock_arc().await)
     }
+}
+
+// The locale setting accepts the locales which have a catalog
+fn available_locales(bot: &Bot) -> Vec<String> {
+    bot.catalog()
+        .locales()
+        .into_iter()
+        .map(|locale| locale.to_string())
+        .collect()
+}
+
+fn quota_message(catalog: &Catalog, locale: &str, exhausted: &QuotaExhausted) -> String {
+    let budget = catalog.t(
+        locale,
+        &format!("sandbox.budgets.{}", exhausted.budget.as_str()),
+        &[],
+    );
+    let key = if exhausted.channel {
+        "sandbox.channel_quota"
+    } else {
+        "sandbox.user_quota"
+    };
+
+    catalog.t(
+        locale,
+        key,
+        &[
+            ("budget", budget),
+            ("wait", exhausted.wait_secs().to_string()),
+        ],
+    )
+}
+
+/// Append a line of sandbox output to a file, returns false once it was cut off at `limit` bytes
+fn push_output(output: &mut String, text: &str, limit: usize) -> bool {
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    output.push_str(text);
+
+    if output.len() > limit {
+        let mut end = limit;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
 
-    pub async fn get_sandbox_state(&self) -> Result<MutexGuardArc<LuaState>> {
-        Ok(self.sandbox_state.lock_arc().await)
+        return false;
     }
+
+    true
 }
 
 fn trim_codeblocks(service: ServiceKind, text: String) -> String {
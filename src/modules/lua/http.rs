@@ -1,4 +1,3 @@
-use crossbeam::channel::Sender;
 use futures::{StreamExt, TryStreamExt};
 use hyper::{body::Bytes, Body, Client, Request, Response};
 use hyper_tls::HttpsConnector;
@@ -12,7 +11,17 @@ use std::{
 };
 use thiserror::Error;
 
-use super::state::{LuaAsyncCallback, SandboxState};
+use super::{
+    quota::Budget,
+    state::{LuaAsyncSender, SandboxState},
+};
+use crate::metrics::METRICS;
+
+fn record_fetch<T>(state: &str, res: &Result<T, hyper::Error>) {
+    METRICS
+        .http_fetches
+        .inc(&[state, if res.is_ok() { "ok" } else { "error" }]);
+}
 
 pub fn http_fetch<'a>(
     state: &'a Lua,
@@ -39,6 +48,10 @@ pub fn http_fetch<'a>(
             .store(calls_left - 1, Ordering::Relaxed)
     }
 
+    if let Err(exhausted) = sandbox_state.take_quota(Budget::HttpCalls) {
+        return Err(LuaError::RuntimeError(exhausted.to_string()));
+    }
+
     // Parse url
     let url = match url::Url::parse(url) {
         Ok(url) => url,
@@ -119,7 +132,10 @@ pub fn http_fetch<'a>(
             // Rate limit how often http calls can be made
             http_rate_limiter.until_ready().await;
 
-            match client.request(req).await {
+            let res = client.request(req).await;
+            record_fetch("sandbox", &res);
+
+            match res {
                 Ok(mut res) => {
                     let body = res
                         .body_mut()
@@ -171,7 +187,7 @@ pub fn http_fetch<'a>(
 }
 
 // bot state only
-pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result<()> {
+pub fn lib_http(state: &Lua, sender: LuaAsyncSender) -> anyhow::Result<()> {
     let http = state.create_table()?;
 
     // http.fetch
@@ -254,13 +270,16 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
                 sender,
                 (max_size, url, sender.clone()),
                 async move {
-                    match client.request(req).await {
+                    let res = client.request(req).await;
+                    record_fetch("bot", &res);
+
+                    match res {
                         Ok(res) => Ok(res),
                         Err(err) => Err(err),
                     }
                 },
                 |state,
-                 data: (usize, String, Sender<LuaAsyncCallback>),
+                 data: (usize, String, LuaAsyncSender),
                  res: Result<Response<Body>, hyper::Error>| {
                     let res = res?;
                     let (max_size, url, sender) = data;
@@ -282,7 +301,7 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
                     tbl.set("statusText", res.status().canonical_reason())?;
                     tbl.set("url", state.create_string(&url)?)?;
 
-                    fn create_next_body(state: &Lua, sender: Sender<LuaAsyncCallback>, max_size: usize, bytes_received: usize, mut body: Body) -> anyhow::Result<LuaTable> {
+                    fn create_next_body(state: &Lua, sender: LuaAsyncSender, max_size: usize, bytes_received: usize, mut body: Body) -> anyhow::Result<LuaTable> {
                         Ok(create_lua_future!(
                             state,
                             sender,
@@ -292,7 +311,7 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
 
                                 (body, bytes)
                             },
-                            |state, data: (usize, usize, Sender<LuaAsyncCallback>), res: (Body, Option<Result<Bytes, hyper::Error>>)| {
+                            |state, data: (usize, usize, LuaAsyncSender), res: (Body, Option<Result<Bytes, hyper::Error>>)| {
                                 let (max_size, mut bytes_received, sender) = data;
                                 if let Some(data) = res.1 {
                                     let data = data?;
@@ -330,7 +349,10 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
                 sender,
                 (url,),
                 async move {
-                    match client.request(req).await {
+                    let res = client.request(req).await;
+                    record_fetch("bot", &res);
+
+                    match res {
                         Ok(mut res) => {
                             let body = res
                                 .body_mut()
@@ -1,18 +1,24 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use mlua::{
-    prelude::{LuaError, LuaMultiValue},
-    Lua,
+    prelude::{LuaError, LuaMultiValue, LuaValue},
+    Lua, Table,
 };
 use std::{
-    fs::read_to_string,
     path::{Component, Path, PathBuf},
+    sync::Mutex,
 };
 
+use crate::utils::vfs::Vfs;
+
 #[macro_use]
 pub mod r#async;
 pub mod bot;
 pub mod image;
+pub mod kv;
+pub mod log;
 pub mod os;
+pub mod owned;
+pub mod scripts;
 pub mod tags;
 
 fn remove_upwards_components(path: &Path) -> PathBuf {
@@ -31,7 +37,7 @@ fn remove_upwards_components(path: &Path) -> PathBuf {
 
 pub fn include_lua<'a>(
     state: &'a Lua,
-    root_path: &Path,
+    vfs: &Vfs,
     path: &str,
 ) -> Result<Option<LuaMultiValue<'a>>> {
     let path = remove_upwards_components(Path::new(path));
@@ -41,27 +47,25 @@ pub fn include_lua<'a>(
         .components()
         .any(|c| c.as_os_str() == "**" || c.as_os_str().to_string_lossy().starts_with("*."))
     {
-        let path = root_path.join(path);
         let pattern = path.as_os_str().to_string_lossy().to_string();
-        let paths = glob::glob(pattern.as_ref())?;
 
-        for path in paths {
-            let path = path?;
-            let name = Path::new(&path).strip_prefix(root_path).unwrap();
-            let source = read_to_string(&path)?;
+        for path in vfs.glob(&pattern)? {
+            let source = vfs.read_to_string(&path)?;
             state
                 .load(&source)
-                .set_name(name.as_os_str().to_string_lossy())?
+                .set_name(path.as_os_str().to_string_lossy())?
                 .eval()?;
         }
     } else {
-        let lua_path = path.clone();
-        let path = root_path.join(path);
+        // Running the entry point again reloads the bot, the required modules have to be read again
+        if path == Path::new("bot.lua") {
+            clear_loaded_modules(state)?;
+        }
 
-        let source = read_to_string(path)?;
+        let source = vfs.read_to_string(&path)?;
         let result = state
             .load(&source)
-            .set_name(lua_path.as_os_str().to_string_lossy())?
+            .set_name(path.as_os_str().to_string_lossy())?
             .eval()?;
 
         return Ok(Some(result));
@@ -70,11 +74,11 @@ pub fn include_lua<'a>(
     Ok(None)
 }
 
-pub fn lib_include(root_path: PathBuf, state: &Lua) -> Result<()> {
+pub fn lib_include(vfs: Vfs, state: &Lua) -> Result<()> {
     let include_fn = state.create_function(move |state, path: String| {
-        include_lua(state, root_path.as_path(), &path)
+        include_lua(state, &vfs, &path)
             .map_err(|err| {
-                println!("error including \"{}\": {}", path, err.to_string());
+                log_error!("modules/lua", "error including \"{}\": {}", path, err.to_string());
 
                 LuaError::SyntaxError {
                     message: err.to_string(),
@@ -88,3 +92,235 @@ pub fn lib_include(root_path: PathBuf, state: &Lua) -> Result<()> {
 
     Ok(())
 }
+
+// Registry tables of the values returned by modules loaded with `require` and the paths they were
+// loaded from, keyed by module name
+const LOADED_MODULES: &str = "__LOADED_MODULES";
+const LOADED_MODULE_PATHS: &str = "__LOADED_MODULE_PATHS";
+
+/// Path of a module like `lib.json`, either `lib/json.lua` or `lib/json/init.lua`
+fn module_path(vfs: &Vfs, name: &str) -> Result<PathBuf> {
+    let valid = name.split('.').all(|part| {
+        !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    });
+
+    if !valid {
+        return Err(anyhow!("invalid module name \"{}\"", name));
+    }
+
+    let base = name.replace('.', "/");
+    let candidates = [
+        PathBuf::from(format!("{}.lua", base)),
+        Path::new(&base).join("init.lua"),
+    ];
+
+    candidates
+        .iter()
+        .find(|path| vfs.exists(path))
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "module \"{}\" not found, tried {}",
+                name,
+                candidates
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+/// Load a module once per state like `require`, returning its value and the path it was loaded from
+///
+/// `loading` is the stack of modules currently being loaded, used to detect cycles.
+pub fn require_lua<'a>(
+    state: &'a Lua,
+    vfs: &Vfs,
+    loading: &Mutex<Vec<String>>,
+    name: &str,
+) -> Result<LuaMultiValue<'a>> {
+    let loaded: Table = state.named_registry_value(LOADED_MODULES)?;
+    let loaded_paths: Table = state.named_registry_value(LOADED_MODULE_PATHS)?;
+
+    // Checked before resolving the path so cached modules don't touch the disk
+    match loaded.get::<_, LuaValue>(name)? {
+        LuaValue::Nil => {}
+        cached => {
+            return Ok(LuaMultiValue::from_vec(vec![
+                cached,
+                loaded_paths.get::<_, LuaValue>(name)?,
+            ]))
+        }
+    }
+
+    let path = module_path(vfs, name)?;
+    let path_str = path.to_string_lossy().to_string();
+
+    {
+        let mut loading = loading.lock().unwrap();
+
+        if let Some(start) = loading.iter().position(|n| n == name) {
+            let mut cycle = loading[start..].to_vec();
+            cycle.push(name.to_string());
+
+            return Err(anyhow!(
+                "cycle detected while requiring \"{}\": {}",
+                name,
+                cycle.join(" -> ")
+            ));
+        }
+
+        loading.push(name.to_string());
+    }
+
+    let res = vfs.read_to_string(&path).and_then(|source| {
+        Ok(state
+            .load(&source)
+            .set_name(&path_str)?
+            .call::<_, LuaValue>((name, path_str.as_str()))?)
+    });
+
+    loading.lock().unwrap().pop();
+
+    // Like `package.loaded`, modules returning nothing are stored as true
+    let value = match res? {
+        LuaValue::Nil => LuaValue::Boolean(true),
+        value => value,
+    };
+    loaded.set(name, value.clone())?;
+    loaded_paths.set(name, path_str.as_str())?;
+
+    Ok(LuaMultiValue::from_vec(vec![
+        value,
+        LuaValue::String(state.create_string(&path_str)?),
+    ]))
+}
+
+/// Forget the modules loaded with `require` so they are loaded again from the files
+pub fn clear_loaded_modules(state: &Lua) -> Result<()> {
+    state.set_named_registry_value(LOADED_MODULES, state.create_table()?)?;
+    state.set_named_registry_value(LOADED_MODULE_PATHS, state.create_table()?)?;
+
+    Ok(())
+}
+
+pub fn lib_require(vfs: Vfs, state: &Lua) -> Result<()> {
+    clear_loaded_modules(state)?;
+
+    let loading = Mutex::new(Vec::new());
+    let require_fn = state.create_function(move |state, name: String| {
+        require_lua(state, &vfs, &loading, &name).map_err(|err| {
+            log_error!("modules/lua", "error requiring \"{}\": {}", name, err.to_string());
+
+            LuaError::RuntimeError(err.to_string())
+        })
+    })?;
+
+    state.globals().set("require", require_fn)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::{prelude::LuaValue, Lua, Table};
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::Mutex,
+    };
+
+    use super::{clear_loaded_modules, lib_require, module_path, require_lua};
+    use crate::utils::vfs::Vfs;
+
+    // Not named "lua" so nothing falls back to the embedded files
+    fn test_vfs(name: &str, files: &[(&str, &str)]) -> (PathBuf, Vfs) {
+        let root = std::env::temp_dir().join(format!("kaito-{}-{}", name, std::process::id()));
+
+        for (path, source) in files {
+            let path = root.join("modules").join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+
+        let vfs = Vfs::share(&root, "modules");
+        (root, vfs)
+    }
+
+    #[test]
+    fn module_path_test() {
+        let (root, vfs) = test_vfs(
+            "module-path",
+            &[("lib/json.lua", ""), ("lib/lru/init.lua", "")],
+        );
+
+        assert_eq!(
+            module_path(&vfs, "lib.json").unwrap(),
+            Path::new("lib/json.lua")
+        );
+        assert_eq!(
+            module_path(&vfs, "lib.lru").unwrap(),
+            Path::new("lib/lru/init.lua")
+        );
+        assert!(module_path(&vfs, "lib.missing").is_err());
+        assert!(module_path(&vfs, "lib..json").is_err());
+        assert!(module_path(&vfs, "../lib/json").is_err());
+        assert!(module_path(&vfs, "").is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn require_test() {
+        let (root, vfs) = test_vfs(
+            "require",
+            &[
+                ("a.lua", "return require('b')"),
+                ("b.lua", "return require('a')"),
+                ("c.lua", "return { value = 1 }"),
+                ("d.lua", "local x = 1"),
+            ],
+        );
+
+        let lua = Lua::new();
+        lib_require(vfs.clone(), &lua).unwrap();
+
+        let err = lua.load("require('a')").exec().unwrap_err();
+        assert!(
+            err.to_string().contains("a -> b -> a"),
+            "unexpected error: {}",
+            err
+        );
+
+        // The cycle error doesn't leave modules on the loading stack
+        let loading = Mutex::new(Vec::new());
+        let res = require_lua(&lua, &vfs, &loading, "c").unwrap().into_vec();
+        let value = match &res[..] {
+            [LuaValue::Table(value), LuaValue::String(path)] => {
+                assert_eq!(path.to_str().unwrap(), "c.lua");
+                value.clone()
+            }
+            res => panic!("unexpected result: {:?}", res),
+        };
+        assert_eq!(value.get::<_, i64>("value").unwrap(), 1);
+
+        // Cached modules are returned without reading the files again
+        fs::remove_file(root.join("modules/c.lua")).unwrap();
+        let cached = lua
+            .load("local value, path = require('c') return value, path")
+            .eval::<(Table, String)>()
+            .unwrap();
+        assert_eq!(cached.0, value);
+        assert_eq!(cached.1, "c.lua");
+
+        // Like `package.loaded`, modules returning nothing are stored as true
+        assert!(lua.load("return require('d')").eval::<bool>().unwrap());
+
+        clear_loaded_modules(&lua).unwrap();
+        assert!(require_lua(&lua, &vfs, &loading, "c").is_err());
+        assert!(loading.lock().unwrap().is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,5 +1,4 @@
 use anyhow::Result;
-use crossbeam::channel::Sender;
 use mlua::{
     prelude::{LuaError, LuaMultiValue},
     Function, Lua, RegistryKey, Table,
@@ -13,7 +12,7 @@ use std::{
 };
 use thiserror::Error;
 
-use super::super::state::LuaAsyncCallback;
+use super::super::state::LuaAsyncSender;
 
 pub fn create_future(state: &Lua) -> Result<(RegistryKey, Table)> {
     let async_tbl: Table = state.globals().get("async")?;
@@ -43,7 +42,9 @@ macro_rules! create_lua_future {
 
         let sender = $sender.clone();
         let data = $data;
+        let task = sender.track();
         tokio::spawn(async move {
+            let _task = task;
             let fut_res = $fut.await;
 
             let callback: Box<dyn for<'c> FnOnce(&'c Lua) -> anyhow::Result<LuaMultiValue<'c>> + Send> = Box::new(move |state| {
@@ -70,7 +71,7 @@ macro_rules! create_lua_future {
 
 pub fn lib_async(
     state: &Lua,
-    sender: Sender<LuaAsyncCallback>,
+    sender: LuaAsyncSender,
     thread_id: Arc<AtomicU64>,
 ) -> Result<()> {
     let async_tbl = state.create_table()?;
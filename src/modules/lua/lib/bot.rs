@@ -1,32 +1,45 @@
 use anyhow::Result;
-use async_mutex::Mutex;
 use chrono::{NaiveDateTime, Utc};
-use crossbeam::channel::{Sender, TryRecvError};
 use futures::TryFutureExt;
 use mlua::{prelude::*, Error as LuaError, Lua, MetaMethod, Table, UserData, UserDataMethods};
-use std::{
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use std::{sync::Arc, time::Duration};
 
 use super::super::{
-    state::{get_sandbox_state, LuaAsyncCallback, LuaState, SandboxMsg, SandboxTerminationReason},
-    LuaSandboxReplies,
+    pool::SandboxPool,
+    quota::Budget,
+    state::{get_sandbox_state, LuaAsyncSender, SandboxMsg, SandboxTerminationReason},
+    LuaModule, LuaSandboxReplies,
 };
 use crate::{
     bot::{
-        db::{Uid, User as DbUser},
+        db::{
+            archive::{DbArchive, ImportSummary},
+            SettingOverride, Uid, User as DbUser,
+        },
         Bot, ROLES,
     },
+    config::ConfigReload,
+    i18n::DEFAULT_LOCALE,
+    metrics::METRICS,
     message::{Attachment, MessageEmbed, MessageSettings},
     services::{
         Channel, ChannelId, Message, MessageId, Server, ServerId, Service, ServiceFeatures,
         ServiceKind, Services, User, UserId,
     },
-    settings::SettingContext,
+    settings::{EffectiveSetting, SettingContext},
     utils::escape_untrusted_text,
 };
 
+// The setting context for "server", "channel" or "user" relative to the message
+fn setting_scope(msg: &BotMessage, scope: &str) -> Option<SettingContext> {
+    match scope {
+        "server" => Some(SettingContext::Server(msg.channel().server().id())),
+        "channel" => Some(SettingContext::Channel(msg.channel().id())),
+        "user" => Some(SettingContext::User(msg.author().uid())),
+        _ => None,
+    }
+}
+
 fn table_to_embed(tbl: LuaTable) -> Result<MessageEmbed> {
     let mut embed = MessageEmbed::default();
 
@@ -138,8 +151,8 @@ pub fn bot_flags(state: &Lua, bot_tbl: &LuaTable) -> Result<()> {
 pub fn lib_bot(
     state: &Lua,
     bot: &Arc<Bot>,
-    sender: Sender<LuaAsyncCallback>,
-    (sandbox_state, lua_sandbox_replies): (Arc<Mutex<LuaState>>, Arc<LuaSandboxReplies>),
+    sender: LuaAsyncSender,
+    (sandbox_pool, lua_sandbox_replies): (Arc<SandboxPool>, Arc<LuaSandboxReplies>),
 ) -> Result<()> {
     let bot_tbl = state.create_table()?;
 
@@ -153,8 +166,10 @@ pub fn lib_bot(
             sender2,
             (),
             async move {
-                if let Err(err) = ctx.modules().lua.module().restart_sandbox().await {
-                    println!("error restarting sandbox: {}", err.to_string());
+                if let Some(lua) = ctx.modules().get::<LuaModule>() {
+                    if let Err(err) = lua.restart_sandbox().await {
+                        log_error!("modules/lua", "error restarting sandbox: {}", err.to_string());
+                    }
                 }
             },
             |_state, _data: (), _res: ()| { Ok(()) }
@@ -164,6 +179,78 @@ pub fn lib_bot(
     })?;
     bot_tbl.set("restart_sandbox", bot_restart_sandbox_fn)?;
 
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let reload_config_fn = state.create_function(move |state, (): ()| {
+        let bot = bot2.clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move { bot.reload_config().await },
+            |state, _data: (), res: Result<ConfigReload>| {
+                let report = res?;
+                let tbl = state.create_table()?;
+
+                tbl.set("applied", report.applied)?;
+                tbl.set("restart_required", report.restart_required)?;
+
+                Ok(tbl)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    bot_tbl.set("reload_config", reload_config_fn)?;
+
+    // bot.t(key, args, locale), the locale defaults to the default locale of the catalog
+    let bot2 = bot.clone();
+    let t_fn = state.create_function(
+        move |_, (key, args, locale): (String, Option<LuaTable>, Option<String>)| {
+            let mut out = Vec::new();
+
+            if let Some(args) = args {
+                for pair in args.pairs::<String, LuaValue>() {
+                    let (name, value) = pair?;
+
+                    let value = match value {
+                        LuaValue::String(s) => s.to_str()?.to_string(),
+                        LuaValue::Integer(i) => i.to_string(),
+                        LuaValue::Number(n) => n.to_string(),
+                        LuaValue::Boolean(b) => b.to_string(),
+                        value => value.type_name().to_string(),
+                    };
+
+                    out.push((name, value));
+                }
+            }
+
+            let args = out
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.clone()))
+                .collect::<Vec<_>>();
+
+            Ok(bot2.catalog().t(
+                locale.as_deref().unwrap_or(DEFAULT_LOCALE),
+                &key,
+                &args,
+            ))
+        },
+    )?;
+    bot_tbl.set("t", t_fn)?;
+
+    let record_command_fn =
+        state.create_function(|_, (name, seconds, ok): (String, f64, bool)| {
+            METRICS
+                .lua_commands
+                .inc(&[&name, if ok { "ok" } else { "error" }]);
+            METRICS.lua_command_duration.observe(&[&name], seconds);
+
+            Ok(())
+        })?;
+    bot_tbl.set("record_command", record_command_fn)?;
+
     let bot2 = bot.clone();
     let sender2 = sender.clone();
     let delete_lua_replies_fn = state.create_function(move |state, message_id: String| {
@@ -452,6 +539,9 @@ pub fn lib_bot(
 
             info_tbl.set("name", info.name)?;
             info_tbl.set("help", info.help)?;
+            info_tbl.set("type", info.kind.as_str())?;
+            info_tbl.set("allowed", info.allowed)?;
+            info_tbl.set("user_override", info.user_override)?;
 
             tbl.raw_insert((idx + 1) as i64, info_tbl)?;
         }
@@ -460,13 +550,40 @@ pub fn lib_bot(
     })?;
     bot_tbl.set("list_settings", list_settings_fn)?;
 
+    let bot2 = bot.clone();
+    let list_modules_fn = state.create_function(move |state, (): ()| {
+        let tbl = state.create_table()?;
+
+        for (idx, (id, enabled)) in bot2.get_ctx().modules().list().into_iter().enumerate() {
+            let module_tbl = state.create_table()?;
+
+            module_tbl.set("id", id)?;
+            module_tbl.set("enabled", enabled)?;
+
+            tbl.raw_insert((idx + 1) as i64, module_tbl)?;
+        }
+
+        Ok(tbl)
+    })?;
+    bot_tbl.set("list_modules", list_modules_fn)?;
+
+    let bot2 = bot.clone();
+    let set_module_enabled_fn =
+        state.create_function(move |_, (module, enabled): (String, bool)| {
+            bot2.get_ctx()
+                .modules()
+                .set_enabled(&module, enabled)
+                .map_err(|err| LuaError::RuntimeError(err.to_string()))
+        })?;
+    bot_tbl.set("set_module_enabled", set_module_enabled_fn)?;
+
     let bot2 = bot.clone();
     let sender2 = sender.clone();
     let set_setting_fn = state.create_function(
         move |state,
-              (msg, server, module, setting, value): (
+              (msg, scope, module, setting, value): (
             LuaAnyUserData,
-            bool,
+            String,
             String,
             String,
             String,
@@ -484,19 +601,16 @@ pub fn lib_bot(
 
             let msg = msg.borrow::<BotMessage>()?.clone();
 
+            let ctx = match setting_scope(&msg, &scope) {
+                Some(ctx) => ctx,
+                None => return Ok(LuaMultiValue::from_vec(vec!["unknown scope".to_lua(state)?])),
+            };
+
             let fut = create_lua_future!(
                 state,
                 sender2,
                 (),
-                module_settings.set_setting(
-                    if server {
-                        SettingContext::Server(msg.channel().server().id())
-                    } else {
-                        SettingContext::Channel(msg.channel().id())
-                    },
-                    &setting,
-                    &value,
-                ),
+                module_settings.set_setting(ctx, &setting, &value),
                 |_state, _data: (), res: Result<()>| { res }
             );
 
@@ -508,6 +622,126 @@ pub fn lib_bot(
     )?;
     bot_tbl.set("set_setting", set_setting_fn)?;
 
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let get_setting_fn = state.create_function(
+        move |state, (msg, module, setting): (LuaAnyUserData, String, String)| {
+            let bot = bot2.clone();
+
+            let module_settings = match bot.get_ctx().modules().get_settings(&module) {
+                Some(settings) => settings,
+                None => {
+                    return Ok(LuaMultiValue::from_vec(vec![
+                        "unknown module".to_lua(state)?
+                    ]))
+                }
+            };
+
+            let msg = msg.borrow::<BotMessage>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                module_settings.get_setting(
+                    &setting,
+                    msg.channel().server().id(),
+                    msg.channel().id(),
+                    Some(msg.author().uid()),
+                ),
+                |state, _data: (), res: Result<EffectiveSetting>| {
+                    let effective = res?;
+                    let tbl = state.create_table()?;
+
+                    tbl.set("name", effective.name)?;
+                    tbl.set("value", effective.value)?;
+                    tbl.set("source", effective.source.as_str())?;
+
+                    Ok(tbl)
+                }
+            );
+
+            Ok(LuaMultiValue::from_vec(vec![
+                LuaValue::Nil,
+                LuaValue::Table(fut),
+            ]))
+        },
+    )?;
+    bot_tbl.set("get_setting", get_setting_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let unset_setting_fn = state.create_function(
+        move |state, (msg, scope, module, setting): (LuaAnyUserData, String, String, String)| {
+            let bot = bot2.clone();
+
+            let module_settings = match bot.get_ctx().modules().get_settings(&module) {
+                Some(settings) => settings,
+                None => {
+                    return Ok(LuaMultiValue::from_vec(vec![
+                        "unknown module".to_lua(state)?
+                    ]))
+                }
+            };
+
+            let msg = msg.borrow::<BotMessage>()?.clone();
+
+            let ctx = match setting_scope(&msg, &scope) {
+                Some(ctx) => ctx,
+                None => return Ok(LuaMultiValue::from_vec(vec!["unknown scope".to_lua(state)?])),
+            };
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                module_settings.unset_setting(ctx, &setting),
+                |_state, _data: (), res: Result<bool>| { res }
+            );
+
+            Ok(LuaMultiValue::from_vec(vec![
+                LuaValue::Nil,
+                LuaValue::Table(fut),
+            ]))
+        },
+    )?;
+    bot_tbl.set("unset_setting", unset_setting_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let list_setting_overrides_fn = state.create_function(move |state, (msg,): (LuaAnyUserData,)| {
+        let bot = bot2.clone();
+
+        let msg = msg.borrow::<BotMessage>()?.clone();
+        let server_id = msg.channel().server().id();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move { bot.db().list_setting_overrides(server_id).await },
+            |state, _data: (), res: Result<Vec<SettingOverride>>| {
+                let tbl = state.create_table()?;
+
+                for (idx, setting) in res?.into_iter().enumerate() {
+                    let setting_tbl = state.create_table()?;
+
+                    setting_tbl.set("key", setting.key)?;
+                    setting_tbl.set("value", setting.value)?;
+                    setting_tbl.set("channel", setting.channel_id.map(|id| id.to_str()))?;
+
+                    tbl.raw_insert((idx + 1) as i64, setting_tbl)?;
+                }
+
+                Ok(tbl)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    bot_tbl.set("list_setting_overrides", list_setting_overrides_fn)?;
+
+    let bot2 = bot.clone();
     let sender2 = sender.clone();
     let run_sandboxed_lua_fn = state.create_function(
         move |state,
@@ -517,9 +751,10 @@ pub fn lib_bot(
             String,
             Table
         )| {
-            let sandbox_state = sandbox_state.clone();
+            let bot = bot2.clone();
+            let sandbox_pool = sandbox_pool.clone();
 
-            let _user = user.borrow::<BotUser>()?.clone();
+            let user = user.borrow::<BotUser>()?.clone();
             let msg = msg.borrow::<BotMessage>()?.clone();
 
             let env_encoded: String = serde_json::to_string(&LuaValue::Table(env))
@@ -530,9 +765,19 @@ pub fn lib_bot(
                 sender2,
                 (),
                 async move {
-                    let lua_state = sandbox_state.lock_arc().await;
+                    let server_id = msg.channel().server().id();
+                    let quotas = match bot.get_ctx().modules().get::<LuaModule>() {
+                        Some(lua) => lua.execution_quotas(server_id, msg.channel().id(), &user.1).await?,
+                        None => Default::default(),
+                    };
+
+                    if let Err(exhausted) = quotas.take(Budget::Executions) {
+                        return Err(exhausted.into());
+                    }
+
+                    let lua_state = sandbox_pool.get(Some(server_id)).await;
 
-                    let (_sandbox_state, recv) = match lua_state.run_sandboxed(&code, msg, Some(env_encoded)) {
+                    let (sandbox_state, mut recv) = match lua_state.run_sandboxed(&code, msg, Some(env_encoded), quotas) {
                         Ok(recv) => recv,
                         Err(err) => {
                             return Err(anyhow::anyhow!(err.to_string()));
@@ -542,15 +787,11 @@ pub fn lib_bot(
                     drop(lua_state);
 
                     let mut out_str = String::new();
-                    let start = Instant::now();
+                    let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
 
                     loop {
-                        if start.elapsed() > Duration::from_secs(2) {
-                            break;
-                        }
-
-                        match recv.try_recv() {
-                            Ok(out) => match out {
+                        match tokio::time::timeout_at(deadline, recv.recv()).await {
+                            Ok(Some(out)) => match out {
                                 SandboxMsg::Out(o) => {
                                     if !out_str.is_empty() {
                                         out_str.push('\n');
@@ -559,9 +800,12 @@ pub fn lib_bot(
                                     out_str.push_str(&o);
                                 }
                                 SandboxMsg::Error(err) => {
+                                    METRICS.sandbox_executions.inc(&["error"]);
                                     return Err(anyhow::anyhow!(err));
                                 }
                                 SandboxMsg::Terminated(reason) => {
+                                    METRICS.sandbox_executions.inc(&[reason.as_str()]);
+
                                     match reason {
                                         SandboxTerminationReason::Done => {
                                             break
@@ -572,13 +816,25 @@ pub fn lib_bot(
                                         SandboxTerminationReason::TimeLimit => {
                                             return Err(anyhow::anyhow!("Execution time limit reached, terminated execution"));
                                         }
+                                        SandboxTerminationReason::MemoryLimit => {
+                                            return Err(anyhow::anyhow!("Memory limit exceeded, terminated execution"));
+                                        }
+                                        SandboxTerminationReason::QuotaExhausted(exhausted) => {
+                                            return Err(exhausted.into());
+                                        }
                                     }
                                 }
                             },
-                            Err(TryRecvError::Empty) => {
-                                tokio::time::sleep(Duration::from_millis(50)).await;
+                            Ok(None) => {
+                                METRICS.sandbox_executions.inc(&["disconnected"]);
+                                break;
+                            }
+                            Err(_) => {
+                                // Do not leave the code running once nothing waits for its output
+                                sandbox_state.terminate(SandboxTerminationReason::TimeLimit);
+                                METRICS.sandbox_executions.inc(&["output_timeout"]);
+                                return Err(anyhow::anyhow!("Execution time limit reached, terminated execution"));
                             }
-                            Err(TryRecvError::Disconnected) => break,
                         }
                     }
 
@@ -599,59 +855,66 @@ pub fn lib_bot(
 
     let bot2 = bot.clone();
     let sender2 = sender.clone();
-    let get_data_fn = state.create_function(move |state, (key,): (String,)| {
+    // Only single servers, the whole database is exported with the CLI so it never ends up in a channel
+    let export_db_fn = state.create_function(move |state, server: LuaAnyUserData| {
         let bot = bot2.clone();
 
-        if !key.chars().all(|c| c.is_ascii_alphanumeric()) {
-            return Err(LuaError::RuntimeError("key must be alphanumeric".into()));
-        }
+        let server_id = server.borrow::<BotServer>()?.id();
 
         let fut = create_lua_future!(
             state,
             sender2,
             (),
-            tokio::fs::read(bot.data_path().join(format!("{}.txt", key))),
-            |state, _data: (), res: Result<Vec<u8>, std::io::Error>| {
-                match res {
-                    Ok(data) => Ok(LuaValue::String(state.create_string(&data)?)),
-                    Err(err) => {
-                        if err.kind() == std::io::ErrorKind::NotFound {
-                            Ok(LuaValue::Nil)
-                        } else {
-                            Err(err.into())
-                        }
-                    }
-                }
-            }
+            async move {
+                let sid = bot.db().get_sid(server_id).await?;
+                let archive = bot.db().export_archive(Some(sid)).await?;
+
+                serde_json::to_string_pretty(&archive).map_err(anyhow::Error::from)
+            },
+            |_state, _data: (), res: Result<String>| { res }
         );
 
         Ok(fut)
     })?;
-    bot_tbl.set("get_data", get_data_fn)?;
+    bot_tbl.set("export_db", export_db_fn)?;
 
     let bot2 = bot.clone();
-    let set_data_fn = state.create_function(move |state, (key, value): (String, String)| {
-        let bot = bot2.clone();
+    let sender2 = sender.clone();
+    let import_db_fn = state.create_function(
+        move |state, (data, server): (LuaString, Option<LuaAnyUserData>)| {
+            let bot = bot2.clone();
 
-        if !key.chars().all(|c| c.is_ascii_alphanumeric()) {
-            return Err(LuaError::RuntimeError("key must be alphanumeric".into()));
-        }
+            let archive: DbArchive = serde_json::from_slice(data.as_bytes())
+                .map_err(|err| LuaError::RuntimeError(format!("invalid archive: {}", err)))?;
+            let server_id = match server {
+                Some(server) => Some(server.borrow::<BotServer>()?.id()),
+                None => None,
+            };
 
-        let fut = create_lua_future!(
-            state,
-            sender,
-            (),
-            tokio::fs::write(bot.data_path().join(format!("{}.txt", key)), value),
-            |_state, _data: (), res: Result<(), std::io::Error>| {
-                res?;
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move { bot.db().import_archive(&archive, server_id).await },
+                |state, _data: (), res: Result<ImportSummary>| {
+                    let summary = res?;
+                    let tbl = state.create_table()?;
 
-                Ok(())
-            }
-        );
+                    tbl.set("users", summary.users)?;
+                    tbl.set("servers", summary.servers)?;
+                    tbl.set("restrictions", summary.restrictions)?;
+                    tbl.set("settings", summary.settings)?;
+                    tbl.set("tags", summary.tags)?;
+                    tbl.set("skipped_tags", summary.skipped_tags)?;
 
-        Ok(fut)
-    })?;
-    bot_tbl.set("set_data", set_data_fn)?;
+                    Ok(tbl)
+                }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("import_db", import_db_fn)?;
 
     bot_flags(state, &bot_tbl)?;
 
@@ -665,7 +928,7 @@ pub struct BotMessage(Arc<BotMessageInner>);
 
 pub struct BotMessageInner {
     bot: Arc<Bot>,
-    sender: Sender<LuaAsyncCallback>,
+    sender: LuaAsyncSender,
     id: MessageId,
     author: BotUser,
     channel: BotChannel,
@@ -677,7 +940,7 @@ pub struct BotMessageInner {
 impl BotMessage {
     pub async fn from_msg(
         bot: Arc<Bot>,
-        sender: Sender<LuaAsyncCallback>,
+        sender: LuaAsyncSender,
         msg: &Arc<dyn Message<impl Service>>,
     ) -> Result<BotMessage> {
         let attachments = msg.attachments().to_vec();
@@ -1019,7 +1282,7 @@ pub struct BotChannel(Arc<BotChannelInner>);
 
 pub struct BotChannelInner {
     bot: Arc<Bot>,
-    sender: Sender<LuaAsyncCallback>,
+    sender: LuaAsyncSender,
     id: ChannelId,
     server: BotServer,
     service: ServiceKind,
@@ -1028,7 +1291,7 @@ pub struct BotChannelInner {
 impl BotChannel {
     pub async fn from_channel(
         bot: Arc<Bot>,
-        sender: Sender<LuaAsyncCallback>,
+        sender: LuaAsyncSender,
         channel: &Arc<dyn Channel<impl Service>>,
     ) -> Result<BotChannel> {
         let service_server = channel.server().await? as Arc<dyn Server<_>>;
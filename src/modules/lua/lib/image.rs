@@ -1,5 +1,4 @@
 use anyhow::Result;
-use crossbeam::channel::Sender;
 use futures::TryStreamExt;
 use graphicsmagick::{
     types,
@@ -20,10 +19,12 @@ use tokio::task::JoinError;
 
 use crate::{
     bot::Bot,
+    metrics::METRICS,
     modules::lua::{
         http::HttpError,
         lib::bot::BotMessage,
-        state::{get_sandbox_state, LuaAsyncCallback},
+        quota::Budget,
+        state::{get_sandbox_state, LuaAsyncSender},
     },
     services::{Message, ServiceKind},
 };
@@ -342,7 +343,7 @@ async fn download_image(url: &url::Url) -> Result<Vec<u8>> {
     Ok(body)
 }
 
-async fn create_image(sender: Sender<LuaAsyncCallback>, data: Vec<u8>, svg: bool) -> Result<Image> {
+async fn create_image(sender: LuaAsyncSender, data: Vec<u8>, svg: bool) -> Result<Image> {
     match tokio::task::spawn_blocking(move || {
         // Ensure the image is valid
         let mut wand = MagickWand::new();
@@ -383,7 +384,7 @@ async fn create_image(sender: Sender<LuaAsyncCallback>, data: Vec<u8>, svg: bool
     }
 }
 
-pub fn lib_image(state: &Lua, bot: Arc<Bot>, sender: Sender<LuaAsyncCallback>) -> Result<()> {
+pub fn lib_image(state: &Lua, bot: Arc<Bot>, sender: LuaAsyncSender) -> Result<()> {
     let image = state.create_table()?;
 
     // image.create_draw_buffer
@@ -717,14 +718,14 @@ pub fn lib_image(state: &Lua, bot: Arc<Bot>, sender: Sender<LuaAsyncCallback>) -
 }
 
 #[derive(Clone)]
-pub struct Image(Arc<ImageInner>, Sender<LuaAsyncCallback>);
+pub struct Image(Arc<ImageInner>, LuaAsyncSender);
 
 impl Image {
     pub fn copy_data(&self) -> Vec<u8> {
         self.0.data.clone()
     }
 
-    pub fn async_sender(&self) -> Sender<LuaAsyncCallback> {
+    pub fn async_sender(&self) -> LuaAsyncSender {
         self.1.clone()
     }
 
@@ -748,8 +749,14 @@ macro_rules! image_method {
                         "image operation limit reached".into(),
                     ));
                 }
+
+                if let Err(exhausted) = sandbox_state.take_quota(Budget::ImageOperations) {
+                    return Err(LuaError::RuntimeError(exhausted.to_string()));
+                }
             }
 
+            METRICS.image_operations.inc(&[$name]);
+
             let data = image.copy_data();
             let sender = image.async_sender();
 
@@ -0,0 +1,222 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua};
+use std::{sync::Arc, time::Duration};
+
+use super::super::state::LuaAsyncSender;
+use crate::bot::Bot;
+
+const MAX_NAME_SIZE: usize = 256;
+
+fn check_name(kind: &str, name: &str) -> LuaResult<()> {
+    if name.is_empty() || name.len() > MAX_NAME_SIZE {
+        return Err(LuaError::RuntimeError(format!(
+            "{} must be between 1 and {} bytes",
+            kind, MAX_NAME_SIZE
+        )));
+    }
+
+    Ok(())
+}
+
+// Values are stored as JSON, nil meaning no value
+fn encode_value(state: &Lua, value: LuaValue) -> LuaResult<Option<String>> {
+    match value {
+        LuaValue::Nil => Ok(None),
+        value => {
+            let value: serde_json::Value = state.from_value(value)?;
+
+            serde_json::to_string(&value)
+                .map(Some)
+                .map_err(|err| LuaError::ExternalError(Arc::new(err)))
+        }
+    }
+}
+
+fn decode_value<'a>(state: &'a Lua, value: Option<String>) -> Result<LuaValue<'a>> {
+    match value {
+        Some(value) => {
+            let value: serde_json::Value = serde_json::from_str(&value)?;
+            Ok(state.to_value(&value)?)
+        }
+        None => Ok(LuaValue::Nil),
+    }
+}
+
+fn ttl(ttl: Option<u64>) -> Option<Duration> {
+    ttl.map(Duration::from_secs)
+}
+
+pub fn lib_kv(state: &Lua, bot: &Arc<Bot>, sender: LuaAsyncSender) -> Result<()> {
+    let kv_tbl = state.create_table()?;
+
+    // kv.get
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let get_fn = state.create_function(move |state, (namespace, key): (String, String)| {
+        let bot = bot2.clone();
+
+        check_name("namespace", &namespace)?;
+        check_name("key", &key)?;
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move { bot.db().kv_get(&namespace, &key).await },
+            |state, _data: (), res: Result<Option<String>>| { decode_value(state, res?) }
+        );
+
+        Ok(fut)
+    })?;
+    kv_tbl.set("get", get_fn)?;
+
+    // kv.set, setting nil deletes the key
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let set_fn = state.create_function(
+        move |state, (namespace, key, value, ttl_secs): (String, String, LuaValue, Option<u64>)| {
+            let bot = bot2.clone();
+
+            check_name("namespace", &namespace)?;
+            check_name("key", &key)?;
+            let value = encode_value(state, value)?;
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    match value {
+                        Some(value) => {
+                            bot.db()
+                                .kv_set(&namespace, &key, &value, ttl(ttl_secs))
+                                .await
+                        }
+                        None => bot.db().kv_delete(&namespace, &key).await.map(|_| ()),
+                    }
+                },
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    kv_tbl.set("set", set_fn)?;
+
+    // kv.delete
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let delete_fn = state.create_function(move |state, (namespace, key): (String, String)| {
+        let bot = bot2.clone();
+
+        check_name("namespace", &namespace)?;
+        check_name("key", &key)?;
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move { bot.db().kv_delete(&namespace, &key).await },
+            |_state, _data: (), res: Result<bool>| { res }
+        );
+
+        Ok(fut)
+    })?;
+    kv_tbl.set("delete", delete_fn)?;
+
+    // kv.incr
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let incr_fn = state.create_function(
+        move |state, (namespace, key, by, ttl_secs): (String, String, Option<i64>, Option<u64>)| {
+            let bot = bot2.clone();
+
+            check_name("namespace", &namespace)?;
+            check_name("key", &key)?;
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    bot.db()
+                        .kv_incr(&namespace, &key, by.unwrap_or(1), ttl(ttl_secs))
+                        .await
+                },
+                |_state, _data: (), res: Result<i64>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    kv_tbl.set("incr", incr_fn)?;
+
+    // kv.compare_and_set
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let compare_and_set_fn = state.create_function(
+        move |state,
+              (namespace, key, expected, value, ttl_secs): (
+            String,
+            String,
+            LuaValue,
+            LuaValue,
+            Option<u64>,
+        )| {
+            let bot = bot2.clone();
+
+            check_name("namespace", &namespace)?;
+            check_name("key", &key)?;
+            let expected = encode_value(state, expected)?;
+            let value = encode_value(state, value)?;
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    bot.db()
+                        .kv_compare_and_set(
+                            &namespace,
+                            &key,
+                            expected.as_deref(),
+                            value.as_deref(),
+                            ttl(ttl_secs),
+                        )
+                        .await
+                },
+                |_state, _data: (), res: Result<bool>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    kv_tbl.set("compare_and_set", compare_and_set_fn)?;
+
+    // kv.list
+    let bot2 = bot.clone();
+    let sender2 = sender;
+    let list_fn = state.create_function(
+        move |state, (namespace, prefix): (String, Option<String>)| {
+            let bot = bot2.clone();
+
+            check_name("namespace", &namespace)?;
+            let prefix = prefix.unwrap_or_default();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move { bot.db().kv_list(&namespace, &prefix).await },
+                |_state, _data: (), res: Result<Vec<String>>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    kv_tbl.set("list", list_fn)?;
+
+    state.globals().set("kv", kv_tbl)?;
+
+    Ok(())
+}
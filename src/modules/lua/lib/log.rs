@@ -0,0 +1,65 @@
+use anyhow::Result;
+use mlua::{
+    prelude::{LuaTable, LuaValue},
+    Lua,
+};
+
+use crate::logging::{self, Level};
+
+// log.error(message, fields), log.warn, log.info, log.debug and log.trace
+//
+// The fields are logged as contextual fields, except for `target` which is appended to the "lua" target
+pub fn lib_log(state: &Lua) -> Result<()> {
+    let log = state.create_table()?;
+
+    for level in [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ] {
+        let log_fn =
+            state.create_function(move |_, (message, fields): (String, Option<LuaTable>)| {
+                let mut target = "lua".to_string();
+                let mut out = Vec::new();
+
+                if let Some(fields) = fields {
+                    for pair in fields.pairs::<String, LuaValue>() {
+                        let (key, value) = pair?;
+
+                        let value = match value {
+                            LuaValue::String(s) => s.to_str()?.to_string(),
+                            LuaValue::Integer(i) => i.to_string(),
+                            LuaValue::Number(n) => n.to_string(),
+                            LuaValue::Boolean(b) => b.to_string(),
+                            value => value.type_name().to_string(),
+                        };
+
+                        if key == "target" {
+                            target = format!("lua/{}", value);
+                        } else {
+                            out.push((key, value));
+                        }
+                    }
+                }
+
+                if logging::enabled(level, &target) {
+                    let fields = out
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.clone()))
+                        .collect::<Vec<_>>();
+
+                    logging::log(level, &target, &fields, format_args!("{}", message));
+                }
+
+                Ok(())
+            })?;
+
+        log.set(level.as_str(), log_fn)?;
+    }
+
+    state.globals().set("log", log)?;
+
+    Ok(())
+}
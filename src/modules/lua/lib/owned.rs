@@ -0,0 +1,128 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua, MetaMethod, UserData, UserDataMethods};
+use std::sync::Arc;
+
+use super::{super::state::LuaAsyncSender, bot::BotUser};
+use crate::bot::{
+    db::{BotDb, Sid, Uid},
+    Bot,
+};
+
+/// An entry a user owns in a server, like a tag or a script, which can be edited, deleted and
+/// transferred to another user
+#[async_trait]
+pub trait OwnedEntry: Send + Sync + 'static {
+    fn sid(&self) -> Sid;
+    fn name(&self) -> &str;
+    fn uid(&self) -> Uid;
+    fn transfer_uid(&self) -> Option<Uid>;
+
+    /// Lua fields of the entry besides its owners
+    fn index<'lua>(&self, state: &'lua Lua, index: &str) -> LuaResult<LuaValue<'lua>>;
+
+    async fn edit(db: &BotDb, sid: Sid, name: &str, content: &str) -> Result<()>;
+    async fn delete(db: &BotDb, sid: Sid, name: &str) -> Result<()>;
+    async fn set_uid(db: &BotDb, sid: Sid, name: &str, uid: Uid) -> Result<()>;
+    async fn set_transfer_uid(db: &BotDb, sid: Sid, name: &str, uid: Option<Uid>) -> Result<()>;
+}
+
+pub struct LuaOwned<T> {
+    bot: Arc<Bot>,
+    sender: LuaAsyncSender,
+    inner: T,
+}
+
+impl<T: OwnedEntry> LuaOwned<T> {
+    pub fn new(bot: Arc<Bot>, sender: LuaAsyncSender, inner: T) -> LuaOwned<T> {
+        LuaOwned { bot, sender, inner }
+    }
+
+    fn key(&self) -> (Arc<Bot>, Sid, String) {
+        (
+            self.bot.clone(),
+            self.inner.sid(),
+            self.inner.name().to_string(),
+        )
+    }
+}
+
+impl<T: OwnedEntry> UserData for LuaOwned<T> {
+    fn add_methods<'a, M: UserDataMethods<'a, Self>>(methods: &mut M) {
+        methods.add_method("edit", |state, entry, content: String| {
+            let (bot, sid, name) = entry.key();
+
+            let fut = create_lua_future!(
+                state,
+                entry.sender,
+                (),
+                T::edit(bot.db(), sid, &name, &content),
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        });
+
+        methods.add_method("delete", |state, entry, _: ()| {
+            let (bot, sid, name) = entry.key();
+
+            let fut = create_lua_future!(
+                state,
+                entry.sender,
+                (),
+                T::delete(bot.db(), sid, &name),
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        });
+
+        methods.add_method("set_owner", |state, entry, user: LuaAnyUserData| {
+            let (bot, sid, name) = entry.key();
+
+            let uid = user.borrow::<BotUser>()?.uid();
+
+            let fut = create_lua_future!(
+                state,
+                entry.sender,
+                (),
+                T::set_uid(bot.db(), sid, &name, uid),
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        });
+
+        methods.add_method(
+            "set_transfer_user",
+            |state, entry, user: Option<LuaAnyUserData>| {
+                let (bot, sid, name) = entry.key();
+
+                let uid = match user {
+                    Some(data) => Some(data.borrow::<BotUser>()?.uid()),
+                    None => None,
+                };
+
+                let fut = create_lua_future!(
+                    state,
+                    entry.sender,
+                    (),
+                    T::set_transfer_uid(bot.db(), sid, &name, uid),
+                    |_state, _data: (), res: Result<()>| { res }
+                );
+
+                Ok(fut)
+            },
+        );
+
+        methods.add_meta_method(MetaMethod::Index, |state, entry, index: String| {
+            match index.as_str() {
+                "uid" => Ok(LuaValue::Number(entry.inner.uid() as _)),
+                "transfer_uid" => Ok(match entry.inner.transfer_uid() {
+                    Some(uid) => LuaValue::Number(uid as f64),
+                    None => LuaValue::Nil,
+                }),
+                _ => entry.inner.index(state, &index),
+            }
+        });
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua};
+use std::sync::Arc;
+
+use super::{
+    super::state::LuaAsyncSender,
+    bot::{BotServer, BotUser},
+    owned::{LuaOwned, OwnedEntry},
+};
+use crate::bot::{
+    db::{BotDb, Script, Sid, Uid},
+    Bot,
+};
+
+pub fn lib_scripts(state: &Lua, bot: &Arc<Bot>, sender: LuaAsyncSender) -> Result<()> {
+    let scripts_tbl = state.create_table()?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let find_script_fn =
+        state.create_function(move |state, (server, name): (LuaAnyUserData, String)| {
+            let bot = bot2.clone();
+            let server = server.borrow::<BotServer>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2.clone(),
+                (bot.clone(), sender2.clone()),
+                bot.db().find_script(server.id(), &name.to_lowercase()),
+                |state, data: (Arc<Bot>, LuaAsyncSender), res: Result<Option<Script>>| {
+                    match res? {
+                        Some(script) => Ok(LuaValue::UserData(
+                            state.create_userdata(LuaScript::new(data.0, data.1, script))?,
+                        )),
+                        None => Ok(LuaValue::Nil),
+                    }
+                }
+            );
+
+            Ok(fut)
+        })?;
+    scripts_tbl.set("find_script", find_script_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let create_script_fn =
+        state.create_function(
+            move |state,
+                  (user, server, name, source): (
+                LuaAnyUserData,
+                LuaAnyUserData,
+                String,
+                String,
+            )| {
+                let bot = bot2.clone();
+                let user = user.borrow::<BotUser>()?.clone();
+                let server = server.borrow::<BotServer>()?.clone();
+
+                let fut = create_lua_future!(
+                    state,
+                    sender2,
+                    (),
+                    bot.db()
+                        .create_script(user.uid(), server.id(), &name.to_lowercase(), &source),
+                    |state, _data: (), res: Result<bool>| {
+                        if res? {
+                            Ok(LuaValue::Nil)
+                        } else {
+                            Ok(LuaValue::String(
+                                state.create_string("script already exists")?,
+                            ))
+                        }
+                    }
+                );
+
+                Ok(fut)
+            },
+        )?;
+    scripts_tbl.set("create_script", create_script_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let count_user_scripts_fn = state.create_function(
+        move |state, (user, server): (LuaAnyUserData, LuaAnyUserData)| {
+            let bot = bot2.clone();
+            let user = user.borrow::<BotUser>()?.clone();
+            let server = server.borrow::<BotServer>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                bot.db().count_uid_scripts(user.uid(), server.id()),
+                |_state, _data: (), res: Result<i64>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    scripts_tbl.set("count_user_scripts", count_user_scripts_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender;
+    let list_scripts_fn = state.create_function(
+        move |state, (user, server): (LuaAnyUserData, LuaAnyUserData)| {
+            let bot = bot2.clone();
+            let user = user.borrow::<BotUser>()?.clone();
+            let server = server.borrow::<BotServer>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                bot.db().list_scripts(user.uid(), server.id()),
+                |_state, _data: (), res: Result<Vec<String>>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    scripts_tbl.set("list_scripts", list_scripts_fn)?;
+
+    state.globals().set("scripts", scripts_tbl)?;
+
+    Ok(())
+}
+
+pub type LuaScript = LuaOwned<Script>;
+
+#[async_trait]
+impl OwnedEntry for Script {
+    fn sid(&self) -> Sid {
+        self.sid
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn uid(&self) -> Uid {
+        self.uid
+    }
+
+    fn transfer_uid(&self) -> Option<Uid> {
+        self.transfer_uid
+    }
+
+    fn index<'lua>(&self, state: &'lua Lua, index: &str) -> LuaResult<LuaValue<'lua>> {
+        match index {
+            "name" => Ok(LuaValue::String(state.create_string(&self.name)?)),
+            "source" => Ok(LuaValue::String(state.create_string(&self.source)?)),
+            _ => Ok(LuaValue::Nil),
+        }
+    }
+
+    async fn edit(db: &BotDb, sid: Sid, name: &str, source: &str) -> Result<()> {
+        db.edit_script(sid, name, source).await
+    }
+
+    async fn delete(db: &BotDb, sid: Sid, name: &str) -> Result<()> {
+        db.delete_script(sid, name).await
+    }
+
+    async fn set_uid(db: &BotDb, sid: Sid, name: &str, uid: Uid) -> Result<()> {
+        db.set_script_uid(sid, name, uid).await
+    }
+
+    async fn set_transfer_uid(db: &BotDb, sid: Sid, name: &str, uid: Option<Uid>) -> Result<()> {
+        db.set_script_transfer_uid(sid, name, uid).await
+    }
+}
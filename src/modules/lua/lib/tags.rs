@@ -1,13 +1,16 @@
 use anyhow::Result;
-use crossbeam::channel::Sender;
-use mlua::{prelude::*, Error as LuaError, Lua, MetaMethod, UserData, UserDataMethods};
+use mlua::{prelude::*, Error as LuaError, Lua};
 use std::sync::Arc;
 
 use super::{
-    super::state::LuaAsyncCallback,
+    super::state::LuaAsyncSender,
     bot::{BotServer, BotUser},
+    owned::{LuaOwned, OwnedEntry},
+};
+use crate::bot::{
+    db::{BotDb, Sid, Tag, Uid},
+    Bot,
 };
-use crate::bot::{db::Tag, Bot};
 
 #[derive(Debug, PartialEq)]
 enum TagPart {
@@ -119,7 +122,7 @@ fn parse_tag(value: &str) -> Vec<TagPart> {
     out
 }
 
-pub fn lib_tags(state: &Lua, bot: &Arc<Bot>, sender: Sender<LuaAsyncCallback>) -> Result<()> {
+pub fn lib_tags(state: &Lua, bot: &Arc<Bot>, sender: LuaAsyncSender) -> Result<()> {
     let tags_tbl = state.create_table()?;
 
     let bot2 = bot.clone();
@@ -134,10 +137,10 @@ pub fn lib_tags(state: &Lua, bot: &Arc<Bot>, sender: Sender<LuaAsyncCallback>) -
                 sender2.clone(),
                 (bot.clone(), sender2.clone()),
                 bot.db().find_tag(server.id(), &tag_key.to_lowercase()),
-                |state, data: (Arc<Bot>, Sender<LuaAsyncCallback>), res: Result<Option<Tag>>| {
+                |state, data: (Arc<Bot>, LuaAsyncSender), res: Result<Option<Tag>>| {
                     match res? {
                         Some(tag) => Ok(LuaValue::UserData(
-                            state.create_userdata(LuaTag::from_tag(data.0, data.1, tag)?)?,
+                            state.create_userdata(LuaTag::new(data.0, data.1, tag))?,
                         )),
                         None => Ok(LuaValue::Nil),
                     }
@@ -263,113 +266,50 @@ pub fn lib_tags(state: &Lua, bot: &Arc<Bot>, sender: Sender<LuaAsyncCallback>) -
 
     Ok(())
 }
-pub struct LuaTag {
-    bot: Arc<Bot>,
-    sender: Sender<LuaAsyncCallback>,
-    inner: Tag,
-}
-
-impl LuaTag {
-    pub fn from_tag(bot: Arc<Bot>, sender: Sender<LuaAsyncCallback>, inner: Tag) -> Result<LuaTag> {
-        Ok(LuaTag { bot, sender, inner })
-    }
-}
-
-impl UserData for LuaTag {
-    fn add_methods<'a, M: UserDataMethods<'a, Self>>(methods: &mut M) {
-        methods.add_method("edit", |state, tag, value: String| {
-            let (bot, sid, key) = (tag.bot.clone(), tag.inner.sid, tag.inner.key.clone());
-
-            let fut = create_lua_future!(
-                state,
-                tag.sender,
-                (),
-                bot.db().edit_tag(sid, &key, &value),
-                |_state, _data: (), res: Result<()>| {
-                    res?;
 
-                    Ok(())
-                }
-            );
-
-            Ok(fut)
-        });
-
-        methods.add_method("delete", |state, tag, _: ()| {
-            let (bot, sid, key) = (tag.bot.clone(), tag.inner.sid, tag.inner.key.clone());
+pub type LuaTag = LuaOwned<Tag>;
 
-            let fut = create_lua_future!(
-                state,
-                tag.sender,
-                (),
-                bot.db().delete_tag(sid, &key),
-                |_state, _data: (), res: Result<()>| {
-                    res?;
+#[async_trait]
+impl OwnedEntry for Tag {
+    fn sid(&self) -> Sid {
+        self.sid
+    }
 
-                    Ok(())
-                }
-            );
+    fn name(&self) -> &str {
+        &self.key
+    }
 
-            Ok(fut)
-        });
+    fn uid(&self) -> Uid {
+        self.uid
+    }
 
-        methods.add_method("set_owner", |state, tag, user: LuaAnyUserData| {
-            let (bot, sid, key) = (tag.bot.clone(), tag.inner.sid, tag.inner.key.clone());
+    fn transfer_uid(&self) -> Option<Uid> {
+        self.transfer_uid
+    }
 
-            let uid = user.borrow::<BotUser>()?.uid();
+    fn index<'lua>(&self, state: &'lua Lua, index: &str) -> LuaResult<LuaValue<'lua>> {
+        match index {
+            "value" => Ok(LuaValue::String(state.create_string(&self.value)?)),
+            _ => Ok(LuaValue::Nil),
+        }
+    }
 
-            let fut = create_lua_future!(
-                state,
-                tag.sender,
-                (),
-                bot.db().set_tag_uid(sid, &key, uid),
-                |_state, _data: (), res: Result<()>| {
-                    res?;
+    async fn edit(db: &BotDb, sid: Sid, key: &str, value: &str) -> Result<()> {
+        db.edit_tag(sid, key, value).await
+    }
 
-                    Ok(())
-                }
-            );
+    async fn delete(db: &BotDb, sid: Sid, key: &str) -> Result<()> {
+        db.delete_tag(sid, key).await?;
 
-            Ok(fut)
-        });
-
-        methods.add_method(
-            "set_transfer_user",
-            |state, tag, user: Option<LuaAnyUserData>| {
-                let (bot, sid, key) = (tag.bot.clone(), tag.inner.sid, tag.inner.key.clone());
-
-                let uid = match user {
-                    Some(data) => Some(data.borrow::<BotUser>()?.uid()),
-                    None => None,
-                };
-
-                let fut = create_lua_future!(
-                    state,
-                    tag.sender,
-                    (),
-                    bot.db().set_tag_transfer_uid(sid, &key, uid),
-                    |_state, _data: (), res: Result<()>| {
-                        res?;
-
-                        Ok(())
-                    }
-                );
+        Ok(())
+    }
 
-                Ok(fut)
-            },
-        );
+    async fn set_uid(db: &BotDb, sid: Sid, key: &str, uid: Uid) -> Result<()> {
+        db.set_tag_uid(sid, key, uid).await
+    }
 
-        methods.add_meta_method(MetaMethod::Index, |state, tag, index: String| {
-            match index.as_str() {
-                "uid" => Ok(mlua::Value::Number(tag.inner.uid as _)),
-                "transfer_uid" => Ok(match tag.inner.transfer_uid {
-                    Some(uid) => mlua::Value::Number(uid as f64),
-                    None => mlua::Value::Nil,
-                }),
-                "value" => Ok(mlua::Value::String(state.create_string(&tag.inner.value)?)),
-                _ => Ok(mlua::Value::Nil),
-            }
-        });
+    async fn set_transfer_uid(db: &BotDb, sid: Sid, key: &str, uid: Option<Uid>) -> Result<()> {
+        db.set_tag_transfer_uid(sid, key, uid).await
     }
 }
 
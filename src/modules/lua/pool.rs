@@ -0,0 +1,144 @@
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use async_mutex::{Mutex, MutexGuardArc};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use super::state::{self, LuaState, MEMORY_LIMIT};
+use crate::{bot::Bot, metrics::METRICS, services::ServerId};
+
+// Recycle a state before it runs into the memory limit on every allocation
+const RECYCLE_MEMORY: usize = MEMORY_LIMIT / 4 * 3;
+
+/// The sandbox states shared by all servers
+///
+/// Every server prefers the same state, so a busy server only delays the servers hashed to the same
+/// state, and only while no other state is free.
+pub struct SandboxPool {
+    bot: Arc<Bot>,
+    states: ArcSwap<Vec<Arc<Mutex<LuaState>>>>,
+    run_memory_limit: AtomicUsize,
+}
+
+impl SandboxPool {
+    pub fn new(bot: &Arc<Bot>, size: usize, run_memory_limit: usize) -> Result<SandboxPool> {
+        let pool = SandboxPool {
+            bot: bot.clone(),
+            states: ArcSwap::from_pointee(Vec::new()),
+            run_memory_limit: AtomicUsize::new(run_memory_limit),
+        };
+
+        pool.resize(size)?;
+
+        Ok(pool)
+    }
+
+    pub fn size(&self) -> usize {
+        self.states.load().len()
+    }
+
+    /// Memory that a single execution can allocate, applied to the next executions
+    pub fn set_run_memory_limit(&self, limit: usize) {
+        self.run_memory_limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Grow or shrink the pool, the states that are kept are not restarted and the drivers of the
+    /// removed states stop once they are dropped
+    pub fn resize(&self, size: usize) -> Result<()> {
+        let size = size.max(1);
+        let mut states = self.states.load().iter().take(size).cloned().collect::<Vec<_>>();
+
+        while states.len() < size {
+            let state = Arc::new(Mutex::new(LuaState::create_state(&self.bot, true, None)?));
+            tokio::spawn(state::drive(
+                Arc::downgrade(&state),
+                recycle(self.bot.clone(), states.len()),
+            ));
+            states.push(state);
+        }
+
+        self.states.store(Arc::new(states));
+
+        Ok(())
+    }
+
+    /// Lock a state for the server, falling back to any free state if its own is busy
+    pub async fn get(&self, server_id: Option<ServerId>) -> MutexGuardArc<LuaState> {
+        let mut state = self.lock(server_id).await;
+        state.set_run_memory_limit(self.run_memory_limit.load(Ordering::Relaxed));
+        state
+    }
+
+    async fn lock(&self, server_id: Option<ServerId>) -> MutexGuardArc<LuaState> {
+        let states = self.states.load_full();
+
+        let slot = server_id
+            .map(|server_id| {
+                let mut hasher = DefaultHasher::new();
+                server_id.hash(&mut hasher);
+                hasher.finish() as usize % states.len()
+            })
+            .unwrap_or(0);
+
+        if let Some(state) = states[slot].try_lock_arc() {
+            return state;
+        }
+
+        for state in states.iter() {
+            if let Some(state) = state.try_lock_arc() {
+                return state;
+            }
+        }
+
+        states[slot].lock_arc().await
+    }
+
+    /// Replace every state, terminating everything that is still running
+    pub async fn restart(&self) -> Result<()> {
+        for state in self.states.load_full().iter() {
+            let new_state = LuaState::create_state(&self.bot, true, None)?;
+            *state.lock_arc().await = new_state;
+            METRICS.sandbox_recycles.inc(&["restart"]);
+        }
+
+        Ok(())
+    }
+}
+
+// Replace a state after its think hook once it gets close to the memory limit or ran out of memory
+fn recycle(bot: Arc<Bot>, slot: usize) -> impl FnMut(&mut LuaState) + Send {
+    move |state| {
+        let used_memory = state.used_memory();
+
+        if !state.out_of_memory() && used_memory <= RECYCLE_MEMORY {
+            return;
+        }
+
+        log_info!(
+            "modules/lua",
+            { slot = slot, memory = used_memory },
+            "recycling sandbox state"
+        );
+
+        match LuaState::create_state(&bot, true, None) {
+            Ok(new_state) => {
+                *state = new_state;
+                METRICS.sandbox_recycles.inc(&["memory"]);
+            }
+            Err(err) => {
+                log_error!(
+                    "modules/lua",
+                    { slot = slot },
+                    "error recycling sandbox state: {}",
+                    err.to_string()
+                );
+            }
+        }
+    }
+}
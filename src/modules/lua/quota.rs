@@ -0,0 +1,270 @@
+use governor::{
+    clock::{Clock, QuantaClock},
+    state::{direct::NotKeyed, InMemoryState},
+    Quota, RateLimiter,
+};
+use lru::LruCache;
+use std::{
+    error::Error,
+    fmt,
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bot::{db::Uid, ROLES},
+    services::ChannelId,
+};
+
+type Limiter = RateLimiter<NotKeyed, InMemoryState, QuantaClock>;
+
+/// A rate limited budget which remembers until when it ran out
+struct BudgetLimiter {
+    limiter: Limiter,
+    exhausted_until: Mutex<Option<Instant>>,
+}
+
+impl BudgetLimiter {
+    fn new(per_minute: NonZeroU32) -> BudgetLimiter {
+        BudgetLimiter {
+            limiter: RateLimiter::direct(Quota::per_minute(per_minute)),
+            exhausted_until: Mutex::new(None),
+        }
+    }
+
+    /// The remaining wait if the budget ran out earlier, without taking from it
+    fn exhausted(&self) -> Option<Duration> {
+        self.exhausted_until
+            .lock()
+            .unwrap()
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+            .filter(|wait| !wait.is_zero())
+    }
+
+    fn take(&self) -> Result<(), Duration> {
+        self.limiter.check().map_err(|not_until| {
+            let wait = not_until.wait_time_from(QuantaClock::default().now());
+            *self.exhausted_until.lock().unwrap() = Some(Instant::now() + wait);
+
+            wait
+        })
+    }
+}
+
+/// Instructions are taken from the budgets in chunks of this many instructions
+pub const INSTRUCTION_CHUNK: u64 = 65_536;
+
+// The role from which the quotas are multiplied
+const TRUSTED_ROLE: &str = "trusted";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Budget {
+    Executions,
+    Instructions,
+    HttpCalls,
+    ImageOperations,
+}
+
+impl Budget {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Budget::Executions => "executions",
+            Budget::Instructions => "instructions",
+            Budget::HttpCalls => "http_calls",
+            Budget::ImageOperations => "image_operations",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Subject {
+    User(Uid),
+    Channel(ChannelId),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QuotaExhausted {
+    pub budget: Budget,
+    pub channel: bool,
+    pub wait: Duration,
+}
+
+impl QuotaExhausted {
+    pub fn wait_secs(&self) -> u64 {
+        self.wait.as_secs().max(1)
+    }
+}
+
+impl fmt::Display for QuotaExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the {} {} budget is used up, try again in {} seconds",
+            if self.channel { "channel" } else { "user" },
+            self.budget.as_str(),
+            self.wait_secs()
+        )
+    }
+}
+
+impl Error for QuotaExhausted {}
+
+/// Per minute budgets of a user or a channel
+#[derive(Clone, Copy)]
+pub struct BudgetLimits {
+    pub executions: u32,
+    pub instructions: u32,
+    pub http_calls: u32,
+    pub image_operations: u32,
+}
+
+impl BudgetLimits {
+    // Instructions are counted in chunks
+    fn per_minute(&self, budget: Budget) -> u32 {
+        match budget {
+            Budget::Executions => self.executions,
+            Budget::Instructions => self.instructions / INSTRUCTION_CHUNK as u32,
+            Budget::HttpCalls => self.http_calls,
+            Budget::ImageOperations => self.image_operations,
+        }
+    }
+
+    /// Multiply the budgets for trusted roles and above
+    pub fn for_role(self, role: &str, multiplier: u32) -> BudgetLimits {
+        let position = |role: &str| ROLES.iter().position(|r| *r == role);
+
+        if position(role) < position(TRUSTED_ROLE) {
+            return self;
+        }
+
+        BudgetLimits {
+            executions: self.executions.saturating_mul(multiplier),
+            instructions: self.instructions.saturating_mul(multiplier),
+            http_calls: self.http_calls.saturating_mul(multiplier),
+            image_operations: self.image_operations.saturating_mul(multiplier),
+        }
+    }
+}
+
+const BUDGETS: [Budget; 4] = [
+    Budget::Executions,
+    Budget::Instructions,
+    Budget::HttpCalls,
+    Budget::ImageOperations,
+];
+
+/// Rolling budgets shared by all sandbox executions
+///
+/// The limiters are keyed by their quota as well, so a changed setting starts a new budget.
+pub struct SandboxQuotas {
+    limiters: Mutex<LruCache<(Subject, Budget, u32), Arc<BudgetLimiter>>>,
+}
+
+impl Default for SandboxQuotas {
+    fn default() -> SandboxQuotas {
+        SandboxQuotas {
+            limiters: Mutex::new(LruCache::new(4096)),
+        }
+    }
+}
+
+impl SandboxQuotas {
+    /// The budgets of an execution by a user in a channel
+    pub fn execution(
+        &self,
+        uid: Uid,
+        user_limits: BudgetLimits,
+        channel_id: ChannelId,
+        channel_limits: BudgetLimits,
+    ) -> ExecutionQuotas {
+        let mut limiters = self.limiters.lock().unwrap();
+        let mut get = |subject: Subject, budget: Budget, per_minute: u32| {
+            let per_minute = per_minute.max(1);
+            let key = (subject, budget, per_minute);
+
+            if let Some(limiter) = limiters.get(&key) {
+                return limiter.clone();
+            }
+
+            let limiter = Arc::new(BudgetLimiter::new(NonZeroU32::new(per_minute).unwrap()));
+            limiters.put(key, limiter.clone());
+
+            limiter
+        };
+
+        let mut out = Vec::new();
+
+        for budget in BUDGETS {
+            let user_limiter = get(Subject::User(uid), budget, user_limits.per_minute(budget));
+            let channel_limiter = get(
+                Subject::Channel(channel_id),
+                budget,
+                channel_limits.per_minute(budget),
+            );
+
+            out.push((budget, user_limiter, channel_limiter));
+        }
+
+        ExecutionQuotas(Arc::new(out))
+    }
+}
+
+/// The user and channel budgets of one execution, the default has no limits
+#[derive(Clone, Default)]
+pub struct ExecutionQuotas(Arc<Vec<(Budget, Arc<BudgetLimiter>, Arc<BudgetLimiter>)>>);
+
+impl ExecutionQuotas {
+    /// Take from the user and channel budgets, neither is taken from when the other one ran out.
+    ///
+    /// A user whose budget ran out does not use up the channel budget by retrying, only the
+    /// check that runs out the user budget can still take from the channel.
+    pub fn take(&self, budget: Budget) -> Result<(), QuotaExhausted> {
+        let exhausted = |channel: bool, wait: Duration| QuotaExhausted {
+            budget,
+            channel,
+            wait,
+        };
+
+        for (_, user, channel) in self.0.iter().filter(|(b, _, _)| *b == budget) {
+            if let Some(wait) = user.exhausted() {
+                return Err(exhausted(false, wait));
+            }
+
+            channel.take().map_err(|wait| exhausted(true, wait))?;
+            user.take().map_err(|wait| exhausted(false, wait))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BudgetLimits;
+
+    #[test]
+    fn for_role_test() {
+        let limits = BudgetLimits {
+            executions: 10,
+            instructions: 1000,
+            http_calls: 5,
+            image_operations: u32::MAX,
+        };
+
+        let guest = limits.for_role("guest", 4);
+        assert_eq!(guest.executions, 10);
+        assert_eq!(guest.instructions, 1000);
+
+        let unknown = limits.for_role("unknown", 4);
+        assert_eq!(unknown.executions, 10);
+
+        for role in &["trusted", "admin", "root"] {
+            let limits = limits.for_role(role, 4);
+            assert_eq!(limits.executions, 40);
+            assert_eq!(limits.instructions, 4000);
+            assert_eq!(limits.http_calls, 20);
+            assert_eq!(limits.image_operations, u32::MAX);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 use async_mutex::Mutex;
-use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossbeam::channel::{unbounded, Receiver, SendError, Sender};
 use governor::{
     clock::QuantaClock,
     state::{direct::NotKeyed, InMemoryState},
@@ -8,13 +8,23 @@ use governor::{
 };
 use mlua::{
     prelude::{LuaError, LuaMultiValue, LuaValue},
-    Function, Lua, LuaSerdeExt, RegistryKey, StdLib, Table, Thread, ThreadStatus, ToLua, UserData,
-    UserDataMethods,
+    Debug as LuaDebug, Function, HookTriggers, Lua, LuaSerdeExt, RegistryKey, StdLib, Table,
+    Thread, ThreadStatus, ToLua, UserData, UserDataMethods,
 };
 use paste::paste;
-use std::sync::{
-    atomic::{AtomicBool, AtomicU64, Ordering},
-    Arc,
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex as StdMutex, Weak,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Notify,
+    },
+    time::MissedTickBehavior,
 };
 
 use super::{
@@ -22,26 +32,116 @@ use super::{
     lib::{
         bot::{bot_flags, lib_bot, BotMessage, BotUser},
         image::lib_image,
-        include_lua, lib_include,
+        include_lua,
+        kv::lib_kv,
+        lib_include, lib_require,
+        log::lib_log,
         os::lib_os,
         r#async::lib_async,
+        scripts::lib_scripts,
         tags::lib_tags,
     },
+    pool::SandboxPool,
+    quota::{Budget, ExecutionQuotas, QuotaExhausted, INSTRUCTION_CHUNK},
+    storage::{self, SandboxStorage},
     LuaSandboxReplies,
 };
 use crate::{
     bot::Bot,
     message::MessageSettings,
+    metrics::METRICS,
     services::{ChannelId, MessageId, ServerId},
-    utils::escape_untrusted_text,
+    utils::{
+        escape_untrusted_text,
+        tasks::{TaskGuard, TaskTracker},
+        vfs::Vfs,
+    },
 };
 
+/// Memory limit of every lua state
+pub const MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
+// How often the think hook runs, async callbacks are handled as soon as they are queued
+const THINK_INTERVAL: Duration = Duration::from_millis(50);
+
+// Instructions between two checks of the sandbox limits
+const HOOK_EVERY_INSTRUCTION: u32 = 1024;
+
 pub type LuaAsyncCallback = (
     RegistryKey,
     Option<SandboxState>,
     Box<dyn FnOnce(&Lua) -> Result<LuaMultiValue> + Send>,
 );
 
+/// Queues async callbacks for a lua state and wakes up its driver
+#[derive(Clone)]
+pub struct LuaAsyncSender {
+    sender: Sender<LuaAsyncCallback>,
+    wakeup: Arc<Notify>,
+    tasks: Arc<TaskTracker>,
+}
+
+impl LuaAsyncSender {
+    pub fn send(&self, callback: LuaAsyncCallback) -> Result<(), SendError<LuaAsyncCallback>> {
+        self.sender.send(callback)?;
+        self.wakeup.notify_one();
+
+        Ok(())
+    }
+
+    /// Keeps shutdown waiting while a future of the state is in flight
+    pub fn track(&self) -> TaskGuard {
+        self.tasks.track()
+    }
+}
+
+/// Drive a state until it is dropped
+///
+/// Async callbacks are resolved as soon as they are queued, the think hook runs every
+/// `THINK_INTERVAL`, followed by `after_think`. The state can be replaced behind the mutex at any time.
+pub async fn drive<F>(state: Weak<Mutex<LuaState>>, mut after_think: F)
+where
+    F: FnMut(&mut LuaState) + Send,
+{
+    let mut interval = tokio::time::interval(THINK_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let wakeup = match state.upgrade() {
+            Some(state) => state.lock_arc().await.wakeup.clone(),
+            None => break,
+        };
+
+        let think = tokio::select! {
+            _ = interval.tick() => true,
+            _ = wakeup.notified() => false,
+        };
+
+        let state = match state.upgrade() {
+            Some(state) => state,
+            None => break,
+        };
+        let mut state = state.lock_arc().await;
+
+        let res = if think { state.think() } else { state.resume() };
+
+        if let Err(err) = res {
+            state.note_error(&err);
+
+            log_error!(
+                "modules/lua",
+                { state = if state.sandbox { "sandbox" } else { "bot" } },
+                "error during think: {}",
+                err.to_string()
+            );
+        }
+
+        if think {
+            after_think(&mut state);
+        }
+    }
+}
+
 macro_rules! atomic_get_set {
     ($ident:ident, $ty:ty) => {
         paste! {
@@ -83,12 +183,42 @@ pub fn get_sandbox_state(state: &Lua) -> Option<SandboxState> {
     state.named_registry_value("__SANDBOX_STATE").ok().clone()
 }
 
+fn is_memory_error(err: &LuaError) -> bool {
+    match err {
+        LuaError::MemoryError(_) => true,
+        LuaError::CallbackError { cause, .. } => is_memory_error(cause),
+        _ => false,
+    }
+}
+
+// Runs in every thread of a sandbox state, but the limits only apply while user code is resumed
+fn sandbox_hook(state: &Lua, _debug: LuaDebug) -> mlua::Result<()> {
+    match get_sandbox_state(state).and_then(|sandbox_state| sandbox_state.check_limits()) {
+        Some(SandboxTerminationReason::ExecutionQuota) => Err(LuaError::RuntimeError(
+            "Execution quota exceeded".to_string(),
+        )),
+        Some(SandboxTerminationReason::TimeLimit) => Err(LuaError::RuntimeError(
+            "Execution time limit reached".to_string(),
+        )),
+        Some(SandboxTerminationReason::MemoryLimit) => Err(LuaError::RuntimeError(
+            "Memory limit exceeded".to_string(),
+        )),
+        Some(SandboxTerminationReason::QuotaExhausted(exhausted)) => {
+            Err(LuaError::RuntimeError(exhausted.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
 pub struct LuaState {
     bot: Arc<Bot>,
     inner: Lua,
     sandbox: bool,
-    async_sender: Sender<LuaAsyncCallback>,
+    async_sender: LuaAsyncSender,
     async_receiver: Receiver<LuaAsyncCallback>,
+    wakeup: Arc<Notify>,
+    run_memory_limit: usize,
+    out_of_memory: AtomicBool,
     http_rate_limiter: Arc<RateLimiter<NotKeyed, InMemoryState, QuantaClock>>,
     thread_id: Arc<AtomicU64>,
     shutting_down: AtomicBool,
@@ -98,7 +228,7 @@ impl LuaState {
     pub fn create_state(
         bot: &Arc<Bot>,
         sandbox: bool,
-        bot_state: Option<(Arc<Mutex<LuaState>>, Arc<LuaSandboxReplies>)>,
+        bot_state: Option<(Arc<SandboxPool>, Arc<LuaSandboxReplies>)>,
     ) -> Result<LuaState> {
         // Avoid loading os and io
         let inner = unsafe {
@@ -113,23 +243,39 @@ impl LuaState {
             )
         };
 
-        let (async_sender, async_receiver) = unbounded();
+        let (sender, async_receiver) = unbounded();
+        let wakeup = Arc::new(Notify::new());
+        let async_sender = LuaAsyncSender {
+            sender,
+            wakeup: wakeup.clone(),
+            tasks: bot.tasks().clone(),
+        };
 
         let thread_id = Arc::new(AtomicU64::new(0));
 
         lib_async(&inner, async_sender.clone(), thread_id.clone())?;
         lib_os(&inner)?;
 
-        let lua_root_path = bot.share_path().join("lua");
+        let lua_vfs = Vfs::share(bot.share_path(), "lua");
 
-        lib_include(lua_root_path.clone(), &inner)?;
+        lib_include(lua_vfs.clone(), &inner)?;
+        lib_require(lua_vfs.clone(), &inner)?;
         lib_image(&inner, bot.clone(), async_sender.clone())?;
 
         if sandbox {
+            // Set before anything is loaded, threads inherit the hook when they are created
+            inner.set_hook(
+                HookTriggers {
+                    every_nth_instruction: Some(HOOK_EVERY_INSTRUCTION),
+                    ..Default::default()
+                },
+                sandbox_hook,
+            )?;
+
             let bot_tbl = inner.create_table()?;
             bot_flags(&inner, &bot_tbl)?;
             inner.globals().set("bot", bot_tbl)?;
-            include_lua(&inner, &lua_root_path, "sandbox.lua")?;
+            include_lua(&inner, &lua_vfs, "sandbox.lua")?;
         } else {
             lib_bot(
                 &inner,
@@ -138,14 +284,16 @@ impl LuaState {
                 bot_state.expect("sandbox state for bot state"),
             )?;
             http::lib_http(&inner, async_sender.clone())?;
+            lib_kv(&inner, bot, async_sender.clone())?;
+            lib_log(&inner)?;
+            lib_scripts(&inner, bot, async_sender.clone())?;
             lib_tags(&inner, bot, async_sender.clone())?;
             inner.set_named_registry_value("__ASYNC_THREADS", inner.create_table()?)?;
             inner.set_named_registry_value("__ASYNC_THREADS_CHANNELS", inner.create_table()?)?;
-            include_lua(&inner, &lua_root_path, "bot.lua")?;
+            include_lua(&inner, &lua_vfs, "bot.lua")?;
         }
 
-        // Limit memory to 256 MiB
-        inner.set_memory_limit(256 * 1024 * 1024)?;
+        inner.set_memory_limit(MEMORY_LIMIT)?;
 
         let http_rate_limiter = Arc::new(RateLimiter::direct(Quota::per_second(
             std::num::NonZeroU32::new(2).unwrap(),
@@ -157,6 +305,9 @@ impl LuaState {
             sandbox,
             async_sender,
             async_receiver,
+            wakeup,
+            run_memory_limit: MEMORY_LIMIT,
+            out_of_memory: AtomicBool::new(false),
             http_rate_limiter,
             thread_id,
             shutting_down: AtomicBool::new(false),
@@ -210,13 +361,14 @@ impl LuaState {
         args: Vec<String>,
         edited: bool,
         spammy_commands: bool,
+        locale: String,
     ) -> Result<()> {
         let bot_tbl: Table = self.inner.globals().get("bot")?;
         let on_command_fn: Function = bot_tbl.get("on_command")?;
 
         let thread = self.inner.create_thread(on_command_fn)?;
         let channel_id = msg.channel().id();
-        thread.resume((msg.clone(), args, edited, spammy_commands))?;
+        thread.resume((msg.clone(), args, edited, spammy_commands, locale))?;
 
         self.create_async_thread(thread, Some(channel_id))?;
 
@@ -262,16 +414,21 @@ impl LuaState {
         source: &str,
         msg: BotMessage,
         env_encoded: Option<String>,
-    ) -> Result<(Arc<SandboxStateInner>, Receiver<SandboxMsg>)> {
+        quotas: ExecutionQuotas,
+    ) -> Result<(Arc<SandboxStateInner>, UnboundedReceiver<SandboxMsg>)> {
         let sandbox_tbl: Table = self.inner.globals().get("sandbox")?;
         let run_fn: Function = sandbox_tbl.get("run")?;
 
-        let (sender, receiver) = unbounded();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let storage = SandboxStorage::new(self.bot.clone(), &msg);
 
         let sandbox_state = SandboxState(Arc::new(SandboxStateInner {
             async_sender: self.async_sender.clone(),
             sender: sender.clone(),
             instructions_run: AtomicU64::new(0),
+            execution: StdMutex::new(SandboxExecution::default()),
+            quotas,
+            storage,
             limits: SandboxLimits {
                 lines_left: AtomicU64::new(10),
                 characters_left: AtomicU64::new(2000),
@@ -282,7 +439,13 @@ impl LuaState {
                 message_deletions_left: AtomicU64::new(2),
                 images_left: AtomicU64::new(4),
                 image_operations_left: AtomicU64::new(16),
+                storage_operations_left: AtomicU64::new(32),
+                storage_key_size: 128,
+                storage_value_size: 16 * 1024,
+                storage_size: 256 * 1024,
                 instructions: 12_582_912,
+                time_limit: Duration::from_secs(30),
+                memory: self.run_memory_limit,
             },
             http_rate_limiter: self.http_rate_limiter.clone(),
         }));
@@ -290,26 +453,40 @@ impl LuaState {
         self.inner
             .set_named_registry_value("__SANDBOX_STATE", sandbox_state.clone())?;
 
-        if let Some(env_encoded) = env_encoded {
-            let env = self.inner.to_value(&env_encoded)?;
-            run_fn.call((sandbox_state.clone(), msg, source, env, true))?;
-        } else {
-            run_fn.call((sandbox_state.clone(), msg, source, LuaValue::Nil, true))?;
+        let env = match env_encoded {
+            Some(env_encoded) => self.inner.to_value(&env_encoded)?,
+            None => LuaValue::Nil,
+        };
+
+        if let Err(err) = run_fn.call::<_, ()>((sandbox_state.clone(), msg, source, env, true)) {
+            let err: anyhow::Error = err.into();
+            self.note_error(&err);
+            return Err(err);
         }
 
         Ok((sandbox_state.0, receiver))
     }
 
+    /// Run the think hook and resume everything that is waiting
     pub fn think(&self) -> Result<()> {
+        if !self.sandbox {
+            let bot_tbl: Table = self.inner.globals().get("bot")?;
+            let think_fn: Function = bot_tbl.get("think")?;
+            think_fn.call(())?;
+        }
+
+        self.resume()
+    }
+
+    /// Resolve the queued async callbacks and resume the threads waiting on them
+    pub fn resume(&self) -> Result<()> {
+        self.think_async_callbacks()?;
+
         if self.sandbox {
             let sandbox_tbl: Table = self.inner.globals().get("sandbox")?;
             let think_fn: Function = sandbox_tbl.get("think")?;
             think_fn.call(())?;
         } else {
-            let bot_tbl: Table = self.inner.globals().get("bot")?;
-            let think_fn: Function = bot_tbl.get("think")?;
-            think_fn.call(())?;
-
             let threads: Table = self.inner.named_registry_value("__ASYNC_THREADS")?;
             let thread_channels: Table = self
                 .inner
@@ -323,6 +500,13 @@ impl LuaState {
                         let id = ChannelId::from_str(&channel_str)?;
                         let bot = self.bot.clone();
 
+                        log_debug!(
+                            "modules/lua",
+                            { channel = id.to_str() },
+                            "error during bot async think: {}",
+                            err.to_string()
+                        );
+
                         tokio::spawn(async move {
                             bot.get_ctx()
                                 .services()
@@ -335,7 +519,7 @@ impl LuaState {
                                 .ok();
                         });
                     } else {
-                        println!("error during bot async think: {}", err.to_string());
+                        log_error!("modules/lua", "error during bot async think: {}", err.to_string());
                     }
                 }
 
@@ -346,12 +530,15 @@ impl LuaState {
             }
         }
 
-        self.think_async_callbacks()?;
-
         Ok(())
     }
 
     fn think_async_callbacks(&self) -> Result<()> {
+        METRICS.lua_async_queue_depth.set(
+            &[if self.sandbox { "sandbox" } else { "bot" }],
+            self.async_receiver.len() as f64,
+        );
+
         loop {
             // Check for async callbacks
             match self.async_receiver.try_recv() {
@@ -455,9 +642,29 @@ impl LuaState {
         }
     }
 
-    pub fn async_sender(&self) -> Sender<LuaAsyncCallback> {
+    pub fn async_sender(&self) -> LuaAsyncSender {
         self.async_sender.clone()
     }
+
+    pub fn used_memory(&self) -> usize {
+        self.inner.used_memory()
+    }
+
+    /// Memory that a single sandbox execution can allocate
+    pub fn set_run_memory_limit(&mut self, limit: usize) {
+        self.run_memory_limit = limit.min(MEMORY_LIMIT);
+    }
+
+    /// Whether the state ran out of memory outside of the per execution limit and should be replaced
+    pub fn out_of_memory(&self) -> bool {
+        self.out_of_memory.load(Ordering::Relaxed)
+    }
+
+    fn note_error(&self, err: &anyhow::Error) {
+        if err.downcast_ref::<LuaError>().map_or(false, is_memory_error) {
+            self.out_of_memory.store(true, Ordering::Relaxed);
+        }
+    }
 }
 
 pub enum SandboxMsg {
@@ -466,10 +673,25 @@ pub enum SandboxMsg {
     Terminated(SandboxTerminationReason),
 }
 
+#[derive(Clone, Copy)]
 pub enum SandboxTerminationReason {
     Done,
     ExecutionQuota,
     TimeLimit,
+    MemoryLimit,
+    QuotaExhausted(QuotaExhausted),
+}
+
+impl SandboxTerminationReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SandboxTerminationReason::Done => "done",
+            SandboxTerminationReason::ExecutionQuota => "execution_quota",
+            SandboxTerminationReason::TimeLimit => "time_limit",
+            SandboxTerminationReason::MemoryLimit => "memory_limit",
+            SandboxTerminationReason::QuotaExhausted(_) => "quota_exhausted",
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -479,16 +701,167 @@ impl SandboxState {
     pub fn limits(&self) -> &SandboxLimits {
         &self.0.limits
     }
+
+    /// Take one from the user and channel budgets of the execution
+    pub fn take_quota(&self, budget: Budget) -> Result<(), QuotaExhausted> {
+        self.0.quotas.take(budget)
+    }
+
+    /// Resume a thread running user code, returning the results like `coroutine.resume`
+    fn resume<'lua>(
+        &self,
+        state: &'lua Lua,
+        thread: Thread<'lua>,
+    ) -> mlua::Result<LuaMultiValue<'lua>> {
+        // Async callbacks can resume a thread while another execution is the current one
+        state.set_named_registry_value("__SANDBOX_STATE", self.clone())?;
+
+        // Lower the memory limit of the state to what is left of the budget of the execution, once
+        // terminated the limit is left alone so the error can still be reported
+        let prev_memory_limit = {
+            let mut execution = self.0.execution.lock().unwrap();
+            execution.resumed_at = Some(Instant::now());
+
+            if execution.terminated.is_none() {
+                let used_memory = state.used_memory();
+                let memory_left = self.0.limits.memory.saturating_sub(execution.memory_used);
+                execution.resumed_memory = used_memory;
+
+                Some(state.set_memory_limit((used_memory + memory_left).min(MEMORY_LIMIT))?)
+            } else {
+                None
+            }
+        };
+
+        let res = thread.resume::<_, LuaMultiValue>(());
+
+        let out_of_memory = {
+            let mut execution = self.0.execution.lock().unwrap();
+
+            if let Some(resumed_at) = execution.resumed_at.take() {
+                execution.time_used += resumed_at.elapsed();
+            }
+
+            if let Some(prev_memory_limit) = prev_memory_limit {
+                state.set_memory_limit(prev_memory_limit)?;
+
+                // Memory freed by the execution is credited back to its budget
+                execution.memory_used = (execution.memory_used + state.used_memory())
+                    .saturating_sub(execution.resumed_memory);
+            }
+
+            match res {
+                Err(ref err) if is_memory_error(err) && execution.terminated.is_none() => {
+                    execution.terminated = Some(SandboxTerminationReason::MemoryLimit);
+                    self.0
+                        .sender
+                        .send(SandboxMsg::Terminated(SandboxTerminationReason::MemoryLimit))
+                        .ok();
+                    true
+                }
+                _ => false,
+            }
+        };
+
+        // Give the memory back to the other executions right away
+        if out_of_memory {
+            state.gc_collect()?;
+        }
+
+        let values = match res {
+            Ok(values) => {
+                let mut values = values.into_vec();
+                values.insert(0, LuaValue::Boolean(true));
+                values
+            }
+            Err(err) => {
+                let message = match err {
+                    LuaError::RuntimeError(message) => message,
+                    err => err.to_string(),
+                };
+
+                vec![
+                    LuaValue::Boolean(false),
+                    LuaValue::String(state.create_string(&message)?),
+                ]
+            }
+        };
+
+        Ok(LuaMultiValue::from_vec(values))
+    }
+
+    // Count the instructions since the last check, the first limit that is hit terminates the execution
+    fn check_limits(&self) -> Option<SandboxTerminationReason> {
+        let mut execution = self.0.execution.lock().unwrap();
+        let resumed_at = execution.resumed_at?;
+
+        // Keep stopping the execution once it is terminated
+        if let Some(reason) = execution.terminated {
+            return Some(reason);
+        }
+
+        let instructions_run = self
+            .0
+            .instructions_run
+            .fetch_add(HOOK_EVERY_INSTRUCTION as u64, Ordering::Relaxed)
+            + HOOK_EVERY_INSTRUCTION as u64;
+
+        // Take a chunk from the rolling budgets every time the count passes a multiple of the chunk
+        let chunk_quota = if instructions_run % INSTRUCTION_CHUNK < HOOK_EVERY_INSTRUCTION as u64 {
+            self.0.quotas.take(Budget::Instructions)
+        } else {
+            Ok(())
+        };
+
+        let reason = if instructions_run >= self.0.limits.instructions {
+            SandboxTerminationReason::ExecutionQuota
+        } else if execution.time_used + resumed_at.elapsed() > self.0.limits.time_limit {
+            SandboxTerminationReason::TimeLimit
+        } else if let Err(exhausted) = chunk_quota {
+            SandboxTerminationReason::QuotaExhausted(exhausted)
+        } else {
+            return None;
+        };
+
+        execution.terminated = Some(reason);
+        self.0.sender.send(SandboxMsg::Terminated(reason)).ok();
+
+        Some(reason)
+    }
+}
+
+/// Time and memory used by the user code of an execution
+#[derive(Default)]
+pub struct SandboxExecution {
+    resumed_at: Option<Instant>,
+    resumed_memory: usize,
+    time_used: Duration,
+    memory_used: usize,
+    terminated: Option<SandboxTerminationReason>,
 }
 
 pub struct SandboxStateInner {
-    pub async_sender: Sender<LuaAsyncCallback>,
-    pub sender: Sender<SandboxMsg>,
+    pub async_sender: LuaAsyncSender,
+    pub sender: UnboundedSender<SandboxMsg>,
     pub instructions_run: AtomicU64,
+    pub execution: StdMutex<SandboxExecution>,
+    pub quotas: ExecutionQuotas,
+    pub storage: SandboxStorage,
     pub limits: SandboxLimits,
     pub http_rate_limiter: Arc<RateLimiter<NotKeyed, InMemoryState, QuantaClock>>,
 }
 
+impl SandboxStateInner {
+    /// Stop the execution from outside, its code errors the next time it runs
+    pub fn terminate(&self, reason: SandboxTerminationReason) {
+        let mut execution = self.execution.lock().unwrap();
+
+        if execution.terminated.is_none() {
+            execution.terminated = Some(reason);
+        }
+    }
+}
+
 pub struct SandboxLimits {
     pub lines_left: AtomicU64,
     pub characters_left: AtomicU64,
@@ -499,7 +872,16 @@ pub struct SandboxLimits {
     pub message_deletions_left: AtomicU64,
     pub images_left: AtomicU64,
     pub image_operations_left: AtomicU64,
+    pub storage_operations_left: AtomicU64,
+    /// Bytes in a storage key
+    pub storage_key_size: usize,
+    /// Bytes in a JSON encoded storage value
+    pub storage_value_size: usize,
+    /// Bytes in all the keys and values of a storage namespace
+    pub storage_size: usize,
     pub instructions: u64,
+    pub time_limit: Duration,
+    pub memory: usize,
 }
 
 impl SandboxLimits {
@@ -511,6 +893,7 @@ impl SandboxLimits {
     atomic_limit! {message_deletions_left}
     atomic_limit! {images_left}
     atomic_limit! {image_operations_left}
+    atomic_limit! {storage_operations_left}
 }
 
 impl UserData for SandboxState {
@@ -525,17 +908,8 @@ impl UserData for SandboxState {
             Ok(())
         });
 
-        methods.add_method("set_instructions_run", |_, this, value: u64| {
-            this.0.instructions_run.store(value, Ordering::Relaxed);
-            Ok(())
-        });
-
-        methods.add_method("get_instructions_run", |_, this, _: ()| {
-            Ok(this.0.instructions_run.load(Ordering::Relaxed))
-        });
-
-        methods.add_method("get_instruction_limit", |_, this, _: ()| {
-            Ok(this.0.limits.instructions)
+        methods.add_method("resume", |state, this, thread: Thread| {
+            this.resume(state, thread)
         });
 
         methods.add_method("set_state", |state, this, _: ()| {
@@ -550,11 +924,37 @@ impl UserData for SandboxState {
             },
         );
 
+        methods.add_method(
+            "storage_get",
+            |state, this, (scope, key): (String, String)| {
+                storage::storage_get(state, this, &scope, key)
+            },
+        );
+
+        methods.add_method(
+            "storage_set",
+            |state, this, (scope, key, value): (String, String, LuaValue)| {
+                storage::storage_set(state, this, &scope, key, value)
+            },
+        );
+
+        methods.add_method(
+            "storage_delete",
+            |state, this, (scope, key): (String, String)| {
+                storage::storage_delete(state, this, &scope, key)
+            },
+        );
+
+        methods.add_method(
+            "storage_list",
+            |state, this, (scope, prefix): (String, Option<String>)| {
+                storage::storage_list(state, this, &scope, prefix)
+            },
+        );
+
         methods.add_method("terminate", |_, this, value: String| {
             let reason = match value.as_ref() {
                 "done" => SandboxTerminationReason::Done,
-                "exec" => SandboxTerminationReason::ExecutionQuota,
-                "time" => SandboxTerminationReason::TimeLimit,
                 _ => {
                     return Err(LuaError::RuntimeError(format!(
                         "unknown termination reason: \"{}\"",
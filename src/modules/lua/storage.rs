@@ -0,0 +1,196 @@
+use mlua::{
+    prelude::{LuaError, LuaSerdeExt, LuaTable, LuaValue},
+    Lua,
+};
+use std::sync::Arc;
+
+use super::{lib::bot::BotMessage, state::SandboxState};
+use crate::{
+    bot::{
+        db::{storage::StorageScope, Uid},
+        Bot,
+    },
+    services::ServerId,
+};
+
+/// The storage namespaces of the user running sandboxed code and the server it runs in
+pub struct SandboxStorage {
+    bot: Arc<Bot>,
+    uid: Uid,
+    server_id: ServerId,
+}
+
+impl SandboxStorage {
+    pub fn new(bot: Arc<Bot>, msg: &BotMessage) -> SandboxStorage {
+        SandboxStorage {
+            bot,
+            uid: msg.author().uid(),
+            server_id: msg.channel().server().id(),
+        }
+    }
+
+    fn scope(&self, scope: &str) -> Result<StorageScope, LuaError> {
+        match scope {
+            "user" => Ok(StorageScope::User(self.uid)),
+            "server" => Ok(StorageScope::Server(self.server_id)),
+            _ => Err(LuaError::RuntimeError(format!(
+                "unknown storage scope \"{}\"",
+                scope
+            ))),
+        }
+    }
+}
+
+// Count the operation against the limits of the execution and validate the key
+fn check_operation(sandbox_state: &SandboxState, key: &str) -> Result<(), LuaError> {
+    let limits = sandbox_state.limits();
+
+    if limits.storage_operations_left_limit() {
+        return Err(LuaError::RuntimeError(
+            "storage operation limit reached".into(),
+        ));
+    }
+
+    if key.len() > limits.storage_key_size {
+        return Err(LuaError::RuntimeError(format!(
+            "storage keys cannot be longer than {} bytes",
+            limits.storage_key_size
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn storage_get<'a>(
+    state: &'a Lua,
+    sandbox_state: &SandboxState,
+    scope: &str,
+    key: String,
+) -> Result<LuaTable<'a>, LuaError> {
+    check_operation(sandbox_state, &key)?;
+
+    let storage = &sandbox_state.0.storage;
+    let (bot, scope) = (storage.bot.clone(), storage.scope(scope)?);
+
+    let fut = create_lua_future!(
+        state,
+        sandbox_state.0.async_sender,
+        (),
+        async move { bot.db().storage_get(scope, &key).await },
+        |state, _data: (), res: anyhow::Result<Option<String>>| {
+            match res? {
+                Some(value) => {
+                    let value: serde_json::Value = serde_json::from_str(&value)?;
+                    Ok(state.to_value(&value)?)
+                }
+                None => Ok(LuaValue::Nil),
+            }
+        }
+    );
+
+    Ok(fut)
+}
+
+/// Setting a key to nil deletes it
+pub fn storage_set<'a>(
+    state: &'a Lua,
+    sandbox_state: &SandboxState,
+    scope: &str,
+    key: String,
+    value: LuaValue<'a>,
+) -> Result<LuaTable<'a>, LuaError> {
+    check_operation(sandbox_state, &key)?;
+
+    let limits = sandbox_state.limits();
+    let storage = &sandbox_state.0.storage;
+    let (bot, scope) = (storage.bot.clone(), storage.scope(scope)?);
+
+    let value = match value {
+        LuaValue::Nil => None,
+        value => {
+            let value: serde_json::Value = state.from_value(value)?;
+            let encoded = serde_json::to_string(&value)
+                .map_err(|err| LuaError::ExternalError(Arc::new(err)))?;
+
+            if encoded.len() > limits.storage_value_size {
+                return Err(LuaError::RuntimeError(format!(
+                    "storage values cannot be larger than {} bytes",
+                    limits.storage_value_size
+                )));
+            }
+
+            Some(encoded)
+        }
+    };
+    let max_size = limits.storage_size;
+
+    let fut = create_lua_future!(
+        state,
+        sandbox_state.0.async_sender,
+        (max_size,),
+        async move {
+            match value {
+                Some(value) => bot.db().storage_set(scope, &key, &value, max_size).await,
+                None => bot.db().storage_delete(scope, &key).await.map(|_| true),
+            }
+        },
+        |_state, data: (usize,), res: anyhow::Result<bool>| {
+            if !res? {
+                return Err(anyhow::anyhow!(
+                    "storage is full, it can hold at most {} bytes",
+                    data.0
+                ));
+            }
+
+            Ok(())
+        }
+    );
+
+    Ok(fut)
+}
+
+pub fn storage_delete<'a>(
+    state: &'a Lua,
+    sandbox_state: &SandboxState,
+    scope: &str,
+    key: String,
+) -> Result<LuaTable<'a>, LuaError> {
+    check_operation(sandbox_state, &key)?;
+
+    let storage = &sandbox_state.0.storage;
+    let (bot, scope) = (storage.bot.clone(), storage.scope(scope)?);
+
+    let fut = create_lua_future!(
+        state,
+        sandbox_state.0.async_sender,
+        (),
+        async move { bot.db().storage_delete(scope, &key).await },
+        |_state, _data: (), res: anyhow::Result<bool>| { res }
+    );
+
+    Ok(fut)
+}
+
+pub fn storage_list<'a>(
+    state: &'a Lua,
+    sandbox_state: &SandboxState,
+    scope: &str,
+    prefix: Option<String>,
+) -> Result<LuaTable<'a>, LuaError> {
+    let prefix = prefix.unwrap_or_default();
+
+    check_operation(sandbox_state, &prefix)?;
+
+    let storage = &sandbox_state.0.storage;
+    let (bot, scope) = (storage.bot.clone(), storage.scope(scope)?);
+
+    let fut = create_lua_future!(
+        state,
+        sandbox_state.0.async_sender,
+        (),
+        async move { bot.db().storage_list(scope, &prefix).await },
+        |_state, _data: (), res: anyhow::Result<Vec<String>>| { res }
+    );
+
+    Ok(fut)
+}
@@ -2,10 +2,11 @@ use anyhow::Result;
 use async_mutex::Mutex;
 use lru::LruCache;
 use rand::{distributions, Rng};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::fs;
 
-use super::{Module, ModuleKind};
+use super::Module;
 use crate::{
     bot::Bot,
     message::MessageSettings,
@@ -14,6 +15,10 @@ use crate::{
     utils::ci_regex,
 };
 
+/// The `[modules.utils]` section of the config, the module has no options yet
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+pub struct UtilsModuleConfig {}
+
 pub struct UtilsModule {
     bot: Arc<Bot>,
     last_generated_messages: Mutex<LruCache<MessageId, (ChannelId, MessageId)>>,
@@ -24,7 +29,7 @@ settings! {
     UtilsModuleSettings,
     UtilsModule,
     {
-        extract_media_urls: bool => (false, SettingFlags::empty(), "Extract media urls out of tweets", [])
+        extract_media_urls: bool => (false, SettingFlags::USER_OVERRIDE, "Extract media urls out of tweets, users can only turn it off for themselves", [])
     }
 }
 
@@ -32,14 +37,13 @@ pub static IMAGE_EXTENSIONS: &[&'static str] = &[".png", ".jpg", ".jpeg", ".gif"
 
 #[async_trait]
 impl Module for UtilsModule {
-    const KIND: ModuleKind = ModuleKind::Utils;
     const ID: &'static str = "utils";
     const NAME: &'static str = "Utils";
 
-    type ModuleConfig = ();
+    type ModuleConfig = UtilsModuleConfig;
     type ModuleSettings = UtilsModuleSettings;
 
-    async fn load(bot: Arc<Bot>, _config: ()) -> Result<Arc<UtilsModule>> {
+    async fn load(bot: Arc<Bot>, _config: UtilsModuleConfig) -> Result<Arc<UtilsModule>> {
         Ok(Arc::new(UtilsModule {
             bot: bot.clone(),
             last_generated_messages: Mutex::new(LruCache::new(64)),
@@ -116,11 +120,12 @@ impl Module for UtilsModule {
         if !matches.is_empty() {
             let channel = msg.channel().await?;
             let server = channel.server().await?;
-            let extract_media_urls = self
-                .settings
-                .extract_media_urls
-                .value(server.id(), channel.id())
-                .await?;
+            // Users can only turn it off for themselves where the channel or server enabled it
+            let setting = &self.settings.extract_media_urls;
+            let extract_media_urls = setting.value(server.id(), channel.id(), None).await?
+                && setting
+                    .value(server.id(), channel.id(), Some(user.uid))
+                    .await?;
 
             if extract_media_urls {
                 let mut out = Vec::new();
@@ -163,7 +168,16 @@ impl Module for UtilsModule {
                             }
                         }
                         Err(err) => {
-                            println!("Error from yt-dlp url extraction: {}", err.to_string())
+                            log_warn!(
+                                "modules/utils",
+                                {
+                                    server = server.id().to_str(),
+                                    channel = channel.id().to_str(),
+                                    url = media_url,
+                                },
+                                "Error from yt-dlp url extraction: {}",
+                                err.to_string()
+                            )
                         }
                     }
 
@@ -260,10 +274,6 @@ impl Module for UtilsModule {
         Ok(())
     }
 
-    async fn enabled(&self, _server_id: ServerId, _channel_id: ChannelId) -> Result<bool> {
-        Ok(true)
-    }
-
     fn settings(&self) -> &Arc<UtilsModuleSettings> {
         &self.settings
     }
@@ -1,6 +1,6 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use std::{str::FromStr, sync::Arc};
+use std::{any::Any, str::FromStr, sync::Arc};
 
 pub mod discord;
 
@@ -8,6 +8,7 @@ use crate::{
     bot::Bot,
     config::ConfigServices,
     message::{Attachment, MessageSettings, ToMessageContent},
+    utils::tasks::TaskTracker,
 };
 
 macro_rules! service_id_functions {
@@ -51,13 +52,55 @@ macro_rules! service_id_functions {
                 }
             }
         }
+
+        // Serialized in the same "service:id" form as `to_str`
+        impl Serialize for $id {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.to_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $id {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<$id, D::Error> {
+                let text = String::deserialize(deserializer)?;
+                $id::from_str(&text).map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+// Enum over the trait objects of every service, for places where generic functions cannot be used
+// like in trait objects
+macro_rules! any_service_object {
+    ($any:ident, $trait:ident, $(($service_module_ident:ident, $service:ty)),+) => {
+        #[derive(Clone)]
+        pub enum $any {
+            $($service_module_ident (Arc<dyn $trait<$service>>)),+
+        }
+
+        impl $any {
+            pub fn new<S: Service>(object: Arc<dyn $trait<S>>) -> $any {
+                let object: Box<dyn Any> = Box::new(object);
+
+                $(
+                    let object = match object.downcast::<Arc<dyn $trait<$service>>>() {
+                        Ok(object) => return $any::$service_module_ident(*object),
+                        Err(object) => object,
+                    };
+                )+
+
+                drop(object);
+                unreachable!("{} from an unknown service", stringify!($trait))
+            }
+        }
     };
 }
 
 macro_rules! services {
     ($services_struct:ident, $($service_ident:ident => ($service_module_ident:ident, $service:ty)),*) => {
         pub struct $services_struct {
-            $(pub $service_ident: Option<ServiceWrapper<$service>>),+
+            $(pub $service_ident: Option<ServiceWrapper<$service>>,)+
+            tasks: Arc<TaskTracker>,
         }
 
         impl $services_struct {
@@ -66,18 +109,31 @@ macro_rules! services {
                 Ok(Arc::new($services_struct {
                     $(
                         $service_ident: if let Some(service_config) = config.$service_ident.clone() {
-                            Some(ServiceWrapper::new(<$service>::init(bot, service_config).await?))
+                            Some(ServiceWrapper::new(<$service>::init(bot.clone(), service_config).await?))
                         } else {
                             None
-                        }
-                    ),+
+                        },
+                    )+
+                    tasks: bot.tasks().clone(),
                 }))
             }
 
+            pub async fn unload(&self) -> Result<()> {
+                $(
+                    if let Some(service) = self.$service_ident.as_ref() {
+                        service.service().unload().await?;
+                    }
+                )+
+
+                Ok(())
+            }
+
             pub async fn send_message<'a, C>(&self, channel_id: ChannelId, content: C, settings: MessageSettings) -> Result<Arc<dyn Message<impl Service>>>
             where
                 C: ToMessageContent<'a>
             {
+                let _task = self.tasks.track();
+
                 match channel_id {
                     $(
                         ChannelId::$service_module_ident (id) => {
@@ -121,6 +177,8 @@ macro_rules! services {
             where
                 C: ToMessageContent<'a>
             {
+                let _task = self.tasks.track();
+
                 match channel_id {
                     $(
                         ChannelId::$service_module_ident (id) => {
@@ -141,6 +199,8 @@ macro_rules! services {
 
             #[allow(unreachable_patterns)]
             pub async fn delete_message(&self, channel_id: ChannelId, message_id: MessageId) -> Result<()> {
+                let _task = self.tasks.track();
+
                 match channel_id {
                     $(
                         ChannelId::$service_module_ident (id) => {
@@ -208,6 +268,24 @@ macro_rules! services {
                 }
             }
 
+            #[allow(unreachable_patterns)]
+            pub async fn server_has_role(&self, server_id: ServerId, role_id: RoleId) -> Result<bool> {
+                match (server_id, role_id) {
+                    $(
+                        (ServerId::$service_module_ident(id), RoleId::$service_module_ident(role_id)) => {
+                            let server = self.$service_ident.as_ref()
+                            .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                            .service()
+                            .server(id)
+                            .await?;
+
+                            Ok(server.has_role(&role_id))
+                        }
+                    ),+
+                    _ => Ok(false),
+                }
+            }
+
             #[allow(unreachable_patterns)]
             pub async fn message(&self, channel_id: ChannelId, message_id: MessageId) -> Result<Arc<dyn Message<impl Service>>> {
                 match (channel_id, message_id) {
@@ -268,6 +346,8 @@ macro_rules! services {
 
             #[allow(unreachable_patterns)]
             pub async fn react(&self, channel_id: ChannelId, message_id: MessageId, reaction: String) -> Result<()> {
+                let _task = self.tasks.track();
+
                 match channel_id {
                     $(
                         ChannelId::$service_module_ident(channel_id) => {
@@ -322,6 +402,38 @@ macro_rules! services {
 
         service_id_functions!{UserId, UserId, $(($service_module_ident, $service)),+}
 
+        #[derive(Copy, Clone, Hash, Eq, PartialEq)]
+        pub enum RoleId {
+            $($service_module_ident (<$service as Service>::RoleId)),+
+        }
+
+        service_id_functions!{RoleId, RoleId, $(($service_module_ident, $service)),+}
+
+        any_service_object!{AnyMessage, Message, $(($service_module_ident, $service)),+}
+        any_service_object!{AnyUser, User, $(($service_module_ident, $service)),+}
+
+        impl AnyMessage {
+            pub fn author_id(&self) -> UserId {
+                match self {
+                    $(AnyMessage::$service_module_ident(msg) => msg.author().id()),+
+                }
+            }
+
+            /// The server and channel of the message, if it was sent in a server
+            pub async fn location(&self) -> Option<(ServerId, ChannelId)> {
+                match self {
+                    $(
+                        AnyMessage::$service_module_ident(msg) => {
+                            let channel = msg.channel().await.ok()?;
+                            let server = channel.server().await.ok()?;
+
+                            Some((server.id(), channel.id()))
+                        }
+                    ),+
+                }
+            }
+        }
+
         #[derive(Copy, Clone, Hash, PartialEq)]
         pub enum ServiceKind {
             $($service_module_ident),+
@@ -379,6 +491,7 @@ pub trait Service: 'static + Sized + Send + Sync {
     type ChannelId: Send + Sync;
     type ServerId: Send + Sync;
     type UserId: Send + Sync;
+    type RoleId: Send + Sync;
 
     async fn init(bot: Arc<Bot>, config: Self::ServiceConfig) -> Result<Arc<Self>>;
     async fn unload(&self) -> Result<()>;
@@ -473,6 +586,7 @@ pub trait Channel<S: Service>: Send + Sync {
 pub trait Server<S: Service>: Send + Sync {
     fn id(&self) -> ServerId;
     fn name(&self) -> &str;
+    fn has_role(&self, id: &S::RoleId) -> bool;
     fn service(&self) -> &Arc<S>;
 }
 
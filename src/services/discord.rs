@@ -4,7 +4,7 @@ use async_mutex::Mutex as AsyncMutex;
 use futures::future::{AbortHandle, Abortable};
 use lru::LruCache;
 use serenity::{
-    client::Context,
+    client::{bridge::gateway::ShardManager, Context},
     http::CacheHttp,
     model::{
         channel::{Message, Reaction, ReactionType},
@@ -36,11 +36,12 @@ pub struct DiscordService {
     bot: Arc<Bot>,
     cache_and_http: ArcSwapOption<CacheAndHttp>,
     context: ArcSwapOption<Context>,
+    shard_manager: ArcSwapOption<tokio::sync::Mutex<ShardManager>>,
     ready_abort: Mutex<Option<AbortHandle>>,
     user_cache: AsyncMutex<LruCache<u64, Arc<DiscordUser>>>,
 }
 
-#[derive(Clone, Deserialize, Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
 pub struct DiscordServiceConfig {
     pub token: String,
 }
@@ -90,7 +91,8 @@ impl EventHandler for SerenityHandler {
 
         self.service.context.store(Some(Arc::new(context)));
 
-        println!(
+        log_info!(
+            "services/discord",
             "{}#{:04} is connected!",
             ready.user.name, ready.user.discriminator
         );
@@ -168,12 +170,14 @@ impl Service for DiscordService {
     type ChannelId = u64;
     type ServerId = u64;
     type UserId = u64;
+    type RoleId = u64;
 
     async fn init(bot: Arc<Bot>, config: Self::ServiceConfig) -> Result<Arc<Self>> {
         let service = Arc::new(DiscordService {
             bot,
             cache_and_http: ArcSwapOption::new(None),
             context: ArcSwapOption::new(None),
+            shard_manager: ArcSwapOption::new(None),
             ready_abort: Default::default(),
             user_cache: AsyncMutex::new(LruCache::new(64)),
         });
@@ -190,7 +194,8 @@ impl Service for DiscordService {
                 Err(err) => {
                     let time = 2 ^ retry_count;
                     retry_count += 1;
-                    println!(
+                    log_error!(
+                        "services/discord",
                         "Error creating discord client: {}, retrying in {} seconds",
                         err.to_string(),
                         time
@@ -211,6 +216,9 @@ impl Service for DiscordService {
         service
             .cache_and_http
             .store(Some(client.cache_and_http.clone()));
+        service
+            .shard_manager
+            .store(Some(client.shard_manager.clone()));
 
         async fn wrap_client(mut client: Client) -> Result<()> {
             let mut retry_count = 1;
@@ -221,7 +229,8 @@ impl Service for DiscordService {
                     Err(err) => {
                         let time = 2 ^ retry_count;
                         retry_count += 1;
-                        println!(
+                        log_error!(
+                            "services/discord",
                             "Error connecting to discord: {}, retrying in {} seconds",
                             err.to_string(),
                             time
@@ -249,6 +258,11 @@ impl Service for DiscordService {
         Ok(service)
     }
     async fn unload(&self) -> Result<()> {
+        // Close the gateway connections
+        if let Some(shard_manager) = self.shard_manager.load_full() {
+            shard_manager.lock().await.shutdown_all().await;
+        }
+
         Ok(())
     }
 
@@ -1,4 +1,4 @@
-use serenity::model::guild;
+use serenity::model::{guild, id::RoleId};
 use std::sync::Arc;
 
 use super::DiscordService;
@@ -25,6 +25,10 @@ impl Server<DiscordService> for DiscordServer {
         &self.guild.name
     }
 
+    fn has_role(&self, id: &u64) -> bool {
+        self.guild.roles.contains_key(&RoleId(*id))
+    }
+
     fn service(&self) -> &Arc<DiscordService> {
         &self.service
     }
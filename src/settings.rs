@@ -1,16 +1,16 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::{marker::PhantomData, str::FromStr, sync::Arc};
+use std::{fmt::Display, marker::PhantomData, str::FromStr, sync::Arc, time::Duration};
 use thiserror::Error;
 
 use crate::{
-    bot::Bot,
+    bot::{db::Uid, Bot},
     modules::Module,
-    services::{ChannelId, ServerId},
+    services::{Channel, ChannelId, RoleId, Server, ServerId, UserId},
 };
 
 macro_rules! settings {
-    ($sname:ident, $module:ident, { $($name:ident: $type:ty => ($default:expr, $flags:expr, $help:expr, [ $($setting_ident:ident => $setting_value:expr)* ])),* }) => {
+    ($sname:ident, $module:ident, { $($name:ident: $type:ty => ($default:expr, $flags:expr, $help:expr, [ $($setting_ident:ident => $setting_value:expr),* $(,)? ])),* }) => {
         pub struct $sname {
             $(
                 pub $name: crate::settings::Setting<$type, $module>,
@@ -53,6 +53,41 @@ macro_rules! settings {
                     _ => Err(anyhow::anyhow!("unknown setting"))
                 }
             }
+
+            async fn get_setting(&self, setting: &str, server_id: $crate::services::ServerId, channel_id: $crate::services::ChannelId, uid: Option<$crate::bot::db::Uid>) -> Result<$crate::settings::EffectiveSetting> {
+                match setting {
+                    $(
+                        stringify!($name) => self.$name.effective_value(server_id, channel_id, uid).await,
+                    )*
+                    _ => Err(anyhow::anyhow!("unknown setting"))
+                }
+            }
+
+            fn check_input(setting: &str, ctx: &$crate::settings::SettingContext, input: &str) -> Result<()> {
+                $(
+                    #[allow(unused, non_camel_case_types)]
+                    type $name = <$type as SettingValue>::Parameters;
+                )*
+
+                match setting {
+                    $(
+                        stringify!($name) => $crate::settings::parse_input::<$type>(stringify!($name), $flags, ctx, input, &$name {
+                            $($setting_ident: Some($setting_value),)*
+                            ..Default::default()
+                        }).map(|_| ()),
+                    )*
+                    _ => Err(anyhow::anyhow!("unknown setting"))
+                }
+            }
+
+            async fn unset_setting(&self, ctx: $crate::settings::SettingContext, setting: &str) -> Result<bool> {
+                match setting {
+                    $(
+                        stringify!($name) => self.$name.unset_value(ctx).await,
+                    )*
+                    _ => Err(anyhow::anyhow!("unknown setting"))
+                }
+            }
         }
     };
 }
@@ -60,6 +95,8 @@ macro_rules! settings {
 bitflags! {
     pub struct SettingFlags: u8 {
         const SERVER_OVERRIDE = 1;
+        // Users can set their own value, which is used before the channel and server values
+        const USER_OVERRIDE = 2;
     }
 }
 
@@ -109,38 +146,92 @@ where
         SettingInfo {
             name: self.name.clone(),
             help: self.help.clone(),
+            kind: T::setting_type(),
+            allowed: T::allowed(&self.parameters),
+            user_override: self.flags.contains(SettingFlags::USER_OVERRIDE),
         }
     }
 
-    pub async fn value(&self, server_id: ServerId, channel_id: ChannelId) -> Result<T> {
+    fn key(&self) -> String {
+        format!("{}/{}", M::ID, self.name)
+    }
+
+    /// Resolve the value, a server value set with `SERVER_OVERRIDE` wins over everything else and
+    /// otherwise it is user, channel, server and then the default
+    pub async fn value(
+        &self,
+        server_id: ServerId,
+        channel_id: ChannelId,
+        uid: Option<Uid>,
+    ) -> Result<T> {
+        Ok(self.value_with_source(server_id, channel_id, uid).await?.0)
+    }
+
+    pub async fn value_with_source(
+        &self,
+        server_id: ServerId,
+        channel_id: ChannelId,
+        uid: Option<Uid>,
+    ) -> Result<(T, SettingSource)> {
         if self.flags.contains(SettingFlags::SERVER_OVERRIDE) {
             if let Some(value) = self.get_server_value(server_id).await? {
-                return Ok(value);
+                return Ok((value, SettingSource::Server));
             }
+        }
 
-            if let Some(value) = self.get_channel_value(channel_id).await? {
-                return Ok(value);
+        if let Some(uid) = uid.filter(|_| self.flags.contains(SettingFlags::USER_OVERRIDE)) {
+            if let Some(value) = self.get_user_value(uid).await? {
+                return Ok((value, SettingSource::User));
             }
+        }
 
-            Ok(self.default.clone())
-        } else {
-            if let Some(value) = self.get_channel_value(channel_id).await? {
-                return Ok(value);
-            }
+        if let Some(value) = self.get_channel_value(server_id, channel_id).await? {
+            return Ok((value, SettingSource::Channel));
+        }
 
+        if !self.flags.contains(SettingFlags::SERVER_OVERRIDE) {
             if let Some(value) = self.get_server_value(server_id).await? {
-                return Ok(value);
+                return Ok((value, SettingSource::Server));
             }
-
-            Ok(self.default.clone())
         }
+
+        Ok((self.default.clone(), SettingSource::Default))
     }
 
-    async fn get_channel_value(&self, channel_id: ChannelId) -> Result<Option<T>> {
+    pub async fn effective_value(
+        &self,
+        server_id: ServerId,
+        channel_id: ChannelId,
+        uid: Option<Uid>,
+    ) -> Result<EffectiveSetting> {
+        let (value, source) = self.value_with_source(server_id, channel_id, uid).await?;
+
+        Ok(EffectiveSetting {
+            name: self.name.clone(),
+            value: value.to_input(),
+            source,
+        })
+    }
+
+    async fn get_user_value(&self, uid: Uid) -> Result<Option<T>> {
+        let raw_value = match self.bot.db().get_user_setting(uid, &self.key()).await? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        // Just go back to default if the raw value is invalid
+        Ok(T::set_value(&raw_value, &self.parameters).ok())
+    }
+
+    async fn get_channel_value(
+        &self,
+        server_id: ServerId,
+        channel_id: ChannelId,
+    ) -> Result<Option<T>> {
         let raw_value = match self
             .bot
             .db()
-            .get_channel_setting(channel_id, &format!("{}/{}", M::ID, self.name))
+            .get_channel_setting(Some(server_id), channel_id, &self.key())
             .await?
         {
             Some(v) => v,
@@ -155,7 +246,7 @@ where
         let raw_value = match self
             .bot
             .db()
-            .get_server_setting(server_id, &format!("{}/{}", M::ID, self.name))
+            .get_server_setting(server_id, &self.key())
             .await?
         {
             Some(v) => v,
@@ -168,46 +259,190 @@ where
 
     pub async fn set_value(&self, ctx: SettingContext, input: &str) -> Result<()> {
         // Ensure the value is valid
-        let _value = T::set_value(input, &self.parameters)?;
+        let value = parse_input::<T>(&self.name, self.flags, &ctx, input, &self.parameters)?;
+        value.validate(&self.bot, &ctx, &self.parameters).await?;
 
         match ctx {
             SettingContext::Channel(channel_id) => {
+                // The server is stored with the override so the overrides of a server can be listed
+                let server_id = channel_server(&self.bot, channel_id).await?;
+
                 self.bot
                     .db()
-                    .save_channel_setting(channel_id, &format!("{}/{}", M::ID, self.name), input)
+                    .save_channel_setting(Some(server_id), channel_id, &self.key(), input)
                     .await?;
             }
             SettingContext::Server(server_id) => {
                 self.bot
                     .db()
-                    .save_server_setting(server_id, &format!("{}/{}", M::ID, self.name), input)
+                    .save_server_setting(server_id, &self.key(), input)
+                    .await?;
+            }
+            SettingContext::User(uid) => {
+                self.bot
+                    .db()
+                    .save_user_setting(uid, &self.key(), input)
                     .await?;
             }
         };
 
         Ok(())
     }
+
+    /// Remove the override for the context, returns false if there was none
+    pub async fn unset_value(&self, ctx: SettingContext) -> Result<bool> {
+        match ctx {
+            SettingContext::Channel(channel_id) => {
+                self.bot
+                    .db()
+                    .delete_channel_setting(channel_id, &self.key())
+                    .await
+            }
+            SettingContext::Server(server_id) => {
+                self.bot
+                    .db()
+                    .delete_server_setting(server_id, &self.key())
+                    .await
+            }
+            SettingContext::User(uid) => self.bot.db().delete_user_setting(uid, &self.key()).await,
+        }
+    }
+}
+
+async fn channel_server(bot: &Bot, channel_id: ChannelId) -> Result<ServerId> {
+    let channel = bot
+        .get_ctx()
+        .services()
+        .channel(channel_id)
+        .await
+        .map_err(|_| SettingError::UnknownReference {
+            kind: SettingType::Channel,
+            id: channel_id.to_str(),
+        })?;
+
+    Ok(channel.server().await?.id())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SettingSource {
+    User,
+    Channel,
+    Server,
+    Default,
+}
+
+impl SettingSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettingSource::User => "user",
+            SettingSource::Channel => "channel",
+            SettingSource::Server => "server",
+            SettingSource::Default => "default",
+        }
+    }
+}
+
+// The value a setting resolves to in a channel, formatted the same way it would be set
+pub struct EffectiveSetting {
+    pub name: String,
+    pub value: String,
+    pub source: SettingSource,
 }
 
 // struct used when listing all the settings, due to traits being too complex
 pub struct SettingInfo {
     pub name: String,
     pub help: String,
+    pub kind: SettingType,
+    // Human readable description of the accepted values, if they are restricted
+    pub allowed: Option<String>,
+    pub user_override: bool,
 }
 
 #[async_trait]
 pub trait Settings: Send + Sync {
     fn enumerate(&self) -> Vec<SettingInfo>;
     async fn set_setting(&self, ctx: SettingContext, setting: &str, value: &str) -> Result<()>;
+    async fn get_setting(
+        &self,
+        setting: &str,
+        server_id: ServerId,
+        channel_id: ChannelId,
+        uid: Option<Uid>,
+    ) -> Result<EffectiveSetting>;
+    async fn unset_setting(&self, ctx: SettingContext, setting: &str) -> Result<bool>;
+    /// The checks done before a value is set which don't need a loaded module, used by the CLI
+    fn check_input(setting: &str, ctx: &SettingContext, input: &str) -> Result<()>
+    where
+        Self: Sized;
 }
 
-pub trait SettingValue: Clone + Sized + Deserialize<'static> + Serialize {
-    type Parameters;
+/// Parse the input for a setting, checking that it can be set in the context
+pub fn parse_input<T: SettingValue>(
+    name: &str,
+    flags: SettingFlags,
+    ctx: &SettingContext,
+    input: &str,
+    parameters: &T::Parameters,
+) -> Result<T> {
+    if let SettingContext::User(_) = ctx {
+        if !flags.contains(SettingFlags::USER_OVERRIDE) {
+            return Err(SettingError::NotUserOverridable { name: name.into() }.into());
+        }
+    }
 
+    T::set_value(input, parameters)
+}
+
+#[async_trait]
+pub trait SettingValue: Clone + Sized + Send + Sync + Deserialize<'static> + Serialize {
+    type Parameters: Send + Sync;
+
+    fn setting_type() -> SettingType;
     // Let the value type check that the default value is valid based on the paramters
     fn is_valid(value: &Self, parameters: &Self::Parameters) -> Result<()>;
     // Set
     fn set_value(input: &str, parameters: &Self::Parameters) -> Result<Self>;
+    // Format the value so that `set_value` parses it back to the same value
+    fn to_input(&self) -> String;
+
+    fn allowed(_parameters: &Self::Parameters) -> Option<String> {
+        None
+    }
+
+    // Checks which need the services, only done when a value is set and not when reading it back
+    async fn validate(
+        &self,
+        _bot: &Bot,
+        _ctx: &SettingContext,
+        _parameters: &Self::Parameters,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn range_description<T: Display>(min: Option<T>, max: Option<T>) -> Option<String> {
+    match (min, max) {
+        (Some(min), Some(max)) => Some(format!("from {} to {}", min, max)),
+        (Some(min), None) => Some(format!("at least {}", min)),
+        (None, Some(max)) => Some(format!("at most {}", max)),
+        (None, None) => None,
+    }
+}
+
+fn check_range<T: PartialOrd + Display>(value: T, min: Option<T>, max: Option<T>) -> Result<()> {
+    let below = min.as_ref().map(|min| value < *min).unwrap_or(false);
+    let above = max.as_ref().map(|max| value > *max).unwrap_or(false);
+
+    if below || above {
+        return Err(SettingError::OutOfRange {
+            value: value.to_string(),
+            allowed: range_description(min, max).unwrap_or_default(),
+        }
+        .into());
+    }
+
+    Ok(())
 }
 
 // Setting value - bool
@@ -215,6 +450,14 @@ pub trait SettingValue: Clone + Sized + Deserialize<'static> + Serialize {
 impl SettingValue for bool {
     type Parameters = SettingBoolParameters;
 
+    fn setting_type() -> SettingType {
+        SettingType::Bool
+    }
+
+    fn to_input(&self) -> String {
+        self.to_string()
+    }
+
     fn is_valid(_value: &bool, _parameters: &SettingBoolParameters) -> Result<()> {
         Ok(())
     }
@@ -248,6 +491,14 @@ pub struct SettingBoolParameters {}
 impl SettingValue for String {
     type Parameters = SettingStringParameters;
 
+    fn setting_type() -> SettingType {
+        SettingType::String
+    }
+
+    fn to_input(&self) -> String {
+        self.clone()
+    }
+
     fn is_valid(value: &String, parameters: &SettingStringParameters) -> Result<()> {
         if let Some(max_len) = parameters.max_len {
             let len = value.len();
@@ -268,6 +519,12 @@ impl SettingValue for String {
 
         Ok(input.into())
     }
+
+    fn allowed(parameters: &SettingStringParameters) -> Option<String> {
+        parameters
+            .max_len
+            .map(|max_len| format!("at most {} characters", max_len))
+    }
 }
 
 #[derive(Default)]
@@ -275,27 +532,666 @@ pub struct SettingStringParameters {
     pub max_len: Option<usize>,
 }
 
+// Setting value - i64
+
+impl SettingValue for i64 {
+    type Parameters = SettingIntegerParameters;
+
+    fn setting_type() -> SettingType {
+        SettingType::Integer
+    }
+
+    fn to_input(&self) -> String {
+        self.to_string()
+    }
+
+    fn is_valid(value: &i64, parameters: &SettingIntegerParameters) -> Result<()> {
+        check_range(*value, parameters.min, parameters.max)
+    }
+
+    fn set_value(input: &str, parameters: &SettingIntegerParameters) -> Result<i64> {
+        let value = i64::from_str(input.trim()).map_err(|_| SettingError::UnexpectedInput {
+            expected: SettingType::Integer,
+            input: input.into(),
+        })?;
+
+        <i64 as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn allowed(parameters: &SettingIntegerParameters) -> Option<String> {
+        range_description(parameters.min, parameters.max)
+    }
+}
+
+#[derive(Default)]
+pub struct SettingIntegerParameters {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+// Setting value - f64
+
+impl SettingValue for f64 {
+    type Parameters = SettingFloatParameters;
+
+    fn setting_type() -> SettingType {
+        SettingType::Float
+    }
+
+    fn to_input(&self) -> String {
+        self.to_string()
+    }
+
+    fn is_valid(value: &f64, parameters: &SettingFloatParameters) -> Result<()> {
+        if !value.is_finite() {
+            return Err(SettingError::UnexpectedInput {
+                expected: SettingType::Float,
+                input: value.to_string(),
+            }
+            .into());
+        }
+
+        check_range(*value, parameters.min, parameters.max)
+    }
+
+    fn set_value(input: &str, parameters: &SettingFloatParameters) -> Result<f64> {
+        let value = f64::from_str(input.trim()).map_err(|_| SettingError::UnexpectedInput {
+            expected: SettingType::Float,
+            input: input.into(),
+        })?;
+
+        <f64 as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn allowed(parameters: &SettingFloatParameters) -> Option<String> {
+        range_description(parameters.min, parameters.max)
+    }
+}
+
+#[derive(Default)]
+pub struct SettingFloatParameters {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+// Setting value - Choice
+
+/// One value out of a fixed set of choices
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Choice(pub String);
+
+impl Choice {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl SettingValue for Choice {
+    type Parameters = SettingChoiceParameters;
+
+    fn setting_type() -> SettingType {
+        SettingType::Choice
+    }
+
+    fn to_input(&self) -> String {
+        self.0.clone()
+    }
+
+    fn is_valid(value: &Choice, parameters: &SettingChoiceParameters) -> Result<()> {
+        // Choices only known at runtime are checked in `validate`
+        if parameters.choices.is_none() && parameters.dynamic_choices.is_some() {
+            return Ok(());
+        }
+
+        let choices = parameters.choices.unwrap_or_default();
+
+        if !choices.iter().any(|choice| *choice == value.as_str()) {
+            return Err(SettingError::InvalidChoice {
+                input: value.0.clone(),
+                choices: choices.join(", "),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    // Choices are matched ignoring case and stored the way they are listed
+    fn set_value(input: &str, parameters: &SettingChoiceParameters) -> Result<Choice> {
+        let input = input.trim();
+        let value = parameters
+            .choices
+            .unwrap_or_default()
+            .iter()
+            .find(|choice| choice.eq_ignore_ascii_case(input))
+            .map(|choice| Choice(choice.to_string()))
+            .unwrap_or_else(|| Choice(input.to_string()));
+
+        <Choice as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn allowed(parameters: &SettingChoiceParameters) -> Option<String> {
+        parameters
+            .choices
+            .map(|choices| format!("one of {}", choices.join(", ")))
+    }
+
+    async fn validate(
+        &self,
+        bot: &Bot,
+        _ctx: &SettingContext,
+        parameters: &SettingChoiceParameters,
+    ) -> Result<()> {
+        if let Some(dynamic_choices) = parameters.dynamic_choices {
+            let choices = dynamic_choices(bot);
+
+            if !choices.iter().any(|choice| choice == self.as_str()) {
+                return Err(SettingError::InvalidChoice {
+                    input: self.0.clone(),
+                    choices: choices.join(", "),
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct SettingChoiceParameters {
+    pub choices: Option<&'static [&'static str]>,
+    /// Choices which are only known at runtime, like the loaded locales
+    pub dynamic_choices: Option<fn(&Bot) -> Vec<String>>,
+}
+
+// Setting value - Duration
+
+const DURATION_UNITS: &[(char, u64)] = &[
+    ('s', 1),
+    ('m', 60),
+    ('h', 60 * 60),
+    ('d', 60 * 60 * 24),
+    ('w', 60 * 60 * 24 * 7),
+    ('M', 60 * 60 * 24 * 365 / 12),
+    ('Y', 60 * 60 * 24 * 365),
+];
+
+/// Parse durations like "1h30m", using the same units as `time.parse_duration` in lua
+pub fn parse_duration(input: &str) -> Option<Duration> {
+    let mut secs: u64 = 0;
+    let mut number: Option<u64> = None;
+
+    for c in input.trim().chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = Some(
+                number
+                    .unwrap_or(0)
+                    .checked_mul(10)?
+                    .checked_add(digit as u64)?,
+            );
+        } else if c.is_whitespace() {
+            continue;
+        } else {
+            let (_, unit) = DURATION_UNITS.iter().find(|(letter, _)| *letter == c)?;
+            secs = secs.checked_add(number.take()?.checked_mul(*unit)?)?;
+        }
+    }
+
+    // A trailing number without a unit is ambiguous
+    if number.is_some() || input.trim().is_empty() {
+        return None;
+    }
+
+    Some(Duration::from_secs(secs))
+}
+
+/// Format a duration with the largest units first, the inverse of `parse_duration`
+pub fn format_duration(duration: Duration) -> String {
+    let mut secs = duration.as_secs();
+
+    if secs == 0 {
+        return "0s".into();
+    }
+
+    let mut out = String::new();
+
+    for (letter, unit) in DURATION_UNITS.iter().rev() {
+        if secs >= *unit {
+            out.push_str(&format!("{}{}", secs / unit, letter));
+            secs %= unit;
+        }
+    }
+
+    out
+}
+
+impl SettingValue for Duration {
+    type Parameters = SettingDurationParameters;
+
+    fn setting_type() -> SettingType {
+        SettingType::Duration
+    }
+
+    fn to_input(&self) -> String {
+        format_duration(*self)
+    }
+
+    fn is_valid(value: &Duration, parameters: &SettingDurationParameters) -> Result<()> {
+        let below = parameters.min.map(|min| *value < min).unwrap_or(false);
+        let above = parameters.max.map(|max| *value > max).unwrap_or(false);
+
+        if below || above {
+            return Err(SettingError::OutOfRange {
+                value: format_duration(*value),
+                allowed: <Duration as SettingValue>::allowed(parameters).unwrap_or_default(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn set_value(input: &str, parameters: &SettingDurationParameters) -> Result<Duration> {
+        let value = parse_duration(input).ok_or_else(|| SettingError::UnexpectedInput {
+            expected: SettingType::Duration,
+            input: input.into(),
+        })?;
+
+        <Duration as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn allowed(parameters: &SettingDurationParameters) -> Option<String> {
+        range_description(
+            parameters.min.map(format_duration),
+            parameters.max.map(format_duration),
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct SettingDurationParameters {
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+// Setting value - Vec<String>, entered as a comma separated list
+
+impl SettingValue for Vec<String> {
+    type Parameters = SettingListParameters;
+
+    fn setting_type() -> SettingType {
+        SettingType::List
+    }
+
+    fn to_input(&self) -> String {
+        self.join(", ")
+    }
+
+    fn is_valid(value: &Vec<String>, parameters: &SettingListParameters) -> Result<()> {
+        if let Some(max_items) = parameters.max_items {
+            if value.len() > max_items {
+                return Err(SettingError::TooManyItems {
+                    max: max_items,
+                    count: value.len(),
+                }
+                .into());
+            }
+        }
+
+        for item in value {
+            <String as SettingValue>::is_valid(
+                item,
+                &SettingStringParameters {
+                    max_len: parameters.max_len,
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn set_value(input: &str, parameters: &SettingListParameters) -> Result<Vec<String>> {
+        let value = input
+            .split(',')
+            .map(|item| item.trim())
+            .filter(|item| !item.is_empty())
+            .map(|item| item.to_string())
+            .collect::<Vec<_>>();
+
+        <Vec<String> as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn allowed(parameters: &SettingListParameters) -> Option<String> {
+        match (parameters.max_items, parameters.max_len) {
+            (Some(items), Some(len)) => Some(format!(
+                "at most {} items of at most {} characters",
+                items, len
+            )),
+            (Some(items), None) => Some(format!("at most {} items", items)),
+            (None, Some(len)) => Some(format!("items of at most {} characters", len)),
+            (None, None) => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SettingListParameters {
+    pub max_items: Option<usize>,
+    pub max_len: Option<usize>,
+}
+
+// Service ids are never 0, catches values which were not meant as an id
+fn check_id(kind: SettingType, id: String) -> Result<()> {
+    if id.ends_with(":0") {
+        return Err(SettingError::UnknownReference { kind, id }.into());
+    }
+
+    Ok(())
+}
+
+// Setting value - ChannelId, has to be a channel on the server the setting is set for
+
+#[async_trait]
+impl SettingValue for ChannelId {
+    type Parameters = SettingChannelParameters;
+
+    fn setting_type() -> SettingType {
+        SettingType::Channel
+    }
+
+    fn to_input(&self) -> String {
+        self.to_str()
+    }
+
+    // The channel is looked up on the service in `validate`
+    fn is_valid(value: &ChannelId, _parameters: &SettingChannelParameters) -> Result<()> {
+        check_id(SettingType::Channel, value.to_str())
+    }
+
+    fn set_value(input: &str, parameters: &SettingChannelParameters) -> Result<ChannelId> {
+        let value =
+            ChannelId::from_str(input.trim()).map_err(|_| SettingError::UnexpectedInput {
+                expected: SettingType::Channel,
+                input: input.into(),
+            })?;
+
+        <ChannelId as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    async fn validate(
+        &self,
+        bot: &Bot,
+        ctx: &SettingContext,
+        _parameters: &SettingChannelParameters,
+    ) -> Result<()> {
+        let server_id = match ctx {
+            SettingContext::Server(server_id) => *server_id,
+            SettingContext::Channel(channel_id) => channel_server(bot, *channel_id).await?,
+            // Users can pick channels of any server
+            SettingContext::User(_) => return channel_server(bot, *self).await.map(|_| ()),
+        };
+
+        if channel_server(bot, *self).await? != server_id {
+            return Err(SettingError::ForeignReference {
+                kind: SettingType::Channel,
+                id: self.to_str(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct SettingChannelParameters {}
+
+// Setting value - UserId, has to be a user known to the service
+
+#[async_trait]
+impl SettingValue for UserId {
+    type Parameters = SettingUserParameters;
+
+    fn setting_type() -> SettingType {
+        SettingType::User
+    }
+
+    fn to_input(&self) -> String {
+        self.to_str()
+    }
+
+    // The user is looked up on the service in `validate`
+    fn is_valid(value: &UserId, _parameters: &SettingUserParameters) -> Result<()> {
+        check_id(SettingType::User, value.to_str())
+    }
+
+    fn set_value(input: &str, parameters: &SettingUserParameters) -> Result<UserId> {
+        let value = UserId::from_str(input.trim()).map_err(|_| SettingError::UnexpectedInput {
+            expected: SettingType::User,
+            input: input.into(),
+        })?;
+
+        <UserId as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    async fn validate(
+        &self,
+        bot: &Bot,
+        _ctx: &SettingContext,
+        _parameters: &SettingUserParameters,
+    ) -> Result<()> {
+        bot.get_ctx()
+            .services()
+            .user(*self)
+            .await
+            .map_err(|_| SettingError::UnknownReference {
+                kind: SettingType::User,
+                id: self.to_str(),
+            })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct SettingUserParameters {}
+
+// Setting value - RoleId, has to be a role of the server the setting is set for
+
+#[async_trait]
+impl SettingValue for RoleId {
+    type Parameters = SettingRoleParameters;
+
+    fn setting_type() -> SettingType {
+        SettingType::Role
+    }
+
+    fn to_input(&self) -> String {
+        self.to_str()
+    }
+
+    // The role is looked up on the service in `validate`
+    fn is_valid(value: &RoleId, _parameters: &SettingRoleParameters) -> Result<()> {
+        check_id(SettingType::Role, value.to_str())
+    }
+
+    fn set_value(input: &str, parameters: &SettingRoleParameters) -> Result<RoleId> {
+        let value = RoleId::from_str(input.trim()).map_err(|_| SettingError::UnexpectedInput {
+            expected: SettingType::Role,
+            input: input.into(),
+        })?;
+
+        <RoleId as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    async fn validate(
+        &self,
+        bot: &Bot,
+        ctx: &SettingContext,
+        _parameters: &SettingRoleParameters,
+    ) -> Result<()> {
+        let server_id = match ctx {
+            SettingContext::Server(server_id) => *server_id,
+            SettingContext::Channel(channel_id) => channel_server(bot, *channel_id).await?,
+            // Roles only exist within a server
+            SettingContext::User(_) => {
+                return Err(SettingError::ServerOnlyReference {
+                    kind: SettingType::Role,
+                }
+                .into())
+            }
+        };
+
+        if !bot
+            .get_ctx()
+            .services()
+            .server_has_role(server_id, *self)
+            .await?
+        {
+            return Err(SettingError::ForeignReference {
+                kind: SettingType::Role,
+                id: self.to_str(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct SettingRoleParameters {}
+
+#[derive(Clone, Copy)]
 pub enum SettingContext {
     Channel(ChannelId),
     Server(ServerId),
+    User(Uid),
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum SettingType {
     Bool,
+    String,
+    Integer,
+    Float,
+    Choice,
+    Duration,
+    List,
+    Channel,
+    User,
+    Role,
+}
+
+impl SettingType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettingType::Bool => "bool",
+            SettingType::String => "string",
+            SettingType::Integer => "integer",
+            SettingType::Float => "float",
+            SettingType::Choice => "choice",
+            SettingType::Duration => "duration",
+            SettingType::List => "list",
+            SettingType::Channel => "channel",
+            SettingType::User => "user",
+            SettingType::Role => "role",
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum SettingError {
-    #[error("unable to parse \"{}\" as {}", input, format!("{:?}", expected).to_lowercase())]
+    #[error("unable to parse \"{}\" as {}", input, expected.as_str())]
     UnexpectedInput {
         expected: SettingType,
         input: String,
     },
     #[error("len {} exceeded max length {}", length, max)]
     ExceededMaxLength { max: usize, length: usize },
+    #[error("{} is out of range, expected {}", value, allowed)]
+    OutOfRange { value: String, allowed: String },
+    #[error("\"{}\" is not one of {}", input, choices)]
+    InvalidChoice { input: String, choices: String },
+    #[error("{} items exceeded max items {}", count, max)]
+    TooManyItems { max: usize, count: usize },
+    #[error("unknown {} \"{}\"", kind.as_str(), id)]
+    UnknownReference { kind: SettingType, id: String },
+    #[error("{} cannot be set by users", name)]
+    NotUserOverridable { name: String },
+    #[error("{} \"{}\" belongs to another server", kind.as_str(), id)]
+    ForeignReference { kind: SettingType, id: String },
+    #[error("a {} can only be set for a server or channel", kind.as_str())]
+    ServerOnlyReference { kind: SettingType },
 }
 
 pub mod prelude {
-    pub use super::{Setting, SettingBoolParameters, SettingFlags, SettingValue};
+    pub use super::{
+        Choice, Setting, SettingBoolParameters, SettingChannelParameters, SettingChoiceParameters,
+        SettingDurationParameters, SettingFlags, SettingFloatParameters, SettingIntegerParameters,
+        SettingListParameters, SettingRoleParameters, SettingStringParameters,
+        SettingUserParameters, SettingValue,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, parse_duration};
+    use std::time::Duration;
+
+    #[test]
+    fn parse_duration_test() {
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(90 * 60)));
+        assert_eq!(
+            parse_duration(" 1d 2h "),
+            Some(Duration::from_secs(26 * 60 * 60))
+        );
+        assert_eq!(parse_duration("1M"), Some(Duration::from_secs(2_628_000)));
+        assert_eq!(parse_duration("0s"), Some(Duration::from_secs(0)));
+
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("1h30"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("5x"), None);
+        assert_eq!(parse_duration("99999999999999999999s"), None);
+        assert_eq!(parse_duration("18446744073709551615Y"), None);
+    }
+
+    #[test]
+    fn format_duration_test() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0s");
+        assert_eq!(format_duration(Duration::from_secs(90 * 60)), "1h30m");
+        assert_eq!(
+            format_duration(Duration::from_secs(8 * 24 * 60 * 60 + 5)),
+            "1w1d5s"
+        );
+
+        for input in &["1Y2M3w4d5h6m7s", "45m", "2w"] {
+            assert_eq!(
+                format_duration(parse_duration(input).unwrap()),
+                input.to_string()
+            );
+        }
+    }
 }
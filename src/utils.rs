@@ -1,6 +1,8 @@
 use crate::services::ServiceKind;
 
 pub mod shell_parser;
+pub mod tasks;
+pub mod vfs;
 
 pub fn escape_untrusted_text(service: ServiceKind, text: String) -> String {
     match service {
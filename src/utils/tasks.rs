@@ -0,0 +1,47 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::Notify;
+
+/// Counts in-flight work so shutdown can wait for it to finish
+#[derive(Default)]
+pub struct TaskTracker {
+    count: AtomicUsize,
+    notify: Notify,
+}
+
+impl TaskTracker {
+    pub fn track(self: &Arc<Self>) -> TaskGuard {
+        self.count.fetch_add(1, Ordering::AcqRel);
+
+        TaskGuard(self.clone())
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    pub async fn wait_idle(&self) {
+        loop {
+            // Create the notification future before checking to not miss a wakeup
+            let notified = self.notify.notified();
+
+            if self.count() == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
+
+pub struct TaskGuard(Arc<TaskTracker>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.notify.notify_waiters();
+        }
+    }
+}
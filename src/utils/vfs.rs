@@ -0,0 +1,119 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "embed")]
+use include_dir::{include_dir, Dir};
+
+#[cfg(feature = "embed")]
+static EMBEDDED: &[(&str, Dir<'static>)] = &[
+    ("lua", include_dir!("$CARGO_MANIFEST_DIR/lua")),
+    ("locales", include_dir!("$CARGO_MANIFEST_DIR/locales")),
+];
+
+/// Read only view of a directory in the share path, like `lua` or `locales`
+///
+/// With the `embed` feature the directory is compiled into the binary. Files that exist on disk
+/// still take precedence, so single files can be changed during development without a rebuild.
+#[derive(Clone)]
+pub struct Vfs {
+    root: PathBuf,
+    #[cfg(feature = "embed")]
+    embedded: Option<&'static Dir<'static>>,
+}
+
+impl Vfs {
+    pub fn share(share_path: &Path, name: &str) -> Vfs {
+        Vfs {
+            root: share_path.join(name),
+            #[cfg(feature = "embed")]
+            embedded: EMBEDDED
+                .iter()
+                .find(|(dir_name, _)| *dir_name == name)
+                .map(|(_, dir)| dir),
+        }
+    }
+
+    pub fn exists(&self, path: &Path) -> bool {
+        self.root.join(path).is_file() || self.embedded_file(path).is_some()
+    }
+
+    pub fn read_to_string(&self, path: &Path) -> Result<String> {
+        let disk_path = self.root.join(path);
+
+        if disk_path.is_file() {
+            return Ok(fs::read_to_string(disk_path)?);
+        }
+
+        self.embedded_file(path)
+            .map(|source| source.to_string())
+            .ok_or_else(|| anyhow!("no such file: {}", disk_path.display()))
+    }
+
+    /// Paths relative to the root matching the pattern, from both the disk and the embedded files
+    pub fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let mut paths = BTreeSet::new();
+
+        let disk_pattern = self.root.join(pattern);
+        for path in glob::glob(&disk_pattern.to_string_lossy())? {
+            let path = path?;
+
+            if let Ok(relative) = path.strip_prefix(&self.root) {
+                paths.insert(relative.to_path_buf());
+            }
+        }
+
+        let pattern = glob::Pattern::new(pattern)?;
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+
+        for path in self.embedded_files() {
+            if pattern.matches_path_with(&path, options) {
+                paths.insert(path);
+            }
+        }
+
+        Ok(paths.into_iter().collect())
+    }
+
+    #[cfg(feature = "embed")]
+    fn embedded_file(&self, path: &Path) -> Option<&'static str> {
+        self.embedded?.get_file(path)?.contents_utf8()
+    }
+
+    #[cfg(not(feature = "embed"))]
+    fn embedded_file(&self, _path: &Path) -> Option<&'static str> {
+        None
+    }
+
+    #[cfg(feature = "embed")]
+    fn embedded_files(&self) -> Vec<PathBuf> {
+        fn collect(dir: &'static Dir<'static>, out: &mut Vec<PathBuf>) {
+            for file in dir.files() {
+                out.push(file.path().to_path_buf());
+            }
+
+            for dir in dir.dirs() {
+                collect(dir, out);
+            }
+        }
+
+        let mut out = Vec::new();
+
+        if let Some(dir) = self.embedded {
+            collect(dir, &mut out);
+        }
+
+        out
+    }
+
+    #[cfg(not(feature = "embed"))]
+    fn embedded_files(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+}